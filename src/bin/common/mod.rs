@@ -0,0 +1,24 @@
+//! Helpers shared by the binaries.
+use std::process;
+use std::fmt::Display;
+
+use failure::Fail;
+
+/// returns the error message followed by the messages of all its causes
+// not used if compiled without any engine feature
+#[allow(dead_code)]
+pub fn describe(err: &Fail) -> String {
+    let mut out = err.to_string();
+    let mut cause = err.cause();
+    while let Some(err) = cause {
+        out.push_str(&format!("\n    caused by: {}", err));
+        cause = err.cause();
+    }
+    out
+}
+
+/// prints the message to stderr and exits with 2 (invalid arguments/setup)
+pub fn exit_with<D: Display>(msg: D) -> ! {
+    eprintln!("{}", msg);
+    process::exit(2)
+}
@@ -0,0 +1,201 @@
+//! Renders a preview of a template and writes it to a directory.
+//!
+//! ```no_rust
+//! preview_template [--engine tera|handlebars|minijinja] [--base-templates <glob|dir>]
+//!     [--from <email>] [--to <email>] [--subject <subject>]
+//!     --templates <templates_dir> --out <out_dir> <template_id> <data.json>
+//! ```
+//!
+//! The mail is written as `<template_id>.eml` and each body as
+//! `<template_id>.body<idx>.<ext>` into the output directory.
+extern crate mail_render_template_engine as rte;
+extern crate mail_types as mail;
+extern crate mail_headers as headers;
+extern crate soft_ascii_string;
+extern crate failure;
+
+mod common;
+
+use std::env;
+use std::process;
+
+use soft_ascii_string::SoftAsciiString;
+
+use mail::default_impl::simple_context;
+use headers::HeaderTryFrom;
+use headers::components::Domain;
+
+use common::{describe, exit_with};
+use rte::{
+    RenderEngine, RenderTemplateEngine,
+    PreviewData, PreviewOptions, DEFAULT_SETTINGS,
+    render_preview
+};
+
+const USAGE: &str = concat!(
+    "usage: preview_template [--engine tera|handlebars|minijinja] [--base-templates <glob|dir>]\n",
+    "    [--from <email>] [--to <email>] [--subject <subject>]\n",
+    "    --templates <templates_dir> --out <out_dir> <template_id> <data.json>"
+);
+
+struct Args {
+    engine: String,
+    // not read if compiled without any engine feature
+    #[allow(dead_code)]
+    base_templates: Option<String>,
+    templates_dir: String,
+    out_dir: String,
+    template_id: String,
+    data_file: String,
+    options: PreviewOptions
+}
+
+fn main() {
+    let args = parse_args(env::args().skip(1)).unwrap_or_else(|msg| exit_with(msg));
+
+    match &*args.engine {
+        "tera" => preview_with_tera(&args),
+        "handlebars" => preview_with_handlebars(&args),
+        "minijinja" => preview_with_minijinja(&args),
+        other => exit_with(format!("unknown engine: {:?}\n{}", other, USAGE))
+    }
+}
+
+fn parse_args(mut iter: impl Iterator<Item=String>) -> Result<Args, String> {
+    let mut engine = None;
+    let mut base_templates = None;
+    let mut templates_dir = None;
+    let mut out_dir = None;
+    let mut options = PreviewOptions::default();
+    let mut positional = Vec::new();
+
+    while let Some(arg) = iter.next() {
+        {
+            let mut value = || iter.next().ok_or_else(|| USAGE.to_owned());
+            match &*arg {
+                "--engine" => { engine = Some(value()?); continue },
+                "--base-templates" => { base_templates = Some(value()?); continue },
+                "--templates" => { templates_dir = Some(value()?); continue },
+                "--out" => { out_dir = Some(value()?); continue },
+                "--from" => { options.from = value()?; continue },
+                "--to" => { options.to = value()?; continue },
+                "--subject" => { options.subject = Some(value()?); continue },
+                _ => {}
+            }
+        }
+        match &*arg {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            _ if !arg.starts_with("-") && positional.len() < 2 => positional.push(arg),
+            _ => return Err(format!("unexpected argument: {:?}\n{}", arg, USAGE))
+        }
+    }
+
+    if positional.len() != 2 {
+        return Err(USAGE.to_owned());
+    }
+    let data_file = positional.pop().unwrap();
+    let template_id = positional.pop().unwrap();
+
+    Ok(Args {
+        engine: engine.or_else(default_engine).ok_or_else(|| {
+            "preview_template was compiled without any engine feature".to_owned()
+        })?,
+        base_templates,
+        templates_dir: templates_dir.ok_or_else(|| USAGE.to_owned())?,
+        out_dir: out_dir.ok_or_else(|| USAGE.to_owned())?,
+        template_id,
+        data_file,
+        options
+    })
+}
+
+fn default_engine() -> Option<String> {
+    if cfg!(feature="tera-engine") {
+        Some("tera".to_owned())
+    } else if cfg!(feature="handlebars-engine") {
+        Some("handlebars".to_owned())
+    } else if cfg!(feature="minijinja-engine") {
+        Some("minijinja".to_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(feature="tera-engine")]
+fn preview_with_tera(args: &Args) {
+    use rte::tera::TeraRenderEngine;
+
+    let engine = match args.base_templates {
+        Some(ref glob) => TeraRenderEngine::new(glob)
+            .unwrap_or_else(|err| exit_with(format!("loading base templates failed: {}", err))),
+        None => TeraRenderEngine::default()
+    };
+    run(args, RenderTemplateEngine::new(engine))
+}
+
+#[cfg(not(feature="tera-engine"))]
+fn preview_with_tera(_args: &Args) {
+    exit_with("preview_template was compiled without the \"tera-engine\" feature")
+}
+
+#[cfg(feature="handlebars-engine")]
+fn preview_with_handlebars(args: &Args) {
+    use rte::handlebars::HandlebarsRenderEngine;
+
+    if args.base_templates.is_some() {
+        exit_with("--base-templates is only supported with the tera and minijinja engines")
+    }
+    run(args, RenderTemplateEngine::new(HandlebarsRenderEngine::new()))
+}
+
+#[cfg(not(feature="handlebars-engine"))]
+fn preview_with_handlebars(_args: &Args) {
+    exit_with("preview_template was compiled without the \"handlebars-engine\" feature")
+}
+
+#[cfg(feature="minijinja-engine")]
+fn preview_with_minijinja(args: &Args) {
+    use rte::minijinja::MiniJinjaRenderEngine;
+
+    let engine = match args.base_templates {
+        Some(ref dir) => MiniJinjaRenderEngine::new(dir),
+        None => MiniJinjaRenderEngine::default()
+    };
+    run(args, RenderTemplateEngine::new(engine))
+}
+
+#[cfg(not(feature="minijinja-engine"))]
+fn preview_with_minijinja(_args: &Args) {
+    exit_with("preview_template was compiled without the \"minijinja-engine\" feature")
+}
+
+#[allow(dead_code)]
+fn run<R>(args: &Args, mut engine: RenderTemplateEngine<R>)
+    where R: RenderEngine<PreviewData>
+{
+    engine.load_templates(&args.templates_dir, &*DEFAULT_SETTINGS)
+        .unwrap_or_else(|err| exit_with(describe(&err)));
+
+    let data = PreviewData::from_json_file(&args.data_file)
+        .unwrap_or_else(|err| exit_with(describe(&err)));
+
+    let domain = Domain::try_from("preview.test")
+        .unwrap_or_else(|err| exit_with(describe(&err)));
+    let unique_part = SoftAsciiString::from_string("preview")
+        .unwrap_or_else(|_| exit_with("invalid unique part for message ids"));
+    let ctx = simple_context::new(domain, unique_part)
+        .unwrap_or_else(|err| exit_with(describe(&err)));
+
+    let preview = render_preview(&engine, &args.template_id, data, &ctx, &args.options)
+        .unwrap_or_else(|err| exit_with(describe(&err)));
+
+    let written = preview.write_to_dir(&args.out_dir)
+        .unwrap_or_else(|err| exit_with(describe(&err)));
+
+    for path in written {
+        println!("{}", path.display());
+    }
+}
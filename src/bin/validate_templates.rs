@@ -0,0 +1,141 @@
+//! Validates all templates in a templates dir.
+//!
+//! ```no_rust
+//! validate_templates [--engine tera|handlebars|minijinja] [--base-templates <glob|dir>] <templates_dir>
+//! ```
+//!
+//! Exits with 0 if no problems where found, 1 if problems where found and
+//! 2 if the arguments are invalid or the templates dir can not be read.
+extern crate mail_render_template_engine as rte;
+extern crate failure;
+
+mod common;
+
+use std::env;
+use std::process;
+
+use common::{describe, exit_with};
+use rte::{RenderEngineBase, ValidationReport, DEFAULT_SETTINGS, validate_templates};
+
+const USAGE: &str =
+    "usage: validate_templates [--engine tera|handlebars|minijinja] [--base-templates <glob|dir>] <templates_dir>";
+
+struct Args {
+    engine: String,
+    // not read if compiled without any engine feature
+    #[allow(dead_code)]
+    base_templates: Option<String>,
+    templates_dir: String
+}
+
+fn main() {
+    let args = parse_args(env::args().skip(1)).unwrap_or_else(|msg| exit_with(msg));
+
+    match &*args.engine {
+        "tera" => validate_with_tera(&args),
+        "handlebars" => validate_with_handlebars(&args),
+        "minijinja" => validate_with_minijinja(&args),
+        other => exit_with(format!("unknown engine: {:?}\n{}", other, USAGE))
+    }
+}
+
+fn parse_args(mut iter: impl Iterator<Item=String>) -> Result<Args, String> {
+    let mut engine = None;
+    let mut base_templates = None;
+    let mut templates_dir = None;
+
+    while let Some(arg) = iter.next() {
+        match &*arg {
+            "--engine" => engine = Some(iter.next().ok_or_else(|| USAGE.to_owned())?),
+            "--base-templates" => base_templates = Some(iter.next().ok_or_else(|| USAGE.to_owned())?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            _ if templates_dir.is_none() && !arg.starts_with("-") => templates_dir = Some(arg),
+            _ => return Err(format!("unexpected argument: {:?}\n{}", arg, USAGE))
+        }
+    }
+
+    Ok(Args {
+        engine: engine.or_else(default_engine).ok_or_else(|| {
+            "validate_templates was compiled without any engine feature".to_owned()
+        })?,
+        base_templates,
+        templates_dir: templates_dir.ok_or_else(|| USAGE.to_owned())?
+    })
+}
+
+fn default_engine() -> Option<String> {
+    if cfg!(feature="tera-engine") {
+        Some("tera".to_owned())
+    } else if cfg!(feature="handlebars-engine") {
+        Some("handlebars".to_owned())
+    } else if cfg!(feature="minijinja-engine") {
+        Some("minijinja".to_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(feature="tera-engine")]
+fn validate_with_tera(args: &Args) {
+    use rte::tera::TeraRenderEngine;
+
+    let mut engine = match args.base_templates {
+        Some(ref glob) => TeraRenderEngine::new(glob)
+            .unwrap_or_else(|err| exit_with(format!("loading base templates failed: {}", err))),
+        None => TeraRenderEngine::default()
+    };
+    run(args, &mut engine)
+}
+
+#[cfg(not(feature="tera-engine"))]
+fn validate_with_tera(_args: &Args) {
+    exit_with("validate_templates was compiled without the \"tera-engine\" feature")
+}
+
+#[cfg(feature="handlebars-engine")]
+fn validate_with_handlebars(args: &Args) {
+    use rte::handlebars::HandlebarsRenderEngine;
+
+    if args.base_templates.is_some() {
+        exit_with("--base-templates is only supported with the tera and minijinja engines")
+    }
+    run(args, &mut HandlebarsRenderEngine::new())
+}
+
+#[cfg(not(feature="handlebars-engine"))]
+fn validate_with_handlebars(_args: &Args) {
+    exit_with("validate_templates was compiled without the \"handlebars-engine\" feature")
+}
+
+#[cfg(feature="minijinja-engine")]
+fn validate_with_minijinja(args: &Args) {
+    use rte::minijinja::MiniJinjaRenderEngine;
+
+    let mut engine = match args.base_templates {
+        Some(ref dir) => MiniJinjaRenderEngine::new(dir),
+        None => MiniJinjaRenderEngine::default()
+    };
+    run(args, &mut engine)
+}
+
+#[cfg(not(feature="minijinja-engine"))]
+fn validate_with_minijinja(_args: &Args) {
+    exit_with("validate_templates was compiled without the \"minijinja-engine\" feature")
+}
+
+#[allow(dead_code)]
+fn run<R>(args: &Args, engine: &mut R)
+    where R: RenderEngineBase
+{
+    let report: ValidationReport<R::LoadingError> =
+        validate_templates(&args.templates_dir, &*DEFAULT_SETTINGS, engine)
+            .unwrap_or_else(|err| exit_with(describe(&err)));
+
+    print!("{}", report);
+    if report.has_problems() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,311 @@
+//! Caching of the resources of specs across renders.
+//!
+//! Every render creates new Content-IDs for the embeddings and attachments
+//! of a spec, but the resources themselves are shared: the cache keeps one
+//! set of resources per spec (version) which is loaded (and transfer encoded)
+//! once, all later renders use the loaded resources.
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Weak, Mutex, RwLock};
+
+use failure::Error;
+use futures::{future, Future};
+use futures::future::Shared;
+
+use mail::Resource;
+
+use ::spec::TemplateSpec;
+
+type Loading = Shared<Box<Future<Item=(), Error=Error> + Send>>;
+
+/// The embeddings and attachments of a spec.
+#[derive(Debug, Clone)]
+pub(crate) struct Resources {
+    /// template level embeddings
+    pub(crate) embeddings: HashMap<String, Resource>,
+    /// the embeddings of each sub-template (in the order of the sub-templates)
+    pub(crate) sub_embeddings: Vec<HashMap<String, Resource>>,
+    pub(crate) attachments: Vec<Resource>
+}
+
+impl Resources {
+
+    fn of_spec(spec: &TemplateSpec) -> Self {
+        Resources {
+            embeddings: spec.embeddings().clone(),
+            sub_embeddings: spec.sub_specs().iter()
+                .map(|sub_spec| sub_spec.embeddings().clone())
+                .collect(),
+            attachments: spec.attachments().clone()
+        }
+    }
+}
+
+/// The resources of a spec (version) shared by all renders of it.
+pub(crate) struct SpecResources {
+    /// the resources, replaced by the loaded ones once loading completed
+    current: RwLock<Arc<Resources>>,
+    /// the loading of the resources, `None` if it was not started
+    loading: Mutex<Option<Loading>>
+}
+
+impl SpecResources {
+
+    fn new(spec: &TemplateSpec) -> Self {
+        SpecResources {
+            current: RwLock::new(Arc::new(Resources::of_spec(spec))),
+            loading: Mutex::new(None)
+        }
+    }
+
+    /// returns the resources to use for a render
+    pub(crate) fn current(&self) -> Arc<Resources> {
+        self.current.read()
+            .expect("[BUG] panic while holding the lock")
+            .clone()
+    }
+
+    /// true if the resources were loaded successfully
+    pub(crate) fn is_loaded(&self) -> bool {
+        self.loading.lock()
+            .expect("[BUG] panic while holding the lock")
+            .as_ref()
+            .and_then(|loading| loading.peek())
+            .map(|result| result.is_ok())
+            .unwrap_or(false)
+    }
+
+    /// returns a future resolving once the resources are loaded
+    ///
+    /// Only the first call loads the resources (using `load_resource`),
+    /// later calls share its result. If loading failed the next call
+    /// loads them again.
+    pub(crate) fn load<F, L>(this: &Arc<Self>, mut load_resource: F) -> impl Future<Item=(), Error=Error>
+        where F: FnMut(&Resource) -> L,
+              L: Future<Item=Resource> + Send + 'static,
+              L::Error: Into<Error>
+    {
+        let mut loading = this.loading.lock()
+            .expect("[BUG] panic while holding the lock");
+        let failed = loading.as_ref()
+            .and_then(|loading| loading.peek())
+            .map(|result| result.is_err())
+            .unwrap_or(false);
+
+        if loading.is_none() || failed {
+            // weak, as the future is stored in the `SpecResources`
+            let spec_resources = Arc::downgrade(this);
+            let future = load_resources(&this.current(), &mut load_resource)
+                .map(move |loaded| store_loaded(&spec_resources, loaded));
+            let future: Box<Future<Item=(), Error=Error> + Send> = Box::new(future);
+            *loading = Some(future.shared());
+        }
+
+        // UNWRAP_SAFE: it was set above if it was not set
+        loading.as_ref().unwrap().clone()
+            .map(|_| ())
+            .map_err(|err| format_err!("{}", *err))
+    }
+}
+
+impl Debug for SpecResources {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        fter.debug_struct("SpecResources")
+            .field("current", &self.current())
+            .field("loaded", &self.is_loaded())
+            .finish()
+    }
+}
+
+fn store_loaded(spec_resources: &Weak<SpecResources>, loaded: Resources) {
+    // the spec (version) might have been dropped while loading
+    if let Some(spec_resources) = spec_resources.upgrade() {
+        *spec_resources.current.write()
+            .expect("[BUG] panic while holding the lock") = Arc::new(loaded);
+    }
+}
+
+// boxed futures, as `impl Future` would capture `F` which is not `'static`
+fn load_resources<F, L>(resources: &Resources, load_resource: &mut F)
+    -> Box<Future<Item=Resources, Error=Error> + Send>
+    where F: FnMut(&Resource) -> L,
+          L: Future<Item=Resource> + Send + 'static,
+          L::Error: Into<Error>
+{
+    let embeddings = load_all(&resources.embeddings, load_resource);
+    let sub_embeddings = resources.sub_embeddings.iter()
+        .map(|embeddings| load_all(embeddings, load_resource))
+        .collect::<Vec<_>>();
+    let attachments = resources.attachments.iter()
+        .map(|resource| load_resource(resource).map_err(|err| err.into()))
+        .collect::<Vec<_>>();
+
+    let loading = embeddings
+        .join3(future::join_all(sub_embeddings), future::join_all(attachments))
+        .map(|(embeddings, sub_embeddings, attachments)| Resources {
+            embeddings, sub_embeddings, attachments
+        });
+    Box::new(loading)
+}
+
+fn load_all<F, L>(resources: &HashMap<String, Resource>, load_resource: &mut F)
+    -> Box<Future<Item=HashMap<String, Resource>, Error=Error> + Send>
+    where F: FnMut(&Resource) -> L,
+          L: Future<Item=Resource> + Send + 'static,
+          L::Error: Into<Error>
+{
+    let loading = resources.iter()
+        .map(|(key, resource)| {
+            let key = key.clone();
+            load_resource(resource)
+                .map(move |loaded| (key, loaded))
+                .map_err(|err| err.into())
+        })
+        .collect::<Vec<_>>();
+
+    Box::new(future::join_all(loading)
+        .map(|loaded| loaded.into_iter().collect()))
+}
+
+/// Cache of the resources of each spec, keyed by the spec id.
+///
+/// Entries are created on the first render of a spec and have to be
+/// invalidated whenever the spec is replaced, removed or changed.
+#[derive(Debug, Default)]
+pub(crate) struct ResourceCache {
+    specs: RwLock<HashMap<String, Arc<SpecResources>>>
+}
+
+impl ResourceCache {
+
+    /// returns the cached resources of the spec, creating them if they are not cached
+    pub(crate) fn get_or_create(&self, id: &str, spec: &TemplateSpec) -> Arc<SpecResources> {
+        let cached = self.specs.read()
+            .expect("[BUG] panic while holding the lock")
+            .get(id)
+            .cloned();
+        if let Some(resources) = cached {
+            return resources;
+        }
+        let mut specs = self.specs.write()
+            .expect("[BUG] panic while holding the lock");
+        // another render might have created it in between
+        specs.entry(id.to_owned())
+            .or_insert_with(|| Arc::new(SpecResources::new(spec)))
+            .clone()
+    }
+
+    /// removes the cached resources of the spec (if there are any)
+    pub(crate) fn invalidate(&self, id: &str) {
+        self.specs.write()
+            .expect("[BUG] panic while holding the lock")
+            .remove(id);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.specs.write()
+            .expect("[BUG] panic while holding the lock")
+            .clear();
+    }
+}
+
+impl Clone for ResourceCache {
+    /// the clone shares the cached resources, but not the cache
+    fn clone(&self) -> Self {
+        let specs = self.specs.read()
+            .expect("[BUG] panic while holding the lock")
+            .clone();
+        ResourceCache { specs: RwLock::new(specs) }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use futures::{future, Future};
+    use headers::components::MediaType;
+    use mail::Resource;
+    use mail::file_buffer::FileBuffer;
+
+    use ::spec::{TemplateSpec, SubTemplateSpec, TemplateSource};
+    use super::{ResourceCache, SpecResources};
+
+    fn resource() -> Resource {
+        let media_type = MediaType::parse("image/png").unwrap();
+        Resource::sourceless_from_buffer(FileBuffer::new(media_type, Vec::new()))
+    }
+
+    fn spec() -> TemplateSpec {
+        let mut embeddings = HashMap::new();
+        embeddings.insert("logo".to_owned(), resource());
+        let mut spec = TemplateSpec::new_with_embeddings(
+            vec1![ SubTemplateSpec::new_with_template_source(
+                TemplateSource::Source { id: "cached".to_owned(), content: String::new() },
+                MediaType::parse("text/html; charset=utf-8").unwrap(),
+                embeddings.clone()
+            ) ],
+            embeddings
+        );
+        spec.attachments_mut().push(resource());
+        spec
+    }
+
+    #[test]
+    fn second_render_does_not_load_the_resources_again() {
+        let cache = ResourceCache::default();
+        let spec = spec();
+        let loads = Cell::new(0);
+        let load = |resource: &Resource| {
+            loads.set(loads.get() + 1);
+            future::ok::<_, ::failure::Error>(resource.clone())
+        };
+
+        let resources = cache.get_or_create("a", &spec);
+        assert!(!resources.is_loaded());
+        SpecResources::load(&resources, &load).wait().unwrap();
+        assert_eq!(loads.get(), 3);
+        assert!(resources.is_loaded());
+
+        let resources = cache.get_or_create("a", &spec);
+        SpecResources::load(&resources, &load).wait().unwrap();
+        assert_eq!(loads.get(), 3);
+
+        // a new spec version is loaded again
+        cache.invalidate("a");
+        let resources = cache.get_or_create("a", &spec);
+        assert!(!resources.is_loaded());
+        SpecResources::load(&resources, &load).wait().unwrap();
+        assert_eq!(loads.get(), 6);
+    }
+
+    #[test]
+    fn failed_loading_is_retried() {
+        let cache = ResourceCache::default();
+        let resources = cache.get_or_create("a", &spec());
+
+        let res = SpecResources::load(&resources, |_| future::err::<Resource, _>(format_err!("unreachable"))).wait();
+        assert_eq!(res.unwrap_err().to_string(), "unreachable");
+        assert!(!resources.is_loaded());
+
+        SpecResources::load(&resources, |resource| future::ok::<_, ::failure::Error>(resource.clone()))
+            .wait().unwrap();
+        assert!(resources.is_loaded());
+    }
+
+    #[test]
+    fn clones_share_the_cached_resources() {
+        let cache = ResourceCache::default();
+        let spec = spec();
+        let first = cache.get_or_create("a", &spec);
+        let clone = cache.clone();
+        assert!(Arc::ptr_eq(&first, &clone.get_or_create("a", &spec)));
+
+        clone.clear();
+        assert!(Arc::ptr_eq(&first, &cache.get_or_create("a", &spec)));
+        assert!(!Arc::ptr_eq(&first, &clone.get_or_create("a", &spec)));
+    }
+}
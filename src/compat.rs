@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+/// What to do if the media type implied by the file extension and the one
+/// detected from the content are incompatible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MismatchPolicy {
+    /// fail with a `FileStemAndContentDifferInMediaType` error (the default)
+    Error,
+    /// use the media type implied by the file extension and log a warning
+    PreferExtension,
+    /// use the media type detected from the content and log a warning
+    PreferContent
+}
+
+impl Default for MismatchPolicy {
+    fn default() -> Self {
+        MismatchPolicy::Error
+    }
+}
+
+/// A table of media types which can be used in place of each other.
+///
+/// It consists of aliases (e.g. `image/jpg` for `image/jpeg`) and a
+/// subtype tree (e.g. `text/csv` is a `text/plain`). Two media types are
+/// compatible if (after resolving aliases) they are the same or one is a
+/// ancestor of the other in the tree, in which case the more specific one
+/// is used.
+///
+/// Besides the explicitly added parents following implicit parents exist
+/// (if the table is not created with `exact`):
+///
+/// - `text/*` is a `text/plain`, except `text/html` which is markup and
+///   should not silently pass as plain text (add it with `add_parent` if needed)
+/// - `*/*+xml` is a `application/xml`, the same applies to `+json` and `+zip`
+/// - open document and office open xml types are `application/zip`
+///
+#[derive(Debug, Clone)]
+pub struct MediaTypeCompatibility {
+    aliases: HashMap<String, String>,
+    parents: HashMap<String, String>,
+    implicit_parents: bool
+}
+
+impl MediaTypeCompatibility {
+
+    /// creates a table where media types are only compatible if they are equal
+    pub fn exact() -> Self {
+        MediaTypeCompatibility {
+            aliases: HashMap::new(),
+            parents: HashMap::new(),
+            implicit_parents: false
+        }
+    }
+
+    /// makes `alias` a different name for the media type `canonical`
+    pub fn add_alias<A, C>(&mut self, alias: A, canonical: C)
+        where A: AsRef<str>, C: AsRef<str>
+    {
+        self.aliases.insert(normalize(alias.as_ref()), normalize(canonical.as_ref()));
+    }
+
+    /// makes `parent` the parent of `media_type` in the subtype tree
+    ///
+    /// This overrides the implicit parent of `media_type`.
+    pub fn add_parent<M, P>(&mut self, media_type: M, parent: P)
+        where M: AsRef<str>, P: AsRef<str>
+    {
+        self.parents.insert(normalize(media_type.as_ref()), normalize(parent.as_ref()));
+    }
+
+    /// returns the canonical name of given media type (without parameters)
+    pub fn canonical(&self, media_type: &str) -> String {
+        let media_type = normalize(media_type);
+        match self.aliases.get(&media_type) {
+            Some(canonical) => canonical.clone(),
+            None => media_type
+        }
+    }
+
+    /// returns the parent of the (canonical) media type in the subtype tree
+    pub fn parent(&self, media_type: &str) -> Option<String> {
+        if let Some(parent) = self.parents.get(media_type) {
+            return Some(parent.clone());
+        }
+        if !self.implicit_parents {
+            return None;
+        }
+
+        let (type_, subtype) = split(media_type)?;
+        for &(suffix, parent) in &[("+xml", "application/xml"), ("+json", "application/json"), ("+zip", "application/zip")] {
+            if subtype.ends_with(suffix) && media_type != parent {
+                return Some(parent.to_owned());
+            }
+        }
+
+        if type_ == "application" && (
+            subtype.starts_with("vnd.openxmlformats-officedocument.")
+            || subtype.starts_with("vnd.oasis.opendocument."))
+        {
+            Some("application/zip".to_owned())
+        } else if type_ == "text" && subtype != "plain" && subtype != "html" {
+            Some("text/plain".to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// returns true if `ancestor` is `media_type` or a ancestor of it
+    ///
+    /// Both media types are expected to be canonical.
+    pub fn is_same_or_descendant(&self, media_type: &str, ancestor: &str) -> bool {
+        let mut current = media_type.to_owned();
+        // the limit protects against cycles in user provided parents
+        for _ in 0..32 {
+            if current == ancestor {
+                return true;
+            }
+            match self.parent(&current) {
+                Some(parent) => current = parent,
+                None => return false
+            }
+        }
+        false
+    }
+
+    /// returns the more specific of the two media types if they are compatible
+    ///
+    /// The returned media type is canonical, if both are the same
+    /// after resolving aliases `Compatible::Same` is returned.
+    pub fn reconcile(&self, left: &str, right: &str) -> Compatible {
+        let left = self.canonical(left);
+        let right = self.canonical(right);
+        if left == right {
+            Compatible::Same(left)
+        } else if self.is_same_or_descendant(&left, &right) {
+            Compatible::Left(left)
+        } else if self.is_same_or_descendant(&right, &left) {
+            Compatible::Right(right)
+        } else {
+            Compatible::Incompatible
+        }
+    }
+}
+
+impl Default for MediaTypeCompatibility {
+    fn default() -> Self {
+        let mut compat = MediaTypeCompatibility::exact();
+        compat.implicit_parents = true;
+        for &(alias, canonical) in DEFAULT_ALIASES {
+            compat.add_alias(alias, canonical);
+        }
+        compat
+    }
+}
+
+/// result of `MediaTypeCompatibility::reconcile`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Compatible {
+    /// both media types are the same (after resolving aliases)
+    Same(String),
+    /// the left media type is more specific
+    Left(String),
+    /// the right media type is more specific
+    Right(String),
+    /// the media types are not compatible
+    Incompatible
+}
+
+const DEFAULT_ALIASES: &[(&str, &str)] = &[
+    ("image/jpg", "image/jpeg"),
+    ("image/pjpeg", "image/jpeg"),
+    ("image/x-png", "image/png"),
+    ("text/xml", "application/xml"),
+    ("application/x-pdf", "application/pdf"),
+    ("application/x-gzip", "application/gzip"),
+    ("application/x-zip-compressed", "application/zip"),
+    ("application/x-javascript", "application/javascript"),
+    ("text/javascript", "application/javascript"),
+];
+
+fn normalize(media_type: &str) -> String {
+    media_type.split(';').next().unwrap_or("").trim().to_lowercase()
+}
+
+fn split(media_type: &str) -> Option<(&str, &str)> {
+    let mut parts = media_type.splitn(2, '/');
+    let type_ = parts.next()?;
+    let subtype = parts.next()?;
+    Some((type_, subtype))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text_subtypes_are_compatible_with_plain_text() {
+        let compat = MediaTypeCompatibility::default();
+        assert_eq!(compat.reconcile("text/plain", "text/x-tex"), Compatible::Right("text/x-tex".to_owned()));
+        assert_eq!(compat.reconcile("text/csv", "text/plain"), Compatible::Left("text/csv".to_owned()));
+        assert_eq!(compat.reconcile("text/csv", "text/html"), Compatible::Incompatible);
+    }
+
+    #[test]
+    fn html_is_not_plain_text() {
+        let mut compat = MediaTypeCompatibility::default();
+        assert_eq!(compat.reconcile("text/plain", "text/html"), Compatible::Incompatible);
+        compat.add_parent("text/html", "text/plain");
+        assert_eq!(compat.reconcile("text/plain", "text/html"), Compatible::Right("text/html".to_owned()));
+    }
+
+    #[test]
+    fn aliases_are_the_same() {
+        let compat = MediaTypeCompatibility::default();
+        assert_eq!(compat.reconcile("image/jpg", "image/jpeg"), Compatible::Same("image/jpeg".to_owned()));
+        assert_eq!(compat.reconcile("image/png", "image/jpeg"), Compatible::Incompatible);
+    }
+
+    #[test]
+    fn structured_suffixes_and_office_formats() {
+        let compat = MediaTypeCompatibility::default();
+        assert_eq!(compat.reconcile("image/svg+xml", "text/xml"), Compatible::Left("image/svg+xml".to_owned()));
+        assert_eq!(
+            compat.reconcile("application/zip", "application/vnd.oasis.opendocument.text"),
+            Compatible::Right("application/vnd.oasis.opendocument.text".to_owned())
+        );
+    }
+
+    #[test]
+    fn exact_only_accepts_equal_types() {
+        let mut compat = MediaTypeCompatibility::exact();
+        assert_eq!(compat.reconcile("text/plain", "text/x-tex"), Compatible::Incompatible);
+        compat.add_parent("text/x-tex", "text/plain");
+        assert_eq!(compat.reconcile("text/plain", "text/x-tex"), Compatible::Right("text/x-tex".to_owned()));
+    }
+}
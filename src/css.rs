@@ -0,0 +1,673 @@
+//! Inlining of CSS rules into `style` attributes of html bodies.
+//!
+//! Many mail clients ignore `<style>` blocks, so the rules of all `<style>`
+//! blocks in the body (and of an optional additional stylesheet, like the
+//! `style.css` of a template dir) are applied as `style` attributes.
+//!
+//! Only simple selectors are supported: type, class, id and universal
+//! selectors, compounds of them (e.g. `p.note`) and descendant/child
+//! combinators (e.g. `table td`, `ul > li`). Rules with other selectors
+//! (pseudo-classes, attribute selectors, ...) and at-rules like `@media`
+//! can not be inlined, they are kept in a `<style>` block.
+//!
+//! Declarations are applied in cascade order: rules by specificity and
+//! source order, then the existing `style` attribute, then `!important`
+//! declarations of rules and last `!important` declarations of the existing
+//! `style` attribute.
+use std::cmp::Ordering;
+
+use ::html2text::{tag_end, decode_entities};
+
+/// elements which have no end tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input",
+    "link", "meta", "param", "source", "track", "wbr"
+];
+
+/// inlines the rules of `extra_css` and of all `<style>` blocks in `html`
+///
+/// The rules of `extra_css` come before the rules of the `<style>` blocks
+/// in source order (i.e. the `<style>` blocks win on equal specificity).
+/// `<style>` blocks with a `media` attribute (other then `all`/`screen`)
+/// are left unchanged.
+pub fn inline_css(html: &str, extra_css: Option<&str>) -> String {
+    let mut stylesheet = Stylesheet::default();
+    if let Some(css) = extra_css {
+        stylesheet.parse(css);
+    }
+    for css in inlinable_style_blocks(html) {
+        stylesheet.parse(&css);
+    }
+    if stylesheet.rules.is_empty() && stylesheet.retained.is_empty() {
+        return html.to_owned();
+    }
+
+    let mut out = String::with_capacity(html.len());
+    let mut retained_pos = None;
+    let mut open_elements: Vec<Element> = Vec::new();
+    let mut rest = html;
+    while let Some(idx) = rest.find('<') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map(|end| end + 3).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = (tag_end(rest) + 1).min(rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+
+        let start_tag = match StartTag::parse(tag) {
+            Some(start_tag) => start_tag,
+            None => {
+                if tag.starts_with("</") {
+                    let name = tag[2..].trim_end_matches('>').trim().to_ascii_lowercase();
+                    if let Some(idx) = open_elements.iter().rposition(|element| element.name == name) {
+                        open_elements.truncate(idx);
+                    }
+                }
+                out.push_str(tag);
+                continue;
+            }
+        };
+
+        match &*start_tag.name {
+            "style" | "script" => {
+                let close = format!("</{}", start_tag.name);
+                let content_len = find_ignore_ascii_case(rest, &close).unwrap_or(rest.len());
+                let content = &rest[..content_len];
+                let after = &rest[content_len..];
+                let close_len = (tag_end(after) + 1).min(after.len());
+
+                if start_tag.name == "style" && is_inlinable_style(&start_tag) {
+                    if retained_pos.is_none() {
+                        retained_pos = Some(out.len());
+                    }
+                } else {
+                    out.push_str(tag);
+                    out.push_str(content);
+                    out.push_str(&after[..close_len]);
+                }
+                rest = &after[close_len..];
+                continue;
+            },
+            _ => {}
+        }
+
+        let element = Element {
+            name: start_tag.name.clone(),
+            id: start_tag.attribute("id").map(|id| id.to_owned()),
+            classes: start_tag.attribute("class")
+                .map(|classes| classes.split_whitespace().map(|class| class.to_owned()).collect())
+                .unwrap_or_default()
+        };
+
+        let in_head = open_elements.iter().any(|element| element.name == "head");
+        if in_head || start_tag.name == "head" || start_tag.name == "html" {
+            out.push_str(tag);
+        } else {
+            let inline = start_tag.attribute("style").map(decode_entities);
+            match stylesheet.style_for(&element, &open_elements, inline.as_ref().map(|style| &**style)) {
+                Some(style) => out.push_str(&start_tag.with_attribute(tag, "style", &escape_attribute(&style))),
+                None => out.push_str(tag)
+            }
+        }
+
+        if start_tag.name == "head" && retained_pos.is_none() {
+            retained_pos = Some(out.len());
+        }
+        if !start_tag.self_closing && !VOID_ELEMENTS.contains(&&*start_tag.name) {
+            open_elements.push(element);
+        }
+    }
+    out.push_str(rest);
+
+    if !stylesheet.retained.is_empty() {
+        let block = format!("<style>{}</style>", stylesheet.retained.trim_end());
+        out.insert_str(retained_pos.unwrap_or(0), &block);
+    }
+    out
+}
+
+/// returns the content of all `<style>` blocks whose rules should be inlined
+fn inlinable_style_blocks(html: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut rest = html;
+    while let Some(idx) = find_ignore_ascii_case(rest, "<style") {
+        rest = &rest[idx..];
+        let end = (tag_end(rest) + 1).min(rest.len());
+        let start_tag = StartTag::parse(&rest[..end]);
+        rest = &rest[end..];
+        let content_len = find_ignore_ascii_case(rest, "</style").unwrap_or(rest.len());
+        if let Some(start_tag) = start_tag {
+            if start_tag.name == "style" && is_inlinable_style(&start_tag) {
+                blocks.push(rest[..content_len].to_owned());
+            }
+        }
+        rest = &rest[content_len..];
+    }
+    blocks
+}
+
+fn is_inlinable_style(tag: &StartTag) -> bool {
+    match tag.attribute("media") {
+        None => true,
+        Some(media) => {
+            let media = media.trim();
+            media.is_empty() || media.eq_ignore_ascii_case("all") || media.eq_ignore_ascii_case("screen")
+        }
+    }
+}
+
+pub(crate) fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    let needle = needle.as_bytes();
+    haystack.as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+/// escapes a attribute value for use in double quotes
+pub(crate) fn escape_attribute(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;")
+}
+
+/// A parsed start tag, with the byte ranges of its attributes in the tag source.
+pub(crate) struct StartTag {
+    pub(crate) name: String,
+    attributes: Vec<Attribute>,
+    pub(crate) self_closing: bool
+}
+
+struct Attribute {
+    name: String,
+    value: String,
+    /// start (including preceding whitespace) and end of the attribute in the tag source
+    span: (usize, usize)
+}
+
+impl StartTag {
+
+    /// parses a tag like `<p class="x">`, returns `None` for end tags, comments etc.
+    pub(crate) fn parse(tag: &str) -> Option<StartTag> {
+        if !tag.starts_with('<') {
+            return None;
+        }
+        let content_end = if tag.ends_with('>') { tag.len() - 1 } else { tag.len() };
+        let content = &tag[1..content_end];
+        let name_len = content.find(|ch: char| !ch.is_ascii_alphanumeric()).unwrap_or(content.len());
+        if name_len == 0 {
+            return None;
+        }
+        let name = content[..name_len].to_ascii_lowercase();
+        let self_closing = content.trim_end().ends_with('/');
+
+        let mut attributes = Vec::new();
+        // offsets are relative to `tag`
+        let mut pos = 1 + name_len;
+        loop {
+            let span_start = pos;
+            let rest = &tag[pos..content_end];
+            let trimmed = rest.trim_start_matches(|ch: char| ch.is_whitespace() || ch == '/');
+            pos += rest.len() - trimmed.len();
+            let name_len = trimmed.find(|ch: char| ch.is_whitespace() || ch == '=' || ch == '/')
+                .unwrap_or(trimmed.len());
+            if name_len == 0 {
+                break;
+            }
+            let attr_name = trimmed[..name_len].to_ascii_lowercase();
+            pos += name_len;
+
+            let mut value = String::new();
+            let rest = &tag[pos..content_end];
+            let after_ws = rest.trim_start();
+            if after_ws.starts_with('=') {
+                let value_src = after_ws[1..].trim_start();
+                pos += rest.len() - value_src.len();
+                match value_src.chars().next() {
+                    Some(quote @ '"') | Some(quote @ '\'') => {
+                        let len = value_src[1..].find(quote).map(|idx| idx + 2).unwrap_or(value_src.len());
+                        value = value_src[1..len.max(2) - 1].to_owned();
+                        pos += len;
+                    },
+                    _ => {
+                        let len = value_src.find(|ch: char| ch.is_whitespace()).unwrap_or(value_src.len());
+                        value = value_src[..len].to_owned();
+                        pos += len;
+                    }
+                }
+            }
+            attributes.push(Attribute { name: attr_name, value, span: (span_start, pos) });
+        }
+        Some(StartTag { name, attributes, self_closing })
+    }
+
+    pub(crate) fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|attribute| attribute.name == name)
+            .map(|attribute| &*attribute.value)
+    }
+
+    /// returns the tag source with the attribute set to the given value
+    ///
+    /// The first existing attribute with the name is replaced in place (others
+    /// are removed), if there is none the attribute is appended. The value
+    /// is inserted as is in double quotes, i.e. it has to be escaped by the caller.
+    pub(crate) fn with_attribute(&self, tag: &str, name: &str, value: &str) -> String {
+        let new_attribute = format!(" {}=\"{}\"", name, value);
+        let mut out = String::with_capacity(tag.len() + new_attribute.len());
+        let mut pos = 0;
+        let mut replaced = false;
+        for attribute in self.attributes.iter().filter(|attribute| attribute.name == name) {
+            out.push_str(&tag[pos..attribute.span.0]);
+            if !replaced {
+                out.push_str(&new_attribute);
+                replaced = true;
+            }
+            pos = attribute.span.1;
+        }
+        let rest = &tag[pos..];
+        if replaced {
+            out.push_str(rest);
+        } else {
+            let insert_at = rest.trim_end_matches('>').trim_end_matches('/').trim_end().len();
+            out.push_str(&rest[..insert_at]);
+            out.push_str(&new_attribute);
+            out.push_str(&rest[insert_at..]);
+        }
+        out
+    }
+}
+
+/// The parts of an element relevant for matching selectors.
+struct Element {
+    name: String,
+    id: Option<String>,
+    classes: Vec<String>
+}
+
+#[derive(Default)]
+struct Stylesheet {
+    rules: Vec<Rule>,
+    /// css source of all rules which can not be inlined
+    retained: String
+}
+
+struct Rule {
+    selector: Vec<(Combinator, Compound)>,
+    specificity: (usize, usize, usize),
+    order: usize,
+    declarations: Vec<Declaration>
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child
+}
+
+#[derive(Default)]
+struct Compound {
+    name: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>
+}
+
+struct Declaration {
+    property: String,
+    value: String,
+    important: bool
+}
+
+impl Stylesheet {
+
+    fn parse(&mut self, css: &str) {
+        let css = strip_comments(css);
+        let mut rest = &*css;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                return;
+            }
+
+            if rest.starts_with('@') {
+                let block_start = rest.find('{');
+                let statement_end = rest.find(';');
+                let end = match (block_start, statement_end) {
+                    (Some(block_start), Some(statement_end)) if statement_end < block_start => statement_end + 1,
+                    (Some(block_start), _) => matching_brace(rest, block_start),
+                    (None, Some(statement_end)) => statement_end + 1,
+                    (None, None) => rest.len()
+                };
+                self.retained.push_str(rest[..end].trim());
+                self.retained.push('\n');
+                rest = &rest[end..];
+                continue;
+            }
+
+            let block_start = match rest.find('{') {
+                Some(block_start) => block_start,
+                None => return
+            };
+            let block_end = rest[block_start..].find('}').map(|idx| block_start + idx).unwrap_or(rest.len());
+            let selectors = &rest[..block_start];
+            let block = &rest[block_start + 1..block_end];
+            rest = if block_end < rest.len() { &rest[block_end + 1..] } else { "" };
+
+            for selector in selectors.split(',').map(str::trim).filter(|selector| !selector.is_empty()) {
+                match parse_selector(selector) {
+                    Some(parsed) => {
+                        let specificity = specificity(&parsed);
+                        let order = self.rules.len();
+                        self.rules.push(Rule {
+                            selector: parsed,
+                            specificity,
+                            order,
+                            declarations: parse_declarations(block)
+                        });
+                    },
+                    None => {
+                        self.retained.push_str(&format!("{} {{{}}}\n", selector, block.trim()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// returns the style attribute for the element, `None` if no rule matches
+    fn style_for(&self, element: &Element, ancestors: &[Element], inline: Option<&str>) -> Option<String> {
+        let mut matching = self.rules.iter()
+            .filter(|rule| matches(&rule.selector, element, ancestors))
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by(|left, right| {
+            match left.specificity.cmp(&right.specificity) {
+                Ordering::Equal => left.order.cmp(&right.order),
+                other => other
+            }
+        });
+
+        let mut properties: Vec<(String, String)> = Vec::new();
+        {
+            let mut set = |declaration: &Declaration| {
+                match properties.iter_mut().find(|&&mut (ref property, _)| *property == declaration.property) {
+                    Some(&mut (_, ref mut value)) => *value = declaration.value.clone(),
+                    None => properties.push((declaration.property.clone(), declaration.value.clone()))
+                }
+            };
+            for rule in matching.iter() {
+                rule.declarations.iter().filter(|declaration| !declaration.important).for_each(&mut set);
+            }
+            let inline = inline.map(parse_declarations).unwrap_or_default();
+            inline.iter().filter(|declaration| !declaration.important).for_each(&mut set);
+            for rule in matching.iter() {
+                rule.declarations.iter().filter(|declaration| declaration.important).for_each(&mut set);
+            }
+            inline.iter().filter(|declaration| declaration.important).for_each(&mut set);
+        }
+
+        let style = properties.iter()
+            .map(|&(ref property, ref value)| format!("{}: {}", property, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Some(style)
+    }
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = rest[start + 2..].find("*/")
+            .map(|end| &rest[start + 2 + end + 2..])
+            .unwrap_or("");
+    }
+    out.push_str(rest);
+    out
+}
+
+/// returns the index after the `}` matching the `{` at `open`
+fn matching_brace(css: &str, open: usize) -> usize {
+    let mut depth = 0;
+    for (idx, ch) in css[open..].char_indices() {
+        match ch {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return open + idx + 1;
+                }
+            },
+            _ => {}
+        }
+    }
+    css.len()
+}
+
+fn parse_declarations(block: &str) -> Vec<Declaration> {
+    split_declarations(block).into_iter()
+        .filter_map(|declaration| {
+            let colon = declaration.find(':')?;
+            let property = declaration[..colon].trim().to_ascii_lowercase();
+            let mut value = declaration[colon + 1..].trim();
+            if property.is_empty() || value.is_empty() {
+                return None;
+            }
+            let important = value.to_ascii_lowercase().ends_with("!important");
+            if important {
+                value = value[..value.len() - "!important".len()].trim_end();
+            }
+            Some(Declaration { property, value: value.to_owned(), important })
+        })
+        .collect()
+}
+
+/// splits at `;` which are not part of a quoted string
+fn split_declarations(block: &str) -> Vec<&str> {
+    let mut declarations = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (idx, ch) in block.char_indices() {
+        match (quote, ch) {
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (Some(open), _) if open == ch => quote = None,
+            (None, ';') => {
+                declarations.push(&block[start..idx]);
+                start = idx + 1;
+            },
+            _ => {}
+        }
+    }
+    declarations.push(&block[start..]);
+    declarations
+}
+
+/// parses a selector, returns `None` if it contains unsupported parts
+///
+/// Each compound is paired with the combinator connecting it to the
+/// compound before it (the combinator of the first compound is ignored).
+fn parse_selector(selector: &str) -> Option<Vec<(Combinator, Compound)>> {
+    let mut parts = Vec::new();
+    let mut combinator = Combinator::Descendant;
+    for token in selector.replace('>', " > ").split_whitespace() {
+        if token == ">" {
+            if parts.is_empty() {
+                return None;
+            }
+            combinator = Combinator::Child;
+            continue;
+        }
+        parts.push((combinator, parse_compound(token)?));
+        combinator = Combinator::Descendant;
+    }
+    if parts.is_empty() || combinator == Combinator::Child {
+        return None;
+    }
+    Some(parts)
+}
+
+fn parse_compound(token: &str) -> Option<Compound> {
+    let mut compound = Compound::default();
+    // like in css any non-ascii character can be part of a identifier
+    let is_ident_char = |ch: char| ch.is_alphanumeric() || ch == '-' || ch == '_' || !ch.is_ascii();
+    let mut rest = token;
+    if rest.starts_with('*') {
+        rest = &rest[1..];
+    } else {
+        let len = rest.find(|ch| !is_ident_char(ch)).unwrap_or(rest.len());
+        if len > 0 {
+            compound.name = Some(rest[..len].to_ascii_lowercase());
+            rest = &rest[len..];
+        }
+    }
+    while !rest.is_empty() {
+        let prefix = rest.chars().next()?;
+        let start = prefix.len_utf8();
+        let len = rest[start..].find(|ch| !is_ident_char(ch)).map(|len| len + start).unwrap_or(rest.len());
+        if len == start {
+            return None;
+        }
+        let name = rest[start..len].to_owned();
+        match prefix {
+            '.' => compound.classes.push(name),
+            '#' if compound.id.is_none() => compound.id = Some(name),
+            _ => return None
+        }
+        rest = &rest[len..];
+    }
+    Some(compound)
+}
+
+fn specificity(selector: &[(Combinator, Compound)]) -> (usize, usize, usize) {
+    selector.iter().fold((0, 0, 0), |(ids, classes, types), &(_, ref compound)| (
+        ids + compound.id.iter().count(),
+        classes + compound.classes.len(),
+        types + compound.name.iter().count()
+    ))
+}
+
+fn compound_matches(compound: &Compound, element: &Element) -> bool {
+    compound.name.as_ref().map(|name| *name == element.name).unwrap_or(true)
+        && compound.id.as_ref().map(|id| Some(id) == element.id.as_ref()).unwrap_or(true)
+        && compound.classes.iter().all(|class| element.classes.contains(class))
+}
+
+fn matches(selector: &[(Combinator, Compound)], element: &Element, ancestors: &[Element]) -> bool {
+    let (&(combinator, ref compound), rest) = match selector.split_last() {
+        Some(last) => last,
+        None => return true
+    };
+    if !compound_matches(compound, element) {
+        return false;
+    }
+    if rest.is_empty() {
+        return true;
+    }
+    match combinator {
+        Combinator::Child => match ancestors.split_last() {
+            Some((parent, ancestors)) => matches(rest, parent, ancestors),
+            None => false
+        },
+        Combinator::Descendant => (0..ancestors.len()).rev()
+            .any(|idx| matches(rest, &ancestors[idx], &ancestors[..idx]))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::inline_css;
+
+    #[test]
+    fn inlines_rules_by_specificity_and_order() {
+        let html = concat!(
+            "<html><head><style>p { color: red; margin: 0 } .note { color: blue } ",
+            "p.note { font-weight: bold }</style></head>",
+            "<body><p>a</p><p class=\"note\">b</p></body></html>"
+        );
+        assert_eq!(inline_css(html, None), concat!(
+            "<html><head></head><body>",
+            "<p style=\"color: red; margin: 0\">a</p>",
+            "<p class=\"note\" style=\"color: blue; margin: 0; font-weight: bold\">b</p>",
+            "</body></html>"
+        ));
+    }
+
+    #[test]
+    fn existing_style_attributes_win_except_over_important() {
+        let html = r#"<style>td { color: red; padding: 1px !important }</style><td style="color: green; padding: 2px">x</td>"#;
+        assert_eq!(inline_css(html, None), r#"<td style="color: green; padding: 1px">x</td>"#);
+    }
+
+    #[test]
+    fn existing_style_attributes_are_decoded_and_escaped() {
+        let html = concat!(
+            "<style>p { color: red !important; margin: 0 }</style>",
+            "<p style=\"font-family: &quot;Helvetica Neue&quot;; content: 'a;b'; color: blue !important\">x</p>"
+        );
+        assert_eq!(
+            inline_css(html, None),
+            "<p style=\"margin: 0; font-family: &quot;Helvetica Neue&quot;; content: 'a;b'; color: blue\">x</p>"
+        );
+
+        let html = "<style>p { margin: 0 }</style><p style='font-family: \"A&amp;B\" &lt;x'>x</p>";
+        assert_eq!(
+            inline_css(html, None),
+            "<p style=\"margin: 0; font-family: &quot;A&amp;B&quot; &lt;x\">x</p>"
+        );
+    }
+
+    #[test]
+    fn supports_descendant_and_child_combinators() {
+        let html = concat!(
+            "<style>ul > li { color: red } div a { color: blue }</style>",
+            "<div><p><a href=\"#\">x</a></p></div><ul><li><ol><li>y</li></ol></li></ul>"
+        );
+        assert_eq!(inline_css(html, None), concat!(
+            "<div><p><a href=\"#\" style=\"color: blue\">x</a></p></div>",
+            "<ul><li style=\"color: red\"><ol><li>y</li></ol></li></ul>"
+        ));
+    }
+
+    #[test]
+    fn keeps_rules_which_can_not_be_inlined() {
+        let html = concat!(
+            "<html><head><style>a:hover { color: red } @media (max-width: 600px) { p { margin: 0 } } ",
+            "p { margin: 1px }</style></head><body><p>x<br/></p></body></html>"
+        );
+        assert_eq!(inline_css(html, None), concat!(
+            "<html><head><style>a:hover {color: red}\n@media (max-width: 600px) { p { margin: 0 } }</style>",
+            "</head><body><p style=\"margin: 1px\">x<br/></p></body></html>"
+        ));
+    }
+
+    #[test]
+    fn uses_extra_css_with_lower_order() {
+        let html = "<style>#main { color: red }</style><div id='main' class=x>y</div><img src=\"cid:a\" class=x>";
+        assert_eq!(
+            inline_css(html, Some("/* base */ .x { color: blue; border: 0 } #main { color: green }")),
+            "<div id='main' class=x style=\"color: red; border: 0\">y</div><img src=\"cid:a\" class=x style=\"color: blue; border: 0\">"
+        );
+    }
+
+    #[test]
+    fn supports_non_ascii_selectors() {
+        let html = "<style>.★ { color: red } .btn–primary { color: blue } *★ { margin: 0 }</style><b class=\"★\">x</b><a class=\"btn–primary\">y</a>";
+        assert_eq!(
+            inline_css(html, None),
+            "<style>*★ {margin: 0}</style><b class=\"★\" style=\"color: red\">x</b><a class=\"btn–primary\" style=\"color: blue\">y</a>"
+        );
+    }
+
+    #[test]
+    fn html_without_styles_is_unchanged() {
+        let html = "<p style=\"color: red\">x</p>";
+        assert_eq!(inline_css(html, None), html);
+    }
+}
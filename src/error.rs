@@ -5,7 +5,7 @@ use std::cmp::PartialEq;
 use std::io;
 use std::ffi::{OsStr, OsString};
 
-use failure::{Fail, Backtrace, Context};
+use failure::{Fail, Backtrace, Context, Error};
 //circular dependency (error <-> rte) but ok here
 use ::spec::TemplateSpec;
 
@@ -14,7 +14,22 @@ pub enum LoadingError<E: Fail> {
     #[fail(display = "{}", _0)]
     SpecCreation(CreatingSpecError),
     #[fail(display = "{}", _0)]
-    SpecUsage(InsertionError<E>)
+    SpecUsage(InsertionError<E>),
+    #[fail(display = "no spec is associated with the id {:?}", id)]
+    UnknownSpecId { id: String },
+    #[fail(display = "the spec associated with the id {:?} was not loaded from a dir", id)]
+    NotReloadable { id: String },
+    /// inserting the spec `id` of a batch failed and all changes of the batch were rolled back
+    ///
+    /// The `old_value` of `error` is always `None` as it was restored. Specs which were
+    /// replaced by the batch but could not be loaded again when rolling back are
+    /// returned in `lost_on_rollback`.
+    #[fail(display = "inserting spec {:?} failed, the batch was rolled back: {}", id, error)]
+    BatchRolledBack {
+        id: String,
+        error: InsertionError<E>,
+        lost_on_rollback: Vec<(String, TemplateSpec)>
+    }
 }
 
 impl<E> From<CreatingSpecError> for LoadingError<E>
@@ -41,6 +56,52 @@ impl<E> From<io::Error> for LoadingError<E>
     }
 }
 
+/// Error returned when rendering a template failed.
+///
+/// This is the error of the render APIs of `RenderTemplateEngine`. Its
+/// `TemplateEngine` implementation returns the `RenderError` of the render
+/// engine instead, into which a `ProcessingError` is converted.
+#[derive(Debug, Fail)]
+pub enum UseTemplateError<E: Fail> {
+    #[fail(display = "{}", _0)]
+    Render(E),
+    #[fail(display = "{}", _0)]
+    Processing(#[cause] ProcessingError)
+}
+
+impl<E> UseTemplateError<E>
+    where E: Fail + From<ProcessingError>
+{
+    /// turns the error into a error of the render engine
+    pub fn into_render_error(self) -> E {
+        match self {
+            UseTemplateError::Render(err) => err,
+            UseTemplateError::Processing(err) => err.into()
+        }
+    }
+}
+
+impl<E> From<ProcessingError> for UseTemplateError<E>
+    where E: Fail
+{
+    fn from(err: ProcessingError) -> Self {
+        UseTemplateError::Processing(err)
+    }
+}
+
+/// Error of using a template which is not caused by the render engine.
+#[derive(Debug, Fail)]
+pub enum ProcessingError {
+    #[fail(display = "a header template of {:?} rendered to an invalid header: {:?}", template_id, line)]
+    InvalidHeader { template_id: String, line: String },
+    #[fail(display = "post-processing the {} body of {:?} failed: {}", media_type, template_id, error)]
+    PostProcessing { template_id: String, media_type: String, error: Error },
+    #[fail(display = "line {} of the {} body of {:?} has {} octets, more than allowed", line, media_type, template_id, length)]
+    LineTooLong { template_id: String, media_type: String, line: usize, length: usize },
+    #[fail(display = "loading the resources of {:?} failed: {}", template_id, error)]
+    ResourceLoading { template_id: String, error: Error }
+}
+
 #[derive(Debug)]
 pub struct InsertionError<E: Fail> {
     pub error: E,
@@ -126,6 +187,9 @@ pub enum CreatingSpecErrorVariant {
     #[fail(display =  "no type info in settings for: {:?}", type_name)]
     MissingTypeInfo { type_name: String },
 
+    #[fail(display = "{:?} is a reserved folder name and can not be used as type name", name)]
+    ReservedTypeName { name: String },
+
     #[fail(display = "media type creation for body failed")]
     BodyMediaTypeCreationFailure,
 
@@ -174,9 +238,121 @@ pub enum CreatingSpecErrorVariant {
     IRIConstructionFailed {
         scheme: &'static str,
         tail: DisplayPath
+    },
+
+    #[fail(display = "the headers template {} contains an invalid header line: {:?}", template, line)]
+    InvalidHeaderLine { template: String, line: String },
+
+    #[fail(display = "the template manifest is malformed: {}", file)]
+    InvalidManifest { file: DisplayPath },
+
+    #[fail(display = "the manifest lists the sub-template {:?} more than once", name)]
+    ManifestDuplicateSubTemplate { name: String },
+
+    #[fail(display = "the manifest refers to the sub-template {:?} but there is no such folder", name)]
+    ManifestUnknownSubTemplate { name: String },
+
+    #[fail(display = "the sub-template folder {:?} is not listed in the manifests order", name)]
+    ManifestUnlistedSubTemplate { name: String },
+
+    #[fail(display = "the manifest media type {:?} is not of the form \"type/subtype\"", media_type)]
+    ManifestInvalidMediaType { media_type: String },
+
+    #[fail(display = "the manifest file reference {:?} is not a plain file name", file)]
+    ManifestInvalidFileName { file: String },
+
+    #[fail(display = "the manifest uses the file {:?} more than once", file)]
+    ManifestDuplicateFileUse { file: String },
+
+    #[fail(display = "the manifest refers to a file which does not exist: {}", file)]
+    ManifestMissingFile { file: DisplayPath },
+
+    #[fail(display = "the manifest locale {:?} is not a valid locale, used more than once or a sub-template", name)]
+    ManifestInvalidLocale { name: String },
+
+    #[fail(display = "the manifest refers to the locale {:?} but there is no such folder", name)]
+    ManifestUnknownLocale { name: String }
+}
+
+/// A string which is not a valid language tag was used as locale.
+#[derive(Debug, Fail)]
+#[fail(display = "{:?} is not a valid locale", tag)]
+pub struct InvalidLocale {
+    pub tag: String
+}
+
+
+#[derive(Debug)]
+pub struct PreviewError {
+    inner: Context<PreviewErrorKind>
+}
+
+impl PreviewError {
+
+    pub fn kind(&self) -> &PreviewErrorKind {
+        self.inner.get_context()
+    }
+}
+
+impl Fail for PreviewError {
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.inner.backtrace()
+    }
+
+    fn cause(&self) -> Option<&Fail> {
+        self.inner.cause()
+    }
+}
+
+impl Display for PreviewError {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.inner, fter)
+    }
+}
+
+impl From<PreviewErrorKind> for PreviewError {
+    fn from(kind: PreviewErrorKind) -> Self {
+        PreviewError::from(Context::new(kind))
+    }
+}
+
+impl From<Context<PreviewErrorKind>> for PreviewError {
+    fn from(inner: Context<PreviewErrorKind>) -> Self {
+        PreviewError { inner }
     }
 }
 
+impl From<io::Error> for PreviewError {
+    fn from(io_err: io::Error) -> Self {
+        io_err.context(PreviewErrorKind::IoError).into()
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum PreviewErrorKind {
+
+    #[fail(display = "the preview data is not valid json")]
+    InvalidData,
+
+    #[fail(display = "the preview address {:?} is invalid", address)]
+    InvalidAddress { address: String },
+
+    #[fail(display = "the sample data file is invalid: {}", file)]
+    InvalidSampleData { file: DisplayPath },
+
+    #[fail(display = "rendering the template failed")]
+    Rendering,
+
+    #[fail(display = "composing the preview mail failed")]
+    Composition,
+
+    #[fail(display = "encoding the preview mail failed")]
+    Encoding,
+
+    #[fail(display = "I/O Error occurred when reading the data or writing the preview")]
+    IoError
+}
+
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct DisplayPath(pub PathBuf);
@@ -0,0 +1,179 @@
+//! Encoding of `text/plain; format=flowed` bodies (RFC 3676).
+//!
+//! Each line of the input is seen as a paragraph, it is wrapped at spaces
+//! into soft lines (lines ending with a space) of at most the given width
+//! (except for lines with a single longer word).
+//! Trailing spaces of the input lines are removed, so that they stay hard
+//! line breaks, and lines starting with a space, `>` or `From ` are
+//! space-stuffed.
+use headers::components::MediaType;
+
+use ::utils::MAX_LINE_LENGTH;
+
+/// the recommended maximal width of lines in `format=flowed` bodies
+pub const FLOWED_LINE_WIDTH: usize = 78;
+
+/// encodes the text as `format=flowed` with lines of at most `width` octets
+///
+/// The width does not include the line break, lines are separated by CRLF.
+/// Words longer than the width can not be wrapped without changing the
+/// text, they are kept on a line of their own. Only words longer than
+/// `MAX_LINE_LENGTH` are split (the split is a hard line break).
+pub(crate) fn encode_flowed(text: &str, width: usize) -> String {
+    encode(text, width, false)
+}
+
+/// like `encode_flowed` but only lines longer than `MAX_LINE_LENGTH` are wrapped
+pub(crate) fn encode_flowed_too_long_lines(text: &str, width: usize) -> String {
+    encode(text, width, true)
+}
+
+fn encode(text: &str, width: usize, only_too_long_lines: bool) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / width.max(1) * 3);
+    let mut lines = text.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        if lines.peek().is_none() && line.is_empty() {
+            // the text ended with a line break
+            break;
+        }
+        let line = line.trim_end_matches('\r').trim_end_matches(' ');
+        let soft_lines =
+            if only_too_long_lines && stuffed_len(line) <= MAX_LINE_LENGTH {
+                vec![ line ]
+            } else {
+                wrap_paragraph(line, width)
+            };
+        for soft_line in soft_lines {
+            if needs_space_stuffing(soft_line) {
+                out.push(' ');
+            }
+            out.push_str(soft_line);
+            out.push_str("\r\n");
+        }
+    }
+    out
+}
+
+/// splits a paragraph into lines, all but the last ending with a space (except for split words)
+fn wrap_paragraph(paragraph: &str, width: usize) -> Vec<&str> {
+    // leave space for space-stuffing
+    let width = width.saturating_sub(1).max(1);
+    let mut lines = Vec::new();
+    let mut rest = paragraph;
+    while rest.len() > width {
+        let limit = floor_char_boundary(rest, width);
+        let end = match rest[..limit].rfind(' ') {
+            // the line keeps the space, it marks the soft line break
+            Some(idx) if !rest[..idx].trim_start().is_empty() => idx + 1,
+            // a word longer than the width
+            _ => match long_word_end(rest) {
+                Some(end) => end,
+                // the word is the end of the paragraph
+                None => break
+            }
+        };
+        // UNWRAP_SAFE: rest is longer than width, so it's not empty
+        let end = if end == 0 { rest.chars().next().unwrap().len_utf8() } else { end };
+        lines.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    lines.push(rest);
+    lines
+}
+
+/// returns the end of the line starting with a word longer than the width
+///
+/// The word is kept whole (with the following space) if the line is not
+/// longer than `MAX_LINE_LENGTH`, else it is split. Returns `None` if the
+/// word is kept whole and ends the paragraph.
+fn long_word_end(rest: &str) -> Option<usize> {
+    // leave space for space-stuffing
+    let max = MAX_LINE_LENGTH - 1;
+    let word_start = rest.len() - rest.trim_start_matches(' ').len();
+    match rest[word_start..].find(' ') {
+        Some(idx) if word_start + idx + 1 <= max => Some(word_start + idx + 1),
+        None if rest.len() <= max => None,
+        _ => Some(floor_char_boundary(rest, max))
+    }
+}
+
+/// the length of the line when encoded (i.e. including space-stuffing)
+fn stuffed_len(line: &str) -> usize {
+    if needs_space_stuffing(line) { line.len() + 1 } else { line.len() }
+}
+
+/// true if the media type has a `format=flowed` parameter
+pub(crate) fn is_format_flowed(media_type: &MediaType) -> bool {
+    media_type.as_str_repr().split(';').skip(1).any(|param| {
+        let mut parts = param.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim().trim_matches('"');
+        name.eq_ignore_ascii_case("format") && value.eq_ignore_ascii_case("flowed")
+    })
+}
+
+/// returns the media type with a `format=flowed` parameter added (if it has none)
+pub(crate) fn with_format_flowed(media_type: &MediaType) -> MediaType {
+    if is_format_flowed(media_type) {
+        return media_type.clone();
+    }
+    MediaType::parse(&format!("{}; format=flowed", media_type.as_str_repr()))
+        .expect("[BUG] adding format=flowed to a valid media type failed")
+}
+
+fn needs_space_stuffing(line: &str) -> bool {
+    line.starts_with(' ') || line.starts_with('>') || line.starts_with("From ")
+}
+
+/// the largest char boundary in `text` which is not larger then `idx`
+pub(crate) fn floor_char_boundary(text: &str, idx: usize) -> usize {
+    if idx >= text.len() {
+        return text.len();
+    }
+    (0..idx + 1).rev()
+        .find(|&idx| text.is_char_boundary(idx))
+        .unwrap_or(0)
+}
+
+
+#[cfg(test)]
+mod test {
+    use ::utils::MAX_LINE_LENGTH;
+    use super::{encode_flowed, encode_flowed_too_long_lines};
+
+    #[test]
+    fn wraps_long_lines_into_soft_lines() {
+        let text = "Hy there, this line is long.\nshort\n";
+        assert_eq!(encode_flowed(text, 12), "Hy there, \r\nthis line \r\nis long.\r\nshort\r\n");
+    }
+
+    #[test]
+    fn removes_trailing_spaces_and_stuffs_lines() {
+        let text = "a  \r\n> quote\r\n indented\r\nFrom me\r\n";
+        assert_eq!(encode_flowed(text, 78), "a\r\n > quote\r\n  indented\r\n From me\r\n");
+    }
+
+    #[test]
+    fn keeps_words_longer_then_the_width() {
+        assert_eq!(encode_flowed("abcdefghij xy abcdefghij", 5), "abcdefghij \r\nxy \r\nabcdefghij\r\n");
+    }
+
+    #[test]
+    fn splits_words_longer_then_the_max_line_length() {
+        let word = "a".repeat(MAX_LINE_LENGTH + 10);
+        let encoded = encode_flowed(&format!("{} xy", word), 5);
+        assert_eq!(encoded, format!("{}\r\n{} \r\nxy\r\n", &word[..MAX_LINE_LENGTH - 1], &word[MAX_LINE_LENGTH - 1..]));
+    }
+
+    #[test]
+    fn only_wraps_too_long_lines_if_asked_to() {
+        let long = "word ".repeat(250);
+        let text = format!("{}\nThis line is longer than the width but shorter than the max line length.\n", long);
+        let encoded = encode_flowed_too_long_lines(&text, 20);
+        let mut lines = encoded.split("\r\n").collect::<Vec<_>>();
+        assert_eq!(lines.pop(), Some(""));
+        assert_eq!(lines.pop(), Some("This line is longer than the width but shorter than the max line length."));
+        assert!(lines.iter().all(|line| line.len() <= 20));
+        assert_eq!(lines.concat(), long.trim_end());
+    }
+}
@@ -1,5 +1,7 @@
 use std::{io as std_io};
-use handlebars_crate::{TemplateError, TemplateFileError};
+use handlebars_crate::{TemplateError, TemplateFileError, RenderError};
+
+use ::error::ProcessingError;
 
 #[derive(Debug, Fail)]
 pub enum LoadingError {
@@ -38,3 +40,11 @@ impl From<TemplateFileError> for LoadingError {
         }
     }
 }
+
+// the `RenderError` of handlebars is used as is, so errors not caused by
+// handlebars are represented by their description
+impl From<ProcessingError> for RenderError {
+    fn from(err: ProcessingError) -> Self {
+        RenderError::new(err.to_string())
+    }
+}
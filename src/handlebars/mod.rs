@@ -41,6 +41,9 @@ mod error;
 /// a name colliding with a non-free template an error is returned. The same is true
 /// for the other way around, i.e. adding a non-free template with the same name
 /// as a free template.
+///
+/// It is not `Clone`, so with a `SharedRenderTemplateEngine` it has to be
+/// used with `update_with` (or `publish`) instead of `update`.
 #[derive(Debug)]
 pub struct HandlebarsRenderEngine {
     handlebars: Handlebars,
@@ -250,7 +253,7 @@ impl RenderEngineBase for HandlebarsRenderEngine {
     }
 
     fn unload_templates(&mut self, spec: &TemplateSpec) {
-        for sub_spec in spec.sub_specs() {
+        for sub_spec in spec.render_templates() {
             self.handlebars.unregister_template(sub_spec.source().id());
         }
     }
@@ -0,0 +1,101 @@
+//! Parsing of header templates (`headers.txt`).
+
+/// parses `Name: value` lines into a list of headers
+///
+/// - empty lines are ignored
+/// - lines starting with whitespace continue the value of the previous header
+///   (they are joined with a single space)
+/// - headers with an empty value are omitted, this way a header can be made
+///   optional in the template without needing a conditional
+///
+/// # Error
+///
+/// The first line which is neither empty, a continuation nor a valid header
+/// line is returned as error.
+pub(crate) fn parse_header_lines(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if line.starts_with(|ch: char| ch == ' ' || ch == '\t') {
+            match headers.last_mut() {
+                Some(&mut (_, ref mut value)) => {
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(line.trim());
+                    continue;
+                },
+                None => return Err(line.to_owned())
+            }
+        }
+
+        let colon_idx = line.find(':').ok_or_else(|| line.to_owned())?;
+        let name = &line[..colon_idx];
+        if !is_valid_header_name(name) {
+            return Err(line.to_owned());
+        }
+        headers.push((name.to_owned(), line[colon_idx + 1..].trim().to_owned()));
+    }
+
+    headers.retain(|&(_, ref value)| !value.is_empty());
+    Ok(headers)
+}
+
+/// a (rendered) header value must not contain line breaks, as they would start a new header
+pub(crate) fn is_valid_header_value(value: &str) -> bool {
+    !value.contains(|ch| ch == '\r' || ch == '\n')
+}
+
+/// a header name consists of printable us-ascii characters except `:` (see RFC 5322)
+fn is_valid_header_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|bch| bch >= 33 && bch <= 126 && bch != b':')
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{parse_header_lines, is_valid_header_value};
+
+    #[test]
+    fn parses_headers_in_order() {
+        let headers = parse_header_lines(
+            "List-Unsubscribe: <https://example.com/unsub>\n\nX-Campaign:  spring  \r\nReply-To: a@example.com\n"
+        ).unwrap();
+        assert_eq!(headers, vec![
+            ("List-Unsubscribe".to_owned(), "<https://example.com/unsub>".to_owned()),
+            ("X-Campaign".to_owned(), "spring".to_owned()),
+            ("Reply-To".to_owned(), "a@example.com".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn joins_continuation_lines() {
+        let headers = parse_header_lines("X-Long: a\n   b\n\tc\n").unwrap();
+        assert_eq!(headers, vec![ ("X-Long".to_owned(), "a b c".to_owned()) ]);
+    }
+
+    #[test]
+    fn omits_headers_with_empty_value() {
+        let headers = parse_header_lines("X-Empty:\nX-Campaign: spring\nX-Blank:   \n").unwrap();
+        assert_eq!(headers, vec![ ("X-Campaign".to_owned(), "spring".to_owned()) ]);
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert_eq!(parse_header_lines("no colon here"), Err("no colon here".to_owned()));
+        assert_eq!(parse_header_lines("Bad Name: x"), Err("Bad Name: x".to_owned()));
+        assert_eq!(parse_header_lines(": x"), Err(": x".to_owned()));
+        assert_eq!(parse_header_lines("  leading continuation"), Err("  leading continuation".to_owned()));
+    }
+
+    #[test]
+    fn header_values_must_not_contain_line_breaks() {
+        assert!(is_valid_header_value("<https://example.com/unsub>"));
+        assert!(!is_valid_header_value("x>\nBcc: victim@evil"));
+        assert!(!is_valid_header_value("x>\r\nBcc: victim@evil"));
+        assert!(!is_valid_header_value("x\r"));
+    }
+}
@@ -0,0 +1,480 @@
+//! Conversion of html bodies to readable plain text.
+//!
+//! This is not a full html parser, it is meant for the (normally quite
+//! simple) html of mail templates:
+//!
+//! - whitespace is collapsed like a browser would do it (except in `<pre>`)
+//! - paragraphs, divs, table rows etc. are separated by line breaks
+//! - `<h1>` and `<h2>` headings are underlined with `=` and `-`
+//! - list items are prefixed with `* ` or their number, nested lists are indented
+//! - links are turned into footnotes (`text[1]` and `[1] <href>` at the end),
+//!   links to content ids or anchors and links whose text is the link are kept inline
+//! - images are replaced by their alt text (if they have one)
+//! - the content of `<head>`, `<style>` and `<script>` is removed
+
+/// converts the given html to plain text
+pub fn html_to_text(html: &str) -> String {
+    let mut converter = Converter::default();
+    let mut rest = html;
+    while let Some(idx) = rest.find('<') {
+        converter.text(&rest[..idx]);
+        rest = &rest[idx..];
+
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+            continue;
+        }
+
+        let end = tag_end(rest);
+        let after_tag = if end < rest.len() { &rest[end + 1..] } else { "" };
+        match Tag::parse(&rest[1..end]) {
+            Some(tag) => {
+                converter.tag(tag);
+                rest = after_tag;
+            },
+            // doctype, processing instruction etc.
+            None if rest[1..].starts_with(|ch| ch == '!' || ch == '?') => rest = after_tag,
+            // not a tag, e.g. "a < b"
+            None => {
+                converter.text("<");
+                rest = &rest[1..];
+            }
+        }
+    }
+    converter.text(rest);
+    converter.finish()
+}
+
+/// returns the index of the `>` ending the tag at the start of `input` (or `input.len()`)
+pub(crate) fn tag_end(input: &str) -> usize {
+    let mut quote = None;
+    for (idx, ch) in input.char_indices() {
+        match (quote, ch) {
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (Some(q), ch) if q == ch => quote = None,
+            (None, '>') => return idx,
+            _ => {}
+        }
+    }
+    input.len()
+}
+
+struct Tag {
+    name: String,
+    is_end: bool,
+    attributes: Vec<(String, String)>
+}
+
+impl Tag {
+
+    /// parses the content of a tag (without the `<`, `>`)
+    fn parse(content: &str) -> Option<Tag> {
+        let (is_end, content) =
+            if content.starts_with('/') { (true, &content[1..]) } else { (false, content) };
+
+        let name_len = content.find(|ch: char| !ch.is_ascii_alphanumeric()).unwrap_or(content.len());
+        if name_len == 0 {
+            return None;
+        }
+        let name = content[..name_len].to_ascii_lowercase();
+        let attributes = parse_attributes(&content[name_len..]);
+        Some(Tag { name, is_end, attributes })
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|&&(ref attr_name, _)| attr_name == name)
+            .map(|&(_, ref value)| &**value)
+    }
+}
+
+fn parse_attributes(mut input: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    loop {
+        input = input.trim_start_matches(|ch: char| ch.is_whitespace() || ch == '/');
+        let name_len = input.find(|ch: char| ch.is_whitespace() || ch == '=' || ch == '/')
+            .unwrap_or(input.len());
+        if name_len == 0 {
+            return attributes;
+        }
+        let name = input[..name_len].to_ascii_lowercase();
+        input = input[name_len..].trim_start();
+
+        let mut value = String::new();
+        if input.starts_with('=') {
+            input = input[1..].trim_start();
+            match input.chars().next() {
+                Some(quote @ '"') | Some(quote @ '\'') => {
+                    let end = input[1..].find(quote).map(|idx| idx + 1).unwrap_or(input.len());
+                    value = decode_entities(&input[1..end]);
+                    input = if end < input.len() { &input[end + 1..] } else { "" };
+                },
+                _ => {
+                    let end = input.find(char::is_whitespace).unwrap_or(input.len());
+                    value = decode_entities(&input[..end]);
+                    input = &input[end..];
+                }
+            }
+        }
+        attributes.push((name, value));
+    }
+}
+
+/// decodes the common named entities and all numeric entities
+pub(crate) fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        let decoded = rest.find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end]).map(|ch| (ch, end)));
+
+        match decoded {
+            Some((ch, end)) => {
+                out.push(ch);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if entity.starts_with("#x") || entity.starts_with("#X") {
+        return u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32);
+    }
+    if entity.starts_with('#') {
+        return entity[1..].parse().ok().and_then(::std::char::from_u32);
+    }
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "euro" => '€',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        _ => return None
+    })
+}
+
+struct Converter {
+    out: String,
+    /// nothing but the indent was written to the current line
+    at_line_start: bool,
+    /// number of line breaks to insert before the next text
+    pending_breaks: usize,
+    /// a space has to be inserted before the next text (if not at line start)
+    pending_space: bool,
+    /// nesting depth of elements whose content is dropped
+    skip_depth: usize,
+    /// nesting depth of `<pre>` elements
+    pre_depth: usize,
+    /// `None` for unordered lists, else the number of the last item
+    lists: Vec<Option<usize>>,
+    /// hrefs of the currently open links with the output length at their start
+    open_links: Vec<(Option<String>, usize)>,
+    /// hrefs listed as footnotes
+    footnotes: Vec<String>
+}
+
+impl Default for Converter {
+    fn default() -> Self {
+        Converter {
+            out: String::new(),
+            at_line_start: true,
+            pending_breaks: 0,
+            pending_space: false,
+            skip_depth: 0,
+            pre_depth: 0,
+            lists: Vec::new(),
+            open_links: Vec::new(),
+            footnotes: Vec::new()
+        }
+    }
+}
+
+impl Converter {
+
+    fn text(&mut self, text: &str) {
+        if self.skip_depth > 0 || text.is_empty() {
+            return;
+        }
+        let text = decode_entities(text);
+        if self.pre_depth > 0 {
+            self.flush_breaks();
+            let indent = self.indent();
+            for (idx, line) in text.split('\n').enumerate() {
+                if idx > 0 {
+                    self.out.push('\n');
+                    self.out.push_str(&indent);
+                }
+                self.out.push_str(line.trim_end_matches('\r'));
+            }
+            self.at_line_start = false;
+            return;
+        }
+
+        for ch in text.chars() {
+            // non breaking spaces are kept as normal spaces but not collapsed
+            if ch.is_whitespace() && ch != '\u{a0}' {
+                self.pending_space = true;
+                continue;
+            }
+            self.flush_breaks();
+            if self.pending_space && !self.at_line_start {
+                self.out.push(' ');
+            }
+            self.pending_space = false;
+            self.at_line_start = false;
+            self.out.push(if ch == '\u{a0}' { ' ' } else { ch });
+        }
+    }
+
+    fn tag(&mut self, tag: Tag) {
+        match &*tag.name {
+            "head" | "style" | "script" | "title" => {
+                if tag.is_end {
+                    self.skip_depth = self.skip_depth.saturating_sub(1);
+                } else {
+                    self.skip_depth += 1;
+                }
+            },
+            _ if self.skip_depth > 0 => {},
+            "br" if !self.out.is_empty() => {
+                self.flush_breaks();
+                self.out.push('\n');
+                let indent = self.indent();
+                self.out.push_str(&indent);
+                self.pending_space = false;
+                self.at_line_start = true;
+            },
+            "p" | "table" | "blockquote" => self.block_break(2),
+            "div" | "tr" | "section" | "article" | "header" | "footer" | "dl" | "dt" | "dd" =>
+                self.block_break(1),
+            "td" | "th" => self.pending_space = true,
+            "hr" => {
+                self.block_break(1);
+                self.flush_breaks();
+                self.out.push_str("----------");
+                self.at_line_start = false;
+                self.block_break(1);
+            },
+            "pre" => {
+                self.block_break(2);
+                if tag.is_end {
+                    self.pre_depth = self.pre_depth.saturating_sub(1);
+                } else {
+                    self.pre_depth += 1;
+                }
+            },
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if tag.is_end {
+                    self.underline_heading(&tag.name);
+                }
+                self.block_break(2);
+            },
+            "ul" | "ol" => {
+                if tag.is_end {
+                    self.lists.pop();
+                } else {
+                    self.lists.push(if tag.name == "ol" { Some(0) } else { None });
+                }
+                self.block_break(if self.lists.is_empty() { 2 } else { 1 });
+            },
+            "li" if !tag.is_end => {
+                self.block_break(1);
+                let marker = match self.lists.last_mut() {
+                    Some(&mut Some(ref mut number)) => {
+                        *number += 1;
+                        format!("{}. ", number)
+                    },
+                    _ => "* ".to_owned()
+                };
+                // the marker is indented one level less than the content of the item
+                let level = self.lists.len().saturating_sub(1);
+                self.flush_breaks_with_indent(level);
+                self.out.push_str(&marker);
+                self.at_line_start = true;
+            },
+            "a" => {
+                if tag.is_end {
+                    self.close_link();
+                } else {
+                    let href = tag.attribute("href").map(|href| href.trim().to_owned());
+                    self.open_links.push((href, self.out.len()));
+                }
+            },
+            "img" => {
+                if let Some(alt) = tag.attribute("alt").filter(|alt| !alt.trim().is_empty()) {
+                    let alt = format!("[{}]", alt.trim());
+                    self.text(&alt);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn close_link(&mut self) {
+        let (href, start) = match self.open_links.pop() {
+            Some((Some(href), start)) => (href, start),
+            _ => return
+        };
+        let is_inline = {
+            let text = self.out.get(start..).unwrap_or("").trim();
+            href.is_empty()
+                || href.starts_with('#')
+                || href.starts_with("cid:")
+                || text == href
+                || href.starts_with("mailto:") && text == &href["mailto:".len()..]
+        };
+        if is_inline {
+            return;
+        }
+
+        let number = match self.footnotes.iter().position(|existing| *existing == href) {
+            Some(idx) => idx + 1,
+            None => {
+                self.footnotes.push(href);
+                self.footnotes.len()
+            }
+        };
+        self.flush_breaks();
+        self.out.push_str(&format!("[{}]", number));
+        self.at_line_start = false;
+    }
+
+    fn underline_heading(&mut self, name: &str) {
+        let underline = match name {
+            "h1" => '=',
+            "h2" => '-',
+            _ => return
+        };
+        let line_start = self.out.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        let len = self.out[line_start..].trim().chars().count();
+        if len > 0 {
+            self.out.push('\n');
+            self.out.extend(::std::iter::repeat(underline).take(len));
+            self.at_line_start = false;
+        }
+    }
+
+    fn block_break(&mut self, breaks: usize) {
+        self.pending_breaks = self.pending_breaks.max(breaks);
+        self.pending_space = false;
+    }
+
+    fn flush_breaks(&mut self) {
+        let level = self.lists.len();
+        self.flush_breaks_with_indent(level);
+    }
+
+    fn flush_breaks_with_indent(&mut self, level: usize) {
+        if self.pending_breaks == 0 {
+            return;
+        }
+        self.pending_breaks_done();
+        self.out.push_str(&"  ".repeat(level));
+        self.at_line_start = true;
+    }
+
+    /// writes the pending line breaks (ignoring them at the start of the output)
+    fn pending_breaks_done(&mut self) {
+        let breaks = self.pending_breaks;
+        self.pending_breaks = 0;
+        self.pending_space = false;
+
+        // remove trailing spaces of the last line
+        let trimmed_len = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed_len);
+        if self.out.is_empty() {
+            return;
+        }
+        let existing = self.out.len() - self.out.trim_end_matches('\n').len();
+        for _ in existing..breaks {
+            self.out.push('\n');
+        }
+    }
+
+    fn indent(&self) -> String {
+        "  ".repeat(self.lists.len())
+    }
+
+    fn finish(mut self) -> String {
+        let trimmed_len = self.out.trim_end().len();
+        self.out.truncate(trimmed_len);
+        if !self.footnotes.is_empty() {
+            self.out.push_str("\n\n");
+            for (idx, href) in self.footnotes.iter().enumerate() {
+                self.out.push_str(&format!("[{}] {}\n", idx + 1, href));
+            }
+        } else if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::html_to_text;
+
+    #[test]
+    fn collapses_whitespace_and_separates_blocks() {
+        let html = "<html><head><title>x</title><style>p { color: red }</style></head>\n\
+            <body><p>Hy   Liz,\n  how are you?</p><p>Bye<br>Bob</p></body></html>";
+        assert_eq!(html_to_text(html), "Hy Liz, how are you?\n\nBye\nBob\n");
+    }
+
+    #[test]
+    fn underlines_headings() {
+        let html = "<h1>Welcome</h1><h2>News</h2><h3>Other</h3>text";
+        assert_eq!(html_to_text(html), "Welcome\n=======\n\nNews\n----\n\nOther\n\ntext\n");
+    }
+
+    #[test]
+    fn renders_lists() {
+        let html = "<ul><li>a</li><li>b<ol><li>one</li><li>two</li></ol></li></ul><p>end</p>";
+        assert_eq!(html_to_text(html), "* a\n* b\n  1. one\n  2. two\n\nend\n");
+    }
+
+    #[test]
+    fn links_become_footnotes() {
+        let html = concat!(
+            r#"<p>See <a href="https://example.com/a">our site</a> or "#,
+            r#"<a href='https://example.com/a'>this</a>, <a href="https://example.com/b">https://example.com/b</a>, "#,
+            r#"<a href="mailto:info@example.com">info@example.com</a> and <a href="https://example.com/c">c</a>.</p>"#
+        );
+        assert_eq!(html_to_text(html), concat!(
+            "See our site[1] or this[1], https://example.com/b, info@example.com and c[2].\n",
+            "\n",
+            "[1] https://example.com/a\n",
+            "[2] https://example.com/c\n"
+        ));
+    }
+
+    #[test]
+    fn decodes_entities_and_uses_alt_texts() {
+        let html = r#"<p>Tom &amp; Jerry &lt;3 &#8364;&#x21; <img src="cid:logo" alt="Logo"> <!-- hidden --></p>"#;
+        assert_eq!(html_to_text(html), "Tom & Jerry <3 €! [Logo]\n");
+    }
+
+    #[test]
+    fn keeps_preformatted_text() {
+        let html = "<p>code:</p><pre>a  b\n  c</pre>";
+        assert_eq!(html_to_text(html), "code:\n\na  b\n  c\n");
+    }
+}
@@ -14,15 +14,20 @@ extern crate conduit_mime_types;
 #[macro_use]
 extern crate lazy_static;
 extern crate serde;
-
-
-#[cfg(any(feature="tera-engine", feature="handlebars-engine"))]
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+#[macro_use]
+extern crate log;
+extern crate arc_swap;
+
 #[cfg(feature="tera-engine")]
 extern crate tera as tera_crate;
 #[cfg(feature="handlebars-engine")]
 extern crate handlebars as handlebars_crate;
+#[cfg(feature="minijinja-engine")]
+extern crate minijinja as minijinja_crate;
 
 // ordered by possible "dependentness",
 // any module further down in the list
@@ -30,19 +35,56 @@ extern crate handlebars as handlebars_crate;
 // But a module depending on a module later
 // in the ordering _should_ not happen.
 pub mod error;
+mod sniff;
 mod utils;
+mod header_lines;
+mod html2text;
+mod css;
+mod post_process;
+mod links;
+mod flowed;
+mod line_length;
+mod locale;
+mod compat;
+mod resolver;
 mod settings;
 mod spec;
+mod report;
+mod cache;
 //TODO rename
 #[macro_use]
 mod traits;
 mod rte;
+mod shared;
+mod validate;
+mod preview;
+mod samples;
+mod watch;
+pub mod typed;
 #[cfg(feature="tera-engine")]
 pub mod tera;
 #[cfg(feature="handlebars-engine")]
 pub mod handlebars;
+#[cfg(feature="minijinja-engine")]
+pub mod minijinja;
 
+pub use self::report::*;
+pub use self::locale::*;
+pub use self::html2text::html_to_text;
+pub use self::css::inline_css;
+pub use self::post_process::*;
+pub use self::links::*;
+pub use self::flowed::FLOWED_LINE_WIDTH;
+pub use self::utils::MAX_LINE_LENGTH;
+pub use self::line_length::LineLengthPolicy;
+pub use self::compat::*;
+pub use self::resolver::*;
 pub use self::settings::*;
 pub use self::spec::*;
 pub use self::traits::*;
-pub use self::rte::*;
\ No newline at end of file
+pub use self::rte::*;
+pub use self::shared::*;
+pub use self::validate::*;
+pub use self::preview::*;
+pub use self::samples::*;
+pub use self::watch::*;
\ No newline at end of file
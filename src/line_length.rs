@@ -0,0 +1,168 @@
+//! Enforcement of the maximal line length of rendered bodies.
+//!
+//! RFC 5322 limits lines to 998 octets (excluding the CRLF), templates
+//! producing e.g. minified html can easily exceed this.
+use ::utils::MAX_LINE_LENGTH;
+use ::flowed::{encode_flowed_too_long_lines, floor_char_boundary, FLOWED_LINE_WIDTH};
+
+/// What to do if a rendered body contains lines longer than `MAX_LINE_LENGTH`.
+///
+/// (see `RenderTemplateEngine::set_line_length_policy`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineLengthPolicy {
+    /// leave over-long lines as they are
+    Ignore,
+    /// wrap over-long lines
+    ///
+    /// `text/html` bodies are wrapped at whitespace outside of tags and
+    /// attribute values or before tags. `text/plain` bodies are encoded as
+    /// `format=flowed` (with the `format` parameter added to the media type),
+    /// only the over-long lines are wrapped. Bodies of other media types
+    /// are left unchanged.
+    Wrap,
+    /// fail rendering with `ProcessingError::LineTooLong`
+    Error
+}
+
+impl Default for LineLengthPolicy {
+    fn default() -> Self {
+        LineLengthPolicy::Ignore
+    }
+}
+
+/// returns the (1-based) number and length of the first line longer than `MAX_LINE_LENGTH`
+pub(crate) fn find_too_long_line(text: &str) -> Option<(usize, usize)> {
+    text.split('\n')
+        .map(|line| line.trim_end_matches('\r').len())
+        .enumerate()
+        .find(|&(_, len)| len > MAX_LINE_LENGTH)
+        .map(|(idx, len)| (idx + 1, len))
+}
+
+/// encodes a text body with over-long lines as `format=flowed`
+///
+/// Only lines longer than `MAX_LINE_LENGTH` are wrapped (at `FLOWED_LINE_WIDTH`).
+pub(crate) fn wrap_text(text: &str) -> String {
+    encode_flowed_too_long_lines(text, FLOWED_LINE_WIDTH)
+}
+
+/// breaks over-long lines of a html body
+///
+/// Lines are broken by replacing whitespace outside of tags and attribute
+/// values with a line break or by inserting a line break before a tag,
+/// both do not change how the html is displayed (except in `<pre>`).
+/// If there is no such position a line is broken at `MAX_LINE_LENGTH`.
+pub(crate) fn wrap_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len() + html.len() / MAX_LINE_LENGTH * 2);
+    // tag/quote state is carried over line breaks
+    let mut state = HtmlState::default();
+    let mut lines = html.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        let (line, cr) =
+            if line.ends_with('\r') {
+                (&line[..line.len() - 1], "\r")
+            } else {
+                (line, "")
+            };
+
+        let mut rest = line;
+        while rest.len() > MAX_LINE_LENGTH {
+            let (end, skip) = state.last_break(rest, MAX_LINE_LENGTH)
+                .unwrap_or_else(|| (floor_char_boundary(rest, MAX_LINE_LENGTH), 0));
+            state.advance(&rest[..end + skip]);
+            out.push_str(&rest[..end]);
+            out.push_str("\r\n");
+            rest = &rest[end + skip..];
+        }
+        state.advance(rest);
+        out.push_str(rest);
+        out.push_str(cr);
+        if lines.peek().is_some() {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HtmlState {
+    in_tag: bool,
+    quote: Option<char>
+}
+
+impl HtmlState {
+
+    fn advance(&mut self, text: &str) {
+        for ch in text.chars() {
+            self.step(ch);
+        }
+    }
+
+    fn step(&mut self, ch: char) {
+        match (self.in_tag, self.quote, ch) {
+            (false, _, '<') => self.in_tag = true,
+            (true, None, '>') => self.in_tag = false,
+            (true, None, '"') | (true, None, '\'') => self.quote = Some(ch),
+            (true, Some(quote), ch) if quote == ch => self.quote = None,
+            _ => {}
+        }
+    }
+
+    /// finds the last position in `line[..max]` where it can be broken
+    ///
+    /// Returns the end of the broken line and how many bytes (of whitespace)
+    /// are replaced by the line break. A break at index 0 is not used.
+    fn last_break(&self, line: &str, max: usize) -> Option<(usize, usize)> {
+        let mut state = *self;
+        let mut last = None;
+        for (idx, ch) in line.char_indices() {
+            if idx > max {
+                break;
+            }
+            if idx > 0 && state.quote.is_none() {
+                if ch == '<' && !state.in_tag {
+                    last = Some((idx, 0));
+                } else if ch == ' ' || ch == '\t' {
+                    last = Some((idx, 1));
+                }
+            }
+            state.step(ch);
+        }
+        last
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{find_too_long_line, wrap_html, MAX_LINE_LENGTH};
+
+    #[test]
+    fn finds_too_long_lines() {
+        let long = "a".repeat(MAX_LINE_LENGTH + 1);
+        assert_eq!(find_too_long_line(&format!("a\r\n{}\r\n", "a".repeat(MAX_LINE_LENGTH))), None);
+        assert_eq!(find_too_long_line(&format!("a\r\nb\r\n{}\r\n", long)), Some((3, MAX_LINE_LENGTH + 1)));
+    }
+
+    #[test]
+    fn wraps_html_outside_of_attribute_values() {
+        let cell = format!("<td title=\"{}\">x</td>", "t ".repeat(20));
+        let html = format!("<table><tr>{}</tr></table>\r\n<p>end</p>", cell.repeat(40));
+        let wrapped = wrap_html(&html);
+
+        assert_eq!(find_too_long_line(&wrapped), None);
+        let without_whitespace = |text: &str| text.replace("\r\n", "").replace(' ', "");
+        assert_eq!(without_whitespace(&wrapped), without_whitespace(&html));
+        for line in wrapped.split("\r\n") {
+            assert_eq!(line.matches('"').count() % 2, 0, "line broken in attribute value");
+        }
+    }
+
+    #[test]
+    fn wraps_html_text_at_whitespace() {
+        let html = format!("<p>{}</p>", "word ".repeat(300));
+        let wrapped = wrap_html(&html);
+        assert_eq!(find_too_long_line(&wrapped), None);
+        assert_eq!(wrapped.replace("\r\n", " "), html);
+    }
+}
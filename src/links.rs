@@ -0,0 +1,242 @@
+//! Rewriting of links in rendered bodies, e.g. for click-tracking.
+//!
+//! In html bodies the `href` of all `<a>` tags is rewritten, in text bodies
+//! all `http://` and `https://` urls are. Links to embeddings (`cid:`),
+//! mail addresses (`mailto:`) and fragments (`#...`) are never rewritten.
+//!
+//! Each distinct url is passed only once to the rewrite function, so the
+//! same url is rewritten the same way in all bodies (e.g. the html body and
+//! the text body generated from it).
+use std::collections::HashMap;
+
+use ::css::{StartTag, find_ignore_ascii_case, escape_attribute};
+use ::html2text::{tag_end, decode_entities};
+
+/// A link which was rewritten.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RewrittenLink {
+    /// the url as it was rendered
+    pub original: String,
+    /// the url it was replaced with
+    pub rewritten: String
+}
+
+/// Rewrites links using a function, remembering the rewritten links.
+///
+/// The function gets the original url and returns the url to use instead,
+/// or `None` if the link should not be rewritten.
+pub struct LinkRewriter<F>
+    where F: FnMut(&str) -> Option<String>
+{
+    rewrite: F,
+    /// cache of all urls passed to the function
+    rewritten: HashMap<String, Option<String>>,
+    /// the rewritten links in the order they were first found
+    links: Vec<RewrittenLink>
+}
+
+impl<F> LinkRewriter<F>
+    where F: FnMut(&str) -> Option<String>
+{
+    pub fn new(rewrite: F) -> Self {
+        LinkRewriter {
+            rewrite,
+            rewritten: HashMap::new(),
+            links: Vec::new()
+        }
+    }
+
+    /// rewrites the `href` of all `<a>` tags in the html
+    ///
+    /// The `href` is entity decoded before it is passed to the rewrite
+    /// function and the rewritten url is escaped again.
+    pub fn rewrite_html(&mut self, html: &str) -> String {
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(idx) = rest.find('<') {
+            out.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+
+            if rest.starts_with("<!--") {
+                let end = rest.find("-->").map(|end| end + 3).unwrap_or(rest.len());
+                out.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+
+            let end = (tag_end(rest) + 1).min(rest.len());
+            let tag = &rest[..end];
+            rest = &rest[end..];
+
+            let start_tag = match StartTag::parse(tag) {
+                Some(start_tag) => start_tag,
+                None => {
+                    out.push_str(tag);
+                    continue;
+                }
+            };
+
+            match &*start_tag.name {
+                "a" => {
+                    let rewritten = start_tag.attribute("href")
+                        .and_then(|href| self.rewrite_url(&decode_entities(href.trim())));
+                    match rewritten {
+                        Some(url) => out.push_str(&start_tag.with_attribute(tag, "href", &escape_attribute(&url))),
+                        None => out.push_str(tag)
+                    }
+                },
+                "style" | "script" => {
+                    out.push_str(tag);
+                    let close = format!("</{}", start_tag.name);
+                    let content_len = find_ignore_ascii_case(rest, &close).unwrap_or(rest.len());
+                    out.push_str(&rest[..content_len]);
+                    rest = &rest[content_len..];
+                },
+                _ => out.push_str(tag)
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// rewrites all `http://` and `https://` urls in the text
+    ///
+    /// Punctuation at the end of an url (e.g. the `.` ending a sentence)
+    /// is not seen as part of it.
+    pub fn rewrite_text(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(idx) = find_url_start(rest) {
+            out.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+            let len = url_len(rest);
+            let url = &rest[..len];
+            match self.rewrite_url(url) {
+                Some(rewritten) => out.push_str(&rewritten),
+                None => out.push_str(url)
+            }
+            rest = &rest[len..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// the links rewritten so far, in the order they were first found
+    pub fn links(&self) -> &[RewrittenLink] {
+        &self.links
+    }
+
+    pub fn into_links(self) -> Vec<RewrittenLink> {
+        self.links
+    }
+
+    fn rewrite_url(&mut self, url: &str) -> Option<String> {
+        if url.is_empty() || !is_rewritable(url) {
+            return None;
+        }
+        if let Some(rewritten) = self.rewritten.get(url) {
+            return rewritten.clone();
+        }
+
+        let rewritten = (self.rewrite)(url);
+        if let Some(ref rewritten) = rewritten {
+            self.links.push(RewrittenLink {
+                original: url.to_owned(),
+                rewritten: rewritten.clone()
+            });
+        }
+        self.rewritten.insert(url.to_owned(), rewritten.clone());
+        rewritten
+    }
+}
+
+fn is_rewritable(url: &str) -> bool {
+    let lowercase = url.to_ascii_lowercase();
+    !(lowercase.starts_with("cid:") || lowercase.starts_with("mailto:") || lowercase.starts_with('#'))
+}
+
+fn find_url_start(text: &str) -> Option<usize> {
+    let http = find_ignore_ascii_case(text, "http://");
+    let https = find_ignore_ascii_case(text, "https://");
+    match (http, https) {
+        (Some(http), Some(https)) => Some(http.min(https)),
+        (http, https) => http.or(https)
+    }
+}
+
+/// the length of the url at the start of the text
+fn url_len(text: &str) -> usize {
+    let mut len = text.find(|ch: char| ch.is_whitespace() || "<>\"'".contains(ch))
+        .unwrap_or(text.len());
+    loop {
+        let url = &text[..len];
+        let trim = match url.chars().last() {
+            Some('.') | Some(',') | Some(';') | Some(':') | Some('!') | Some('?') => true,
+            Some(')') => url.matches('(').count() < url.matches(')').count(),
+            Some(']') => url.matches('[').count() < url.matches(']').count(),
+            _ => false
+        };
+        if !trim {
+            return len;
+        }
+        len -= 1;
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{LinkRewriter, RewrittenLink};
+
+    fn tracking(url: &str) -> Option<String> {
+        Some(format!("https://t.example.com/c?r=1&u={}", url.len()))
+    }
+
+    #[test]
+    fn rewrites_hrefs_of_anchors() {
+        let mut rewriter = LinkRewriter::new(tracking);
+        let html = rewriter.rewrite_html(concat!(
+            "<p><a class=x href=\"https://example.com/?a=1&amp;b=2\">a</a> ",
+            "<a href='mailto:a@example.com'>b</a> <a href=\"cid:logo\">c</a> <a href=\"#top\">d</a> ",
+            "<link href=\"https://example.com/style.css\"><!-- <a href=\"https://example.com\"> --></p>"
+        ));
+        assert_eq!(html, concat!(
+            "<p><a class=x href=\"https://t.example.com/c?r=1&amp;u=28\">a</a> ",
+            "<a href='mailto:a@example.com'>b</a> <a href=\"cid:logo\">c</a> <a href=\"#top\">d</a> ",
+            "<link href=\"https://example.com/style.css\"><!-- <a href=\"https://example.com\"> --></p>"
+        ));
+        assert_eq!(rewriter.into_links(), vec![ RewrittenLink {
+            original: "https://example.com/?a=1&b=2".to_owned(),
+            rewritten: "https://t.example.com/c?r=1&u=28".to_owned()
+        }]);
+    }
+
+    #[test]
+    fn rewrites_urls_in_text() {
+        let mut rewriter = LinkRewriter::new(|url: &str| Some(format!("<{}>", url.to_uppercase())));
+        let text = rewriter.rewrite_text(
+            "See https://example.com/a. Or (http://example.com/b_(c)), mail: mailto:x@example.com\r\n"
+        );
+        assert_eq!(text, "See <HTTPS://EXAMPLE.COM/A>. Or (<HTTP://EXAMPLE.COM/B_(C)>), mail: mailto:x@example.com\r\n");
+        assert_eq!(rewriter.links().len(), 2);
+    }
+
+    #[test]
+    fn same_urls_are_rewritten_once() {
+        let mut calls = 0;
+        let (html, text) = {
+            let mut rewriter = LinkRewriter::new(|url: &str| {
+                calls += 1;
+                if url.contains("unsubscribe") { None } else { Some("https://t.example.com/1".to_owned()) }
+            });
+            let html = rewriter.rewrite_html(
+                "<a href=\"https://example.com\">x</a><a href=\"https://example.com/unsubscribe\">u</a>");
+            let text = rewriter.rewrite_text("x[1] https://example.com https://example.com/unsubscribe");
+            assert_eq!(rewriter.links().len(), 1);
+            (html, text)
+        };
+        assert_eq!(calls, 2);
+        assert_eq!(html, "<a href=\"https://t.example.com/1\">x</a><a href=\"https://example.com/unsubscribe\">u</a>");
+        assert_eq!(text, "x[1] https://t.example.com/1 https://example.com/unsubscribe");
+    }
+}
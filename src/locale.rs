@@ -0,0 +1,177 @@
+//! Locales used to select localized variants of templates.
+//!
+//! A localized variant of the template `welcome` is a normal `TemplateSpec`
+//! associated with the id `welcome.<locale>` (e.g. `welcome.de-AT`). Such
+//! variants are created by `TemplateSpec::from_dirs` from template dirs
+//! named `welcome.de-AT/` as well as from `welcome/de-AT/` folders listed
+//! in the `locales` of the manifest of `welcome/`.
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use ::error::InvalidLocale;
+
+/// name of the header the locale of a used template variant is set as
+pub(crate) const CONTENT_LANGUAGE: &str = "Content-Language";
+
+/// A (simplified) BCP 47 language tag like `de`, `de-AT` or `zh-Hant-TW`.
+///
+/// The tag is normalized when parsed: `_` is accepted as separator but
+/// replaced by `-`, the language is lowercase, scripts are titlecase
+/// and regions uppercase (e.g. `DE_at` becomes `de-AT`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Locale {
+    tag: String
+}
+
+impl Locale {
+
+    /// parses and normalizes a language tag
+    ///
+    /// The first subtag has to consist of 2 or 3 letters, all other
+    /// subtags of 1 to 8 ascii alphanumeric characters.
+    pub fn parse(tag: &str) -> Result<Self, InvalidLocale> {
+        Self::parse_opt(tag)
+            .ok_or_else(|| InvalidLocale { tag: tag.to_owned() })
+    }
+
+    pub(crate) fn parse_opt(tag: &str) -> Option<Self> {
+        let mut normalized = String::with_capacity(tag.len());
+        for (idx, subtag) in tag.split(|ch| ch == '-' || ch == '_').enumerate() {
+            let is_valid =
+                if idx == 0 {
+                    (subtag.len() == 2 || subtag.len() == 3)
+                        && subtag.chars().all(|ch| ch.is_ascii_alphabetic())
+                } else {
+                    subtag.len() >= 1 && subtag.len() <= 8
+                        && subtag.chars().all(|ch| ch.is_ascii_alphanumeric())
+                };
+            if !is_valid {
+                return None;
+            }
+
+            if idx > 0 {
+                normalized.push('-');
+            }
+            let is_alphabetic = subtag.chars().all(|ch| ch.is_ascii_alphabetic());
+            if idx > 0 && subtag.len() == 4 && is_alphabetic {
+                // script, e.g. "Hant"
+                normalized.push_str(&subtag[..1].to_ascii_uppercase());
+                normalized.push_str(&subtag[1..].to_ascii_lowercase());
+            } else if idx > 0 && subtag.len() == 2 && is_alphabetic {
+                // region, e.g. "AT"
+                normalized.push_str(&subtag.to_ascii_uppercase());
+            } else {
+                normalized.push_str(&subtag.to_ascii_lowercase());
+            }
+        }
+        Some(Locale { tag: normalized })
+    }
+
+    /// the normalized tag, usable as value of a `Content-Language` header
+    pub fn as_str(&self) -> &str {
+        &self.tag
+    }
+
+    /// the language, i.e. the first subtag
+    pub fn language(&self) -> &str {
+        self.tag.split('-').next().unwrap_or(&self.tag)
+    }
+
+    /// the locale with the last subtag removed, `None` if there is only one subtag
+    ///
+    /// E.g. `de-AT` has the parent `de`.
+    pub fn parent(&self) -> Option<Locale> {
+        self.tag.rfind('-')
+            .map(|idx| Locale { tag: self.tag[..idx].to_owned() })
+    }
+
+    /// returns this locale followed by all of its parents
+    ///
+    /// E.g. for `zh-Hant-TW` this is `zh-Hant-TW`, `zh-Hant`, `zh`.
+    pub fn fallback_chain(&self) -> Vec<Locale> {
+        let mut chain = vec![ self.clone() ];
+        while let Some(parent) = chain.last().and_then(Locale::parent) {
+            chain.push(parent);
+        }
+        chain
+    }
+}
+
+impl FromStr for Locale {
+    type Err = InvalidLocale;
+
+    fn from_str(tag: &str) -> Result<Self, Self::Err> {
+        Locale::parse(tag)
+    }
+}
+
+impl Display for Locale {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        fter.write_str(&self.tag)
+    }
+}
+
+impl AsRef<str> for Locale {
+    fn as_ref(&self) -> &str {
+        &self.tag
+    }
+}
+
+/// the id under which the variant of a template for the given locale is stored
+///
+/// E.g. `welcome.de-AT` for the template `welcome` and the locale `de-AT`.
+pub fn localized_template_id(template_id: &str, locale: &Locale) -> String {
+    format!("{}.{}", template_id, locale)
+}
+
+/// splits a localized template id (e.g. `welcome.de-AT`) into the template id and locale
+///
+/// Returns `None` if the part after the last `.` is not a valid locale.
+pub(crate) fn split_localized_template_id(id: &str) -> Option<(&str, Locale)> {
+    let idx = id.rfind('.')?;
+    if idx == 0 {
+        return None;
+    }
+    Locale::parse_opt(&id[idx + 1..])
+        .map(|locale| (&id[..idx], locale))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Locale, split_localized_template_id};
+
+    #[test]
+    fn parse_normalizes_tags() {
+        assert_eq!(Locale::parse("de").unwrap().as_str(), "de");
+        assert_eq!(Locale::parse("DE_at").unwrap().as_str(), "de-AT");
+        assert_eq!(Locale::parse("zh-hant-tw").unwrap().as_str(), "zh-Hant-TW");
+        assert_eq!(Locale::parse("es-419").unwrap().as_str(), "es-419");
+    }
+
+    #[test]
+    fn parse_rejects_non_locales() {
+        assert!(Locale::parse("html").is_err());
+        assert!(Locale::parse("d").is_err());
+        assert!(Locale::parse("de-").is_err());
+        assert!(Locale::parse("de-toolongsubtag").is_err());
+        assert!(Locale::parse("").is_err());
+    }
+
+    #[test]
+    fn fallback_chain_strips_subtags() {
+        let chain = Locale::parse("zh-Hant-TW").unwrap().fallback_chain();
+        let chain = chain.iter().map(|locale| locale.as_str()).collect::<Vec<_>>();
+        assert_eq!(chain, &["zh-Hant-TW", "zh-Hant", "zh"]);
+    }
+
+    #[test]
+    fn split_localized_ids() {
+        let (id, locale) = split_localized_template_id("welcome.de_at").unwrap();
+        assert_eq!(id, "welcome");
+        assert_eq!(locale.as_str(), "de-AT");
+        assert!(split_localized_template_id("welcome").is_none());
+        assert!(split_localized_template_id("welcome.html").is_none());
+        assert!(split_localized_template_id(".de").is_none());
+    }
+}
@@ -0,0 +1,39 @@
+use std::io;
+use minijinja_crate;
+
+use ::error::ProcessingError;
+
+
+#[derive(Debug, Fail)]
+pub enum MiniJinjaError {
+
+    #[fail(display="unknown template id: {}", id)]
+    UnknownTemplateId { id: String },
+
+    #[fail(display="template id is used multiple times for different templates: {}", id)]
+    TemplateIdCollision { id: String },
+
+    #[fail(display="can not add free template as template id is used by non-free template: {}", id)]
+    FreeTemplateIdCollision { id: String },
+
+    #[fail(display="{}", _0)]
+    Template(#[cause] minijinja_crate::Error),
+
+    #[fail(display="Template {}: {}", template, err)]
+    Io { #[cause] err: io::Error, template: String },
+
+    #[fail(display="{}", _0)]
+    Processing(#[cause] ProcessingError)
+}
+
+impl From<ProcessingError> for MiniJinjaError {
+    fn from(err: ProcessingError) -> Self {
+        MiniJinjaError::Processing(err)
+    }
+}
+
+impl From<minijinja_crate::Error> for MiniJinjaError {
+    fn from(err: minijinja_crate::Error) -> Self {
+        MiniJinjaError::Template(err)
+    }
+}
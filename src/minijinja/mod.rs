@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::borrow::Cow;
+use std::path::Path;
+use std::fs;
+
+use serde::Serialize;
+use minijinja_crate::{Environment, UndefinedBehavior, Value, path_loader};
+
+use ::traits::{RenderEngine, RenderEngineBase, AdditionalCIds};
+use ::spec::{TemplateSpec, SubTemplateSpec, TemplateSource};
+
+use self::error::MiniJinjaError;
+
+pub mod error;
+
+/// Render Engine using MiniJinja for rendering
+///
+/// # base templates
+///
+/// `new` takes a base templates dir, templates in it are loaded on demand when
+/// they are used through e.g. `{% extends "base_mail.html" %}` or
+/// `{% include "footer.html" %}`. Like with the `TeraRenderEngine` this dir
+/// _is separate from template dirs used by the `RenderTemplateEngine`_.
+///
+/// # "free" templates?
+///
+/// Additional templates not bound to any spec can be added with
+/// `register_free_template`, they are called free templates (see the
+/// `HandlebarsRenderEngine`). Adding a free template with the id of a
+/// template of a spec (or the other way around) is an error.
+///
+/// The id of a template of a spec is it's path (or the id of it's
+/// source), so html escaping is enabled based on the file extension.
+#[derive(Debug, Clone)]
+pub struct MiniJinjaRenderEngine {
+    env: Environment<'static>,
+    /// the ids of all templates loaded from specs
+    spec_templates: HashSet<String>,
+    free_templates: HashSet<String>
+}
+
+impl MiniJinjaRenderEngine {
+
+    /// create a new MiniJinjaRenderEngine given a base templates dir
+    ///
+    /// This will enable the strict undefined behavior by default,
+    /// i.e. using undefined values is an error.
+    pub fn new<P>(base_templates_dir: P) -> Self
+        where P: AsRef<Path>
+    {
+        let mut engine = MiniJinjaRenderEngine::default();
+        engine.env.set_loader(path_loader(base_templates_dir.as_ref()));
+        engine
+    }
+
+    /// exposes `Environment::set_undefined_behavior`
+    ///
+    /// The default of `MiniJinjaRenderEngine` is `UndefinedBehavior::Strict`.
+    pub fn set_undefined_behavior(&mut self, behavior: UndefinedBehavior) {
+        self.env.set_undefined_behavior(behavior)
+    }
+
+    /// exposes `Environment::add_global`
+    pub fn add_global<N, V>(&mut self, name: N, value: V)
+        where N: Into<Cow<'static, str>>, V: Into<Value>
+    {
+        self.env.add_global(name, value)
+    }
+
+    /// get a mut reference to the inner minijinja environment
+    ///
+    /// This can be used to e.g. add filters, tests and functions. But
+    /// adding or removing templates through it can break this instance
+    /// in a potential silent and hard to track way.
+    ///
+    /// This is why it's prefixed with `__` and ends
+    /// with `_dont_use_this` (see `HandlebarsRenderEngine`).
+    #[doc(hidden)]
+    pub fn __inner_mut_dont_use_this(&mut self) -> &mut Environment<'static> {
+        &mut self.env
+    }
+
+    /// Registers a free template based on a string.
+    ///
+    /// If there already is a free template with the name it's replaced.
+    /// Take a look at the type level documentation for more information
+    /// about free templates and potential name collisions.
+    pub fn register_free_template<S>(&mut self, name: &str, source: S) -> Result<(), MiniJinjaError>
+        where S: Into<String>
+    {
+        if self.spec_templates.contains(name) {
+            return Err(MiniJinjaError::FreeTemplateIdCollision { id: name.to_owned() });
+        }
+        self.env.add_template_owned(name.to_owned(), source.into())?;
+        self.free_templates.insert(name.to_owned());
+        Ok(())
+    }
+
+    /// Registers a free template based on the content of an file.
+    pub fn register_free_template_file<P>(&mut self, name: &str, path: P) -> Result<(), MiniJinjaError>
+        where P: AsRef<Path>
+    {
+        let source = read_template(path.as_ref())?;
+        self.register_free_template(name, source)
+    }
+
+    /// Unregister a free template if there is a free template with the given name.
+    pub fn unregister_free_template(&mut self, name: &str) {
+        if self.free_templates.remove(name) {
+            self.env.remove_template(name);
+        }
+    }
+
+    /// Unregister all free templates
+    pub fn clear_free_templates(&mut self) {
+        for id in self.free_templates.drain() {
+            self.env.remove_template(&id);
+        }
+    }
+
+    fn has_template(&self, id: &str) -> bool {
+        self.spec_templates.contains(id) || self.free_templates.contains(id)
+    }
+
+    fn add_spec_template(&mut self, id: &str, source: String) -> Result<(), MiniJinjaError> {
+        self.env.add_template_owned(id.to_owned(), source)?;
+        self.spec_templates.insert(id.to_owned());
+        Ok(())
+    }
+
+    fn remove_spec_template(&mut self, id: &str) {
+        if self.spec_templates.remove(id) {
+            self.env.remove_template(id);
+        }
+    }
+}
+
+fn read_template(path: &Path) -> Result<String, MiniJinjaError> {
+    fs::read_to_string(path)
+        .map_err(|err| MiniJinjaError::Io { err, template: path.display().to_string() })
+}
+
+impl Default for MiniJinjaRenderEngine {
+    /// create a new MiniJinjaRenderEngine without any base templates
+    fn default() -> Self {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        MiniJinjaRenderEngine {
+            env,
+            spec_templates: HashSet::new(),
+            free_templates: HashSet::new()
+        }
+    }
+}
+
+impl RenderEngineBase for MiniJinjaRenderEngine {
+    // nothing gurantees that the templates use \r\n, so by default fix newlines
+    // but it can be disabled
+    const PRODUCES_VALID_NEWLINES: bool = false;
+
+    type RenderError = MiniJinjaError;
+    type LoadingError = MiniJinjaError;
+
+    fn load_templates(&mut self, spec: &TemplateSpec) -> Result<(), Self::LoadingError> {
+        implement_load_helper! {
+            input::<MiniJinjaRenderEngine>(spec, self);
+            error(MiniJinjaError);
+            collision_error_fn(|id| { MiniJinjaError::TemplateIdCollision { id } });
+            has_template_fn(|engine, id| { engine.has_template(id) });
+            remove_fn(|engine, id| { engine.remove_spec_template(id) });
+            add_file_fn(|engine, path| {
+                let source = read_template(Path::new(path))?;
+                engine.add_spec_template(path, source)
+            });
+            add_content_fn(|engine, id, content| { engine.add_spec_template(id, content.clone()) });
+        }
+    }
+
+    /// This can be used to reload a templates.
+    fn unload_templates(&mut self, spec: &TemplateSpec) {
+        for sub_spec in spec.render_templates() {
+            self.remove_spec_template(sub_spec.source().id());
+        }
+    }
+
+    fn unknown_template_id_error(id: &str) -> Self::RenderError {
+        MiniJinjaError::UnknownTemplateId { id: id.to_owned() }
+    }
+}
+
+
+#[derive(Serialize)]
+struct DataWrapper<'a,D: Serialize + 'a> {
+    data: &'a D,
+    cids: AdditionalCIds<'a>
+}
+
+impl<D> RenderEngine<D> for MiniJinjaRenderEngine
+    where D: Serialize
+{
+    fn render(
+        &self,
+        spec: &SubTemplateSpec,
+        data: &D,
+        cids: AdditionalCIds
+    ) -> Result<String, Self::RenderError> {
+        let id = spec.source().id();
+        // don't let the loader look up unknown ids in the base templates dir
+        if !self.spec_templates.contains(id) {
+            return Err(Self::unknown_template_id_error(id));
+        }
+        let data = &DataWrapper { data, cids };
+        Ok(self.env.get_template(id)?.render(data)?)
+    }
+}
@@ -0,0 +1,96 @@
+//! Processing of rendered bodies before they are turned into `FileBuffer`s.
+//!
+//! Post-processors are registered on a `RenderTemplateEngine` for a media
+//! type (e.g. `text/html`) and are run in the order they were added on each
+//! rendered body of that type, after the built-in processing (like css
+//! inlining) and before newlines are fixed. This makes it possible to plug
+//! in e.g. minification, tracking pixels or text wrapping.
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use failure::Error;
+
+use headers::components::MediaType;
+
+use ::css::inline_css;
+use ::spec::TemplateSpec;
+use ::utils::has_full_type;
+
+/// Information about the body passed to a `PostProcessor`.
+pub struct PostProcessContext<'a> {
+    /// the id of the template the body was rendered from
+    pub template_id: &'a str,
+    /// the spec of the template the body was rendered from
+    pub spec: &'a TemplateSpec,
+    /// the media type of the body
+    pub media_type: &'a MediaType
+}
+
+/// A processing step run on rendered bodies of a given media type.
+///
+/// This is implemented for all fitting closures, i.e.
+/// `Fn(String, &PostProcessContext) -> Result<String, Error>`.
+pub trait PostProcessor: Send + Sync {
+
+    /// processes the rendered body, returning the new body
+    fn process(&self, content: String, ctx: &PostProcessContext) -> Result<String, Error>;
+}
+
+impl<F> PostProcessor for F
+    where F: Fn(String, &PostProcessContext) -> Result<String, Error> + Send + Sync
+{
+    fn process(&self, content: String, ctx: &PostProcessContext) -> Result<String, Error> {
+        (self)(content, ctx)
+    }
+}
+
+/// The built-in css inliner as `PostProcessor`.
+///
+/// It inlines the rules of the `<style>` blocks of the body and of the
+/// templates stylesheet (see `inline_css`). It's also used for
+/// `RenderTemplateEngine::set_inline_css`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InlineCss;
+
+impl PostProcessor for InlineCss {
+    fn process(&self, content: String, ctx: &PostProcessContext) -> Result<String, Error> {
+        Ok(inline_css(&content, ctx.spec.stylesheet()))
+    }
+}
+
+/// The post-processors registered on a `RenderTemplateEngine` (in order).
+#[derive(Default, Clone)]
+pub(crate) struct PostProcessors {
+    processors: Vec<(String, Arc<PostProcessor>)>
+}
+
+impl PostProcessors {
+
+    pub(crate) fn add(&mut self, media_type: &str, processor: Arc<PostProcessor>) {
+        self.processors.push((media_type.to_owned(), processor))
+    }
+
+    /// removes all processors for the media type, returns how many were removed
+    pub(crate) fn remove(&mut self, media_type: &str) -> usize {
+        let before = self.processors.len();
+        self.processors.retain(|&(ref for_type, _)| !for_type.eq_ignore_ascii_case(media_type));
+        before - self.processors.len()
+    }
+
+    /// the processors registered for the media type of the body, in order
+    pub(crate) fn for_media_type<'a>(&'a self, media_type: &'a MediaType)
+        -> impl Iterator<Item=&'a PostProcessor> + 'a
+    {
+        self.processors.iter()
+            .filter(move |&&(ref for_type, _)| has_full_type(media_type, for_type))
+            .map(|&(_, ref processor)| &**processor)
+    }
+}
+
+impl Debug for PostProcessors {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        fter.debug_list()
+            .entries(self.processors.iter().map(|&(ref media_type, _)| media_type))
+            .finish()
+    }
+}
@@ -0,0 +1,347 @@
+//! Rendering previews of templates to review them without sending mails.
+//!
+//! A preview is rendered like `RenderTemplateEngine::render_mail` does and then
+//! composed and encoded like any other mail, so the resulting `.eml` file
+//! is exactly what would be sent (except for the addresses). The rendered
+//! headers of the template are added to the `.eml` if the mail does not
+//! already have a header with the same name.
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use failure::Fail;
+use futures::Future;
+use serde::{Serialize, Serializer};
+use serde_json::{self, Value};
+
+use common::MailType;
+use common::encoder::EncodingBuffer;
+use headers::HeaderTryFrom;
+use headers::components::{Email, MediaType};
+use mail::Context;
+use template::{
+    TemplateEngine, MailSendData, MailParts,
+    InspectEmbeddedResources, Embedded
+};
+
+use ::error::{PreviewError, PreviewErrorKind};
+use ::traits::RenderEngine;
+use ::rte::RenderTemplateEngine;
+
+/// Arbitrary (json) data used to render a preview.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviewData(pub Value);
+
+impl PreviewData {
+
+    /// reads the data from a json file
+    pub fn from_json_file<P>(path: P) -> Result<Self, PreviewError>
+        where P: AsRef<Path>
+    {
+        let file = File::open(path)?;
+        let value = serde_json::from_reader(file)
+            .map_err(|err| err.context(PreviewErrorKind::InvalidData))?;
+        Ok(PreviewData(value))
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Self, PreviewError> {
+        let value = serde_json::from_str(json)
+            .map_err(|err| err.context(PreviewErrorKind::InvalidData))?;
+        Ok(PreviewData(value))
+    }
+}
+
+impl Serialize for PreviewData {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+// json data never contains embedded resources
+impl InspectEmbeddedResources for PreviewData {
+    fn inspect_resources(&self, _visitor: &mut FnMut(&Embedded)) {}
+    fn inspect_resources_mut(&mut self, _visitor: &mut FnMut(&mut Embedded)) {}
+}
+
+/// Addresses and subject used for the preview mail.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PreviewOptions {
+    pub from: String,
+    pub to: String,
+    /// if `None` the rendered subject template of the template is used, or
+    /// `"Preview of <template_id>"` if it has none
+    pub subject: Option<String>
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        PreviewOptions {
+            from: "preview@example.com".to_owned(),
+            to: "recipient@example.com".to_owned(),
+            subject: None
+        }
+    }
+}
+
+/// A rendered preview.
+#[derive(Debug)]
+pub struct Preview {
+    /// the id of the template used to render the preview
+    pub template_id: String,
+    /// the encoded mail
+    pub eml: String,
+    /// the rendered bodies as they are contained in the mail
+    pub bodies: Vec<PreviewBody>
+}
+
+/// A rendered body of a preview.
+#[derive(Debug, Clone)]
+pub struct PreviewBody {
+    pub media_type: MediaType,
+    pub content: String
+}
+
+impl Preview {
+
+    /// writes the preview to `out_dir` returning the paths of all written files
+    ///
+    /// The mail is written to `<template_id>.eml` and the bodies to
+    /// `<template_id>.body<idx>.<ext>` where the extension is derived
+    /// from the media type of the body (e.g. `html` for `text/html`).
+    /// Missing directories are created.
+    pub fn write_to_dir<P>(&self, out_dir: P) -> Result<Vec<PathBuf>, PreviewError>
+        where P: AsRef<Path>
+    {
+        let out_dir = out_dir.as_ref();
+        fs::create_dir_all(out_dir)?;
+
+        let mut written = Vec::new();
+        let eml_path = out_dir.join(format!("{}.eml", self.template_id));
+        fs::write(&eml_path, &self.eml)?;
+        written.push(eml_path);
+
+        for (idx, body) in self.bodies.iter().enumerate() {
+            let file_name = format!("{}.body{}.{}",
+                self.template_id, idx, file_extension_for(&body.media_type));
+            let path = out_dir.join(file_name);
+            fs::write(&path, &body.content)?;
+            written.push(path);
+        }
+
+        Ok(written)
+    }
+}
+
+/// renders a preview of the template associated with `template_id`
+pub fn render_preview<R, C>(
+    engine: &RenderTemplateEngine<R>,
+    template_id: &str,
+    data: PreviewData,
+    ctx: &C,
+    options: &PreviewOptions
+) -> Result<Preview, PreviewError>
+    where R: RenderEngine<PreviewData>, C: Context
+{
+    let from = parse_email(&options.from)?;
+    let to = parse_email(&options.to)?;
+
+    // the same as `render_mail`, but the bodies are inspected before they become `MailParts`
+    let headers = engine.render_headers(template_id, &data)
+        .map_err(|err| err.context(PreviewErrorKind::Rendering))?;
+    let rendered = engine.render_bodies(template_id, &data, ctx)
+        .map_err(|err| err.context(PreviewErrorKind::Rendering))?;
+    let bodies = rendered.bodies.iter()
+        .map(|body| PreviewBody {
+            media_type: body.media_type.clone(),
+            content: body.content.clone()
+        })
+        .collect();
+    let mut rendered = rendered.into_rendered_mail(headers);
+
+    let subject = options.subject.clone()
+        .or_else(|| rendered.subject.take())
+        .unwrap_or_else(|| format!("Preview of {}", template_id));
+
+    let prepared: PreparedEngine<R> = PreparedEngine {
+        engine: PhantomData,
+        parts: Mutex::new(Some(rendered.parts))
+    };
+    let send_data = MailSendData::simple_new(
+        from.into(), to.into(), &*subject,
+        Cow::Borrowed(template_id), data
+    );
+
+    let mail = send_data.compose(ctx, &prepared)
+        .map_err(|err| err.context(PreviewErrorKind::Composition))?;
+
+    let encodable_mail = mail.into_encodeable_mail(ctx.clone())
+        .wait()
+        .map_err(|err| err.context(PreviewErrorKind::Encoding))?;
+
+    let mut encoder = EncodingBuffer::new(MailType::Ascii);
+    encodable_mail.encode(&mut encoder)
+        .map_err(|err| err.context(PreviewErrorKind::Encoding))?;
+    let eml = encoder.to_string()
+        .map_err(|err| err.context(PreviewErrorKind::Encoding))?;
+    let eml = insert_missing_headers(&eml, &rendered.headers);
+
+    Ok(Preview {
+        template_id: template_id.to_owned(),
+        eml,
+        bodies
+    })
+}
+
+fn parse_email(address: &str) -> Result<Email, PreviewError> {
+    Email::try_from(address)
+        .map_err(|err| {
+            let kind = PreviewErrorKind::InvalidAddress { address: address.to_owned() };
+            err.context(kind).into()
+        })
+}
+
+fn file_extension_for(media_type: &MediaType) -> String {
+    let full_type = media_type.full_type().to_string();
+    let subtype = full_type.splitn(2, '/').nth(1).unwrap_or("bin");
+    match subtype {
+        "plain" => "txt".to_owned(),
+        "markdown" => "md".to_owned(),
+        other => other.split('+').next().unwrap_or(other).to_owned()
+    }
+}
+
+/// adds the headers to the header section of the encoded mail
+///
+/// Headers the mail already has (e.g. `Subject`) are skipped, values
+/// which are not us-ascii are encoded as encoded-words (RFC 2047).
+fn insert_missing_headers(eml: &str, headers: &[(String, String)]) -> String {
+    let header_end = eml.find("\r\n\r\n").map(|idx| idx + 2).unwrap_or(eml.len());
+    let (header_section, rest) = eml.split_at(header_end);
+
+    let mut out = String::with_capacity(eml.len());
+    out.push_str(header_section);
+    for &(ref name, ref value) in headers {
+        let already_set = header_section.split("\r\n")
+            .filter_map(|line| line.find(':').map(|idx| line[..idx].trim()))
+            .any(|existing| existing.eq_ignore_ascii_case(name));
+        if already_set {
+            continue;
+        }
+        out.push_str(name);
+        out.push_str(": ");
+        if value.is_ascii() {
+            out.push_str(value);
+        } else {
+            out.push_str(&encoded_word(value));
+        }
+        out.push_str("\r\n");
+    }
+    out.push_str(rest);
+    out
+}
+
+/// encodes the value as a single utf-8 `Q` encoded-word
+fn encoded_word(value: &str) -> String {
+    let mut out = String::from("=?utf-8?Q?");
+    for bch in value.bytes() {
+        match bch {
+            b' ' => out.push('_'),
+            b'!' | b'*' | b'+' | b'-' | b'/' => out.push(bch as char),
+            _ if bch.is_ascii_alphanumeric() => out.push(bch as char),
+            _ => out.push_str(&format!("={:02X}", bch))
+        }
+    }
+    out.push_str("?=");
+    out
+}
+
+/// template engine returning the already rendered parts of the preview
+struct PreparedEngine<R> {
+    engine: PhantomData<R>,
+    parts: Mutex<Option<MailParts>>
+}
+
+impl<C, R> TemplateEngine<C, PreviewData> for PreparedEngine<R>
+    where C: Context, R: RenderEngine<PreviewData>
+{
+    type TemplateId = str;
+    type Error = R::RenderError;
+
+    fn use_template(
+        &self,
+        _template_id: &str,
+        _data: &PreviewData,
+        _ctx: &C
+    ) -> Result<MailParts, Self::Error> {
+        let parts = self.parts.lock()
+            .expect("[BUG] panic while holding the lock")
+            .take()
+            .expect("[BUG] composing a preview uses the template once");
+        Ok(parts)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::env;
+    use headers::components::MediaType;
+    use super::{Preview, PreviewBody, PreviewData, insert_missing_headers};
+
+    #[test]
+    fn write_preview_to_dir() {
+        let out_dir = env::temp_dir().join(format!("mail-rte-preview-{}", ::std::process::id()));
+        let preview = Preview {
+            template_id: "welcome".to_owned(),
+            eml: "Subject: hy\r\n\r\n...".to_owned(),
+            bodies: vec![
+                PreviewBody {
+                    media_type: MediaType::parse("text/plain; charset=utf-8").unwrap(),
+                    content: "hy".to_owned()
+                },
+                PreviewBody {
+                    media_type: MediaType::parse("text/html; charset=utf-8").unwrap(),
+                    content: "<p>hy</p>".to_owned()
+                }
+            ]
+        };
+
+        let written = preview.write_to_dir(&out_dir).unwrap();
+        assert_eq!(written, &[
+            out_dir.join("welcome.eml"),
+            out_dir.join("welcome.body0.txt"),
+            out_dir.join("welcome.body1.html")
+        ]);
+        assert_eq!(fs::read_to_string(&written[2]).unwrap(), "<p>hy</p>");
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn rendered_headers_are_added_to_the_eml() {
+        let eml = "Subject: hy\r\nFrom: a@b.example\r\n\r\nbody\r\n";
+        let headers = vec![
+            ("subject".to_owned(), "ignored".to_owned()),
+            ("List-Unsubscribe".to_owned(), "<https://example.com/u>".to_owned()),
+            ("X-Greeting".to_owned(), "Grüße".to_owned())
+        ];
+        assert_eq!(insert_missing_headers(eml, &headers), concat!(
+            "Subject: hy\r\nFrom: a@b.example\r\n",
+            "List-Unsubscribe: <https://example.com/u>\r\n",
+            "X-Greeting: =?utf-8?Q?Gr=C3=BC=C3=9Fe?=\r\n",
+            "\r\nbody\r\n"
+        ));
+    }
+
+    #[test]
+    fn preview_data_from_json() {
+        let data = PreviewData::from_json_str(r#"{"name": "Liz"}"#).unwrap();
+        assert_eq!(data.0["name"], "Liz");
+        assert!(PreviewData::from_json_str("{name").is_err());
+    }
+}
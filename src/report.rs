@@ -0,0 +1,117 @@
+use std::fmt::{self, Display};
+
+use failure::Fail;
+
+use ::error::{CreatingSpecError, InsertionError, DisplayPath};
+use ::spec::TemplateSpec;
+
+/// The result of creating specs without stopping at the first failure.
+///
+/// Created by `TemplateSpec::from_dirs_with_report`.
+#[derive(Debug, Default)]
+pub struct CreationReport {
+    /// the successfully created specs with their ids
+    pub specs: Vec<(String, TemplateSpec)>,
+    /// the template dirs from which no spec could be created
+    pub failures: Vec<SpecFailure>
+}
+
+impl CreationReport {
+
+    pub fn has_failures(&self) -> bool {
+        !self.failures.is_empty()
+    }
+
+    /// returns the specs if there where no failures else the first failure
+    pub fn into_result(self) -> Result<Vec<(String, TemplateSpec)>, CreatingSpecError> {
+        match self.failures.into_iter().next() {
+            Some(failure) => Err(failure.error),
+            None => Ok(self.specs)
+        }
+    }
+}
+
+impl Display for CreationReport {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fter, "created {} spec(s), {} failure(s)", self.specs.len(), self.failures.len())?;
+        for failure in self.failures.iter() {
+            writeln!(fter, "{}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// A template dir from which no spec could be created.
+#[derive(Debug)]
+pub struct SpecFailure {
+    pub dir: DisplayPath,
+    pub error: CreatingSpecError
+}
+
+impl Display for SpecFailure {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        write!(fter, "{}: ", self.dir)?;
+        write_with_causes(fter, &self.error)
+    }
+}
+
+/// The result of loading specs without stopping at the first failure.
+///
+/// Created by `RenderTemplateEngine::load_templates_with_report`.
+#[derive(Debug)]
+pub struct LoadingReport<E: Fail> {
+    /// the ids of all specs which where inserted
+    pub loaded: Vec<String>,
+    /// the template dirs from which no spec could be created
+    pub creation_failures: Vec<SpecFailure>,
+    /// the specs which where created but could not be inserted
+    pub insertion_failures: Vec<(String, InsertionError<E>)>
+}
+
+impl<E> LoadingReport<E>
+    where E: Fail
+{
+    pub fn has_failures(&self) -> bool {
+        !self.creation_failures.is_empty() || !self.insertion_failures.is_empty()
+    }
+}
+
+impl<E> Default for LoadingReport<E>
+    where E: Fail
+{
+    fn default() -> Self {
+        LoadingReport {
+            loaded: Vec::new(),
+            creation_failures: Vec::new(),
+            insertion_failures: Vec::new()
+        }
+    }
+}
+
+impl<E> Display for LoadingReport<E>
+    where E: Fail
+{
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fter, "loaded {} spec(s), {} failure(s)",
+            self.loaded.len(), self.creation_failures.len() + self.insertion_failures.len())?;
+        for failure in self.creation_failures.iter() {
+            writeln!(fter, "{}", failure)?;
+        }
+        for &(ref id, ref error) in self.insertion_failures.iter() {
+            write!(fter, "{}: ", id)?;
+            write_with_causes(fter, error)?;
+            writeln!(fter)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_with_causes(fter: &mut fmt::Formatter, error: &Fail) -> fmt::Result {
+    write!(fter, "{}", error)?;
+    let mut cause = error.cause();
+    while let Some(error) = cause {
+        write!(fter, "\n    caused by: {}", error)?;
+        cause = error.cause();
+    }
+    Ok(())
+}
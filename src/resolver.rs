@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Arc;
+use std::mem::replace;
+
+use failure::Fail;
+
+use headers::components::MediaType;
+
+use ::error::{CreatingSpecError, CreatingSpecErrorVariant};
+use ::compat::{Compatible, MediaTypeCompatibility, MismatchPolicy};
+use ::utils::{file_extension, media_type_str_by_extension, sniff_content};
+
+/// Trait used by `LoadSpecSettings` to determine the media type of embeddings and attachments.
+///
+/// The crate provides following implementations:
+///
+/// - `ExtensionResolver`: uses only the file extension
+/// - `ContentResolver`: uses only the file content
+/// - `StrictResolver`: uses both and fails if they are incompatible (the default)
+/// - `MappingResolver`: uses a custom `extension => media type` table
+///
+pub trait MediaTypeResolver: Debug + Send + Sync {
+
+    /// returns the media type for the file at the given path
+    fn resolve_media_type(&self, path: &Path) -> Result<MediaType, CreatingSpecError>;
+
+    /// true if the `file` command is used for files with an unknown content format
+    fn uses_file_cmd_fallback(&self) -> bool {
+        false
+    }
+}
+
+/// Resolves media types only based on the file extension.
+///
+/// This is fast and lenient, but it does not notice files with
+/// a wrong extension and it can not detect the charset of text
+/// files.
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionResolver;
+
+impl ExtensionResolver {
+    pub fn new() -> Self {
+        ExtensionResolver
+    }
+}
+
+impl MediaTypeResolver for ExtensionResolver {
+    fn resolve_media_type(&self, path: &Path) -> Result<MediaType, CreatingSpecError> {
+        let media_type = media_type_str_by_extension(path)?;
+        Ok(parse_media_type(media_type)?)
+    }
+}
+
+/// Resolves media types only based on the file content.
+///
+/// If the content has a unknown format the media type
+/// `application/octet-stream` is used, except if the
+/// `file` command fallback is enabled.
+#[derive(Debug, Clone, Default)]
+pub struct ContentResolver {
+    file_cmd_fallback: bool
+}
+
+impl ContentResolver {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// use the `file` command (`file -b --mime`) for files with an unknown content format
+    pub fn with_file_cmd_fallback() -> Self {
+        ContentResolver { file_cmd_fallback: true }
+    }
+
+    pub fn uses_file_cmd_fallback(&self) -> bool {
+        self.file_cmd_fallback
+    }
+}
+
+impl MediaTypeResolver for ContentResolver {
+    fn resolve_media_type(&self, path: &Path) -> Result<MediaType, CreatingSpecError> {
+        match sniff_content(path, self.file_cmd_fallback)? {
+            Some(media_type) => Ok(media_type),
+            None => parse_media_type("application/octet-stream; charset=binary")
+        }
+    }
+
+    fn uses_file_cmd_fallback(&self) -> bool {
+        self.file_cmd_fallback
+    }
+}
+
+/// Resolves media types based on the file extension and the file content.
+///
+/// If both differ but are compatible wrt. the `MediaTypeCompatibility` table
+/// (e.g. `text/plain` and `text/x-tex`) the more specific one is used and a
+/// warning is logged. If they are incompatible the `MismatchPolicy` decides
+/// if a error is returned (the default, which makes this the right choice to
+/// catch mistakes e.g. in CI) or which of both is used.
+///
+/// If the content has a unknown format (and the `file` command fallback is
+/// disabled) only the file extension is used.
+///
+/// This is the resolver used by default.
+#[derive(Debug, Clone, Default)]
+pub struct StrictResolver {
+    file_cmd_fallback: bool,
+    compatibility: MediaTypeCompatibility,
+    mismatch_policy: MismatchPolicy
+}
+
+impl StrictResolver {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// use the `file` command (`file -b --mime`) for files with an unknown content format
+    pub fn with_file_cmd_fallback() -> Self {
+        StrictResolver { file_cmd_fallback: true, ..Default::default() }
+    }
+
+    pub fn uses_file_cmd_fallback(&self) -> bool {
+        self.file_cmd_fallback
+    }
+
+    pub fn set_file_cmd_fallback(&mut self, use_file_cmd: bool) {
+        self.file_cmd_fallback = use_file_cmd;
+    }
+
+    pub fn compatibility(&self) -> &MediaTypeCompatibility {
+        &self.compatibility
+    }
+
+    pub fn compatibility_mut(&mut self) -> &mut MediaTypeCompatibility {
+        &mut self.compatibility
+    }
+
+    pub fn set_compatibility(&mut self, compatibility: MediaTypeCompatibility) -> MediaTypeCompatibility {
+        replace(&mut self.compatibility, compatibility)
+    }
+
+    pub fn mismatch_policy(&self) -> MismatchPolicy {
+        self.mismatch_policy
+    }
+
+    pub fn set_mismatch_policy(&mut self, policy: MismatchPolicy) -> MismatchPolicy {
+        replace(&mut self.mismatch_policy, policy)
+    }
+}
+
+impl MediaTypeResolver for StrictResolver {
+    fn resolve_media_type(&self, path: &Path) -> Result<MediaType, CreatingSpecError> {
+        let by_extension = media_type_str_by_extension(path)?;
+        let by_content = match sniff_content(path, self.file_cmd_fallback)? {
+            Some(media_type) => media_type,
+            // the content gives no hint, so there is nothing to compare with
+            None => return parse_media_type(by_extension)
+        };
+        let content_type = by_content.full_type().to_string();
+
+        if content_type == by_extension {
+            return Ok(by_content);
+        }
+
+        let chosen = match self.compatibility.reconcile(by_extension, &content_type) {
+            Compatible::Same(_) => return Ok(by_content),
+            Compatible::Right(_) => {
+                warn!("using {:?} detected from the content instead of {:?} implied by the extension of {}",
+                    content_type, by_extension, path.display());
+                return Ok(by_content);
+            },
+            Compatible::Left(media_type) => {
+                warn!("using {:?} implied by the extension instead of {:?} detected from the content of {}",
+                    media_type, content_type, path.display());
+                media_type
+            },
+            Compatible::Incompatible => match self.mismatch_policy {
+                MismatchPolicy::Error => {
+                    return Err(CreatingSpecErrorVariant::FileStemAndContentDifferInMediaType {
+                        path: path.into(),
+                        by_extension: by_extension.to_owned(),
+                        by_content: content_type
+                    }.into());
+                },
+                MismatchPolicy::PreferContent => {
+                    warn!("media type implied by the extension ({:?}) and content ({:?}) differ, using the latter for {}",
+                        by_extension, content_type, path.display());
+                    return Ok(by_content);
+                },
+                MismatchPolicy::PreferExtension => {
+                    warn!("media type implied by the extension ({:?}) and content ({:?}) differ, using the former for {}",
+                        by_extension, content_type, path.display());
+                    by_extension.to_owned()
+                }
+            }
+        };
+
+        // keep the parameters (i.e. the charset) detected from the content
+        let content_repr = by_content.as_str_repr();
+        let params = content_repr.find(';')
+            .map(|idx| &content_repr[idx..])
+            .unwrap_or("");
+        parse_media_type(&format!("{}{}", chosen, params))
+    }
+
+    fn uses_file_cmd_fallback(&self) -> bool {
+        self.file_cmd_fallback
+    }
+}
+
+/// Resolves media types through a custom `extension => media type` table.
+///
+/// Extensions are matched case insensitive and without the leading ".".
+/// If a file has an extension not in the table the fallback resolver is
+/// used, or if there is none a `NoMediaTypeFor` error is returned.
+#[derive(Debug, Clone, Default)]
+pub struct MappingResolver {
+    mapping: HashMap<String, MediaType>,
+    fallback: Option<Arc<MediaTypeResolver>>
+}
+
+impl MappingResolver {
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// create a new `MappingResolver` using `fallback` for unknown extensions
+    pub fn with_fallback<R>(fallback: R) -> Self
+        where R: MediaTypeResolver + 'static
+    {
+        MappingResolver {
+            mapping: HashMap::new(),
+            fallback: Some(Arc::new(fallback))
+        }
+    }
+
+    /// adds a mapping returning the media type previously associated with the extension
+    pub fn insert<E>(&mut self, extension: E, media_type: MediaType) -> Option<MediaType>
+        where E: AsRef<str>
+    {
+        self.mapping.insert(normalize_extension(extension.as_ref()), media_type)
+    }
+
+    pub fn remove(&mut self, extension: &str) -> Option<MediaType> {
+        self.mapping.remove(&normalize_extension(extension))
+    }
+
+    pub fn get(&self, extension: &str) -> Option<&MediaType> {
+        self.mapping.get(&normalize_extension(extension))
+    }
+}
+
+impl MediaTypeResolver for MappingResolver {
+    fn resolve_media_type(&self, path: &Path) -> Result<MediaType, CreatingSpecError> {
+        let extension = file_extension(path)?;
+        if let Some(media_type) = self.get(extension) {
+            Ok(media_type.clone())
+        } else if let Some(fallback) = self.fallback.as_ref() {
+            fallback.resolve_media_type(path)
+        } else {
+            Err(CreatingSpecErrorVariant::NoMediaTypeFor { stem: extension.to_owned() }.into())
+        }
+    }
+
+    fn uses_file_cmd_fallback(&self) -> bool {
+        self.fallback.as_ref()
+            .map(|fallback| fallback.uses_file_cmd_fallback())
+            .unwrap_or(false)
+    }
+}
+
+fn normalize_extension(extension: &str) -> String {
+    let extension =
+        if extension.starts_with(".") { &extension[1..] } else { extension };
+    extension.to_lowercase()
+}
+
+fn parse_media_type(media_type: &str) -> Result<MediaType, CreatingSpecError> {
+    MediaType::parse(media_type)
+        .map_err(|err| err.context(CreatingSpecErrorVariant::NotAMediaType).into())
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use headers::components::MediaType;
+    use ::error::CreatingSpecErrorVariant;
+    use ::compat::{MediaTypeCompatibility, MismatchPolicy};
+    use super::*;
+
+    #[test]
+    fn extension_resolver_ignores_content() {
+        let mt = ExtensionResolver.resolve_media_type(Path::new("./test_resources/jpg_image.png")).unwrap();
+        assert_eq!(mt.as_str_repr(), "image/png");
+    }
+
+    #[test]
+    fn content_resolver_ignores_extension() {
+        let mt = ContentResolver::new().resolve_media_type(Path::new("./test_resources/jpg_image.png")).unwrap();
+        assert_eq!(mt.as_str_repr(), "image/jpeg; charset=binary");
+    }
+
+    #[test]
+    fn strict_resolver_rejects_mismatches() {
+        let err = StrictResolver::new()
+            .resolve_media_type(Path::new("./test_resources/jpg_image.png"))
+            .unwrap_err();
+
+        match *err.variant() {
+            CreatingSpecErrorVariant::FileStemAndContentDifferInMediaType { .. } => {},
+            _ => panic!("unexpected error: {}", err)
+        }
+    }
+
+    #[test]
+    fn strict_resolver_rejects_html_content_in_text_files() {
+        let err = StrictResolver::new()
+            .resolve_media_type(Path::new("./test_resources/html_content.txt"))
+            .unwrap_err();
+
+        match *err.variant() {
+            CreatingSpecErrorVariant::FileStemAndContentDifferInMediaType { ref by_content, .. } => {
+                assert_eq!(by_content, "text/html");
+            },
+            _ => panic!("unexpected error: {}", err)
+        }
+    }
+
+    #[test]
+    fn strict_resolver_can_prefer_one_of_both() {
+        let path = Path::new("./test_resources/jpg_image.png");
+        let mut resolver = StrictResolver::new();
+
+        resolver.set_mismatch_policy(MismatchPolicy::PreferContent);
+        let mt = resolver.resolve_media_type(path).unwrap();
+        assert_eq!(mt.as_str_repr(), "image/jpeg; charset=binary");
+
+        resolver.set_mismatch_policy(MismatchPolicy::PreferExtension);
+        let mt = resolver.resolve_media_type(path).unwrap();
+        assert_eq!(mt.as_str_repr(), "image/png; charset=binary");
+    }
+
+    #[test]
+    fn strict_resolver_accepts_compatible_types() {
+        let path = Path::new("./test_resources/csv_data.csv");
+        let mt = StrictResolver::new().resolve_media_type(path).unwrap();
+        assert_eq!(mt.as_str_repr(), "text/csv; charset=us-ascii");
+
+        let mut resolver = StrictResolver::new();
+        resolver.set_compatibility(MediaTypeCompatibility::exact());
+        assert!(resolver.resolve_media_type(path).is_err());
+    }
+
+    #[test]
+    fn mapping_resolver_uses_mapping_and_fallback() {
+        let mut resolver = MappingResolver::with_fallback(ExtensionResolver);
+        resolver.insert(".PNG", MediaType::parse("image/x-custom").unwrap());
+
+        let mt = resolver.resolve_media_type(Path::new("./test_resources/png_image.png")).unwrap();
+        assert_eq!(mt.as_str_repr(), "image/x-custom");
+
+        let mt = resolver.resolve_media_type(Path::new("./test_resources/simple.pdf")).unwrap();
+        assert_eq!(mt.as_str_repr(), "application/pdf");
+    }
+
+    #[test]
+    fn mapping_resolver_without_fallback_fails_for_unknown_extensions() {
+        let resolver = MappingResolver::new();
+        let err = resolver.resolve_media_type(Path::new("./test_resources/simple.pdf")).unwrap_err();
+
+        if let &CreatingSpecErrorVariant::NoMediaTypeFor { ref stem } = err.variant() {
+            assert_eq!(stem, "pdf");
+        } else {
+            panic!("unexpected error: {}", err);
+        }
+    }
+}
@@ -1,28 +1,51 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::mem::replace;
+use std::sync::Arc;
+
+use vec1::Vec1;
+use failure::{Fail, Error};
+use futures::{future, Future};
 
 use mail::{Resource, Context};
 use mail::file_buffer::FileBuffer;
 
+use headers::components::MediaType;
+use media_type::CHARSET;
+
 use template::TemplateEngine;
 use template::{
     EmbeddedWithCId,
     BodyPart, MailParts
 };
 
-use ::error::{LoadingError, InsertionError};
-use ::utils::fix_newlines;
+use ::error::{LoadingError, InsertionError, CreatingSpecError, UseTemplateError, ProcessingError};
+use ::report::{CreationReport, LoadingReport};
+use ::utils::{fix_newlines, has_full_type, MAX_LINE_LENGTH};
+use ::header_lines::is_valid_header_value;
+use ::html2text::html_to_text;
+use ::links::{LinkRewriter, RewrittenLink};
+use ::line_length::{LineLengthPolicy, find_too_long_line, wrap_html, wrap_text};
+use ::flowed::{encode_flowed, with_format_flowed};
+use ::post_process::{PostProcessor, PostProcessContext, PostProcessors, InlineCss};
+use ::locale::{Locale, CONTENT_LANGUAGE, localized_template_id, split_localized_template_id};
 use ::spec::TemplateSpec;
+use ::cache::{ResourceCache, SpecResources};
 use ::traits::{RenderEngine, RenderEngineBase, AdditionalCIds};
 use ::settings::LoadSpecSettings;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RenderTemplateEngine<R>
     where R: RenderEngineBase
 {
     fix_newlines: bool,
+    line_length_policy: LineLengthPolicy,
+    inline_css: bool,
+    post_processors: PostProcessors,
     render_engine: R,
     id2spec: HashMap<String, TemplateSpec>,
+    resource_cache: ResourceCache,
+    fallback_locales: Vec<Locale>,
 }
 
 
@@ -34,7 +57,12 @@ impl<R> RenderTemplateEngine<R>
         RenderTemplateEngine {
             render_engine,
             id2spec: Default::default(),
+            resource_cache: Default::default(),
             fix_newlines: !R::PRODUCES_VALID_NEWLINES,
+            line_length_policy: LineLengthPolicy::Ignore,
+            inline_css: false,
+            post_processors: Default::default(),
+            fallback_locales: Vec::new(),
         }
     }
 
@@ -46,6 +74,92 @@ impl<R> RenderTemplateEngine<R>
         self.fix_newlines
     }
 
+    /// sets what happens if a rendered body has lines longer than `MAX_LINE_LENGTH`
+    ///
+    /// The line length is checked after newlines are fixed (if enabled),
+    /// by default over-long lines are ignored.
+    pub fn set_line_length_policy(&mut self, policy: LineLengthPolicy) {
+        self.line_length_policy = policy
+    }
+
+    pub fn line_length_policy(&self) -> LineLengthPolicy {
+        self.line_length_policy
+    }
+
+    /// if enabled css rules are inlined into the `style` attributes of `text/html` bodies
+    ///
+    /// The rules of all `<style>` blocks in the rendered body and of the
+    /// stylesheet of the template (see `TemplateSpec::stylesheet`) are
+    /// applied to the matching elements, as many mail clients ignore
+    /// `<style>` blocks. Rules which can not be inlined are kept in a
+    /// `<style>` block. This is disabled by default.
+    pub fn set_inline_css(&mut self, should_inline_css: bool) {
+        self.inline_css = should_inline_css
+    }
+
+    pub fn does_inline_css(&self) -> bool {
+        self.inline_css
+    }
+
+    /// adds a post-processor for rendered bodies of the given media type
+    ///
+    /// The media type is given without parameters (e.g. `text/html`).
+    /// Post-processors are run in the order they were added, after the
+    /// built-in processing (see `set_inline_css`) and before newlines
+    /// are fixed and the `FileBuffer` for the body is created.
+    pub fn add_post_processor<P>(&mut self, media_type: &str, processor: P)
+        where P: PostProcessor + 'static
+    {
+        self.post_processors.add(media_type, Arc::new(processor))
+    }
+
+    /// removes all post-processors for the given media type, returns how many were removed
+    pub fn remove_post_processors(&mut self, media_type: &str) -> usize {
+        self.post_processors.remove(media_type)
+    }
+
+    pub fn render_engine(&self) -> &R {
+        &self.render_engine
+    }
+
+    /// creates a engine with the settings and specs of this engine but the given render engine
+    ///
+    /// The templates of all specs are loaded into `render_engine`, so it should
+    /// not already contain them. This makes it possible to create a new version
+    /// of the engine if the render engine is not `Clone`.
+    ///
+    /// # Error
+    ///
+    /// If the templates of a spec can not be loaded the error of inserting
+    /// it (see `insert_spec`) is returned.
+    pub fn with_render_engine(&self, render_engine: R)
+        -> Result<Self, InsertionError<R::LoadingError>>
+    {
+        let mut engine = RenderTemplateEngine {
+            render_engine,
+            id2spec: HashMap::with_capacity(self.id2spec.len()),
+            resource_cache: Default::default(),
+            fix_newlines: self.fix_newlines,
+            line_length_policy: self.line_length_policy,
+            inline_css: self.inline_css,
+            post_processors: self.post_processors.clone(),
+            fallback_locales: self.fallback_locales.clone(),
+        };
+        for (id, spec) in self.id2spec.iter() {
+            engine.insert_spec(id.clone(), spec.clone())?;
+        }
+        Ok(engine)
+    }
+
+    /// the locales tried (in order) if a template has no variant for the requested locale
+    pub fn fallback_locales(&self) -> &[Locale] {
+        &self.fallback_locales
+    }
+
+    pub fn set_fallback_locales(&mut self, locales: Vec<Locale>) -> Vec<Locale> {
+        replace(&mut self.fallback_locales, locales)
+    }
+
     /// add a `TemplateSpec`, loading all templates in it
     ///
     /// If a template with the same name is contained it
@@ -71,6 +185,7 @@ impl<R> RenderTemplateEngine<R>
         spec: TemplateSpec
     ) -> Result<Option<TemplateSpec>, InsertionError<R::LoadingError>> {
         use std::collections::hash_map::Entry::*;
+        self.resource_cache.invalidate(&id);
         match self.id2spec.entry(id) {
             Occupied(mut entry) => {
                 let old = entry.insert(spec);
@@ -107,6 +222,7 @@ impl<R> RenderTemplateEngine<R>
     /// (and `None` is returned).
     pub fn remove_spec(&mut self, id: &str) -> Option<TemplateSpec> {
         let res =  self.id2spec.remove(id);
+        self.resource_cache.invalidate(id);
         if let Some(spec) = res.as_ref() {
             self.render_engine.unload_templates(spec);
         }
@@ -117,7 +233,12 @@ impl<R> RenderTemplateEngine<R>
         &self.id2spec
     }
 
+    /// allows changing the specs in place
+    ///
+    /// As the specs (e.g. their embeddings) might be changed this clears
+    /// the resource cache.
     pub fn specs_mut(&mut self) -> impl Iterator<Item=(&String, &mut TemplateSpec)> {
+        self.resource_cache.clear();
         self.id2spec.iter_mut()
     }
 
@@ -125,6 +246,17 @@ impl<R> RenderTemplateEngine<R>
         self.id2spec.get(template_id)
     }
 
+    /// drops all cached resources
+    ///
+    /// The resources (embeddings and attachments) of a spec are cached on the
+    /// first render and loaded once by `use_template_future`, all later renders
+    /// reuse them until the spec is replaced or removed. So, if e.g. an embedded
+    /// file changed without the spec being reloaded, this has to be called to
+    /// pick up the change.
+    pub fn clear_resource_cache(&self) {
+        self.resource_cache.clear()
+    }
+
     /// each folder in `templates_dir` is seen as a TemplateSpec
     ///
     /// # Error
@@ -132,7 +264,7 @@ impl<R> RenderTemplateEngine<R>
     /// If an error can occur when creating the spec(s), or when inserting/using
     /// them. If such an error occurs all previously added Spec are not removed,
     /// i.e. if an error happens some spec and embeddings might be added others
-    /// might not.
+    /// might not. Use `load_templates_atomic` to avoid this.
     pub fn load_templates(
         &mut self,
         templates_dir: impl AsRef<Path>,
@@ -143,72 +275,783 @@ impl<R> RenderTemplateEngine<R>
         }
         Ok(())
     }
+
+    /// like `load_templates` but does not stop at the first failure
+    ///
+    /// Every spec which can be created and inserted is inserted, all others
+    /// are listed in the returned report together with their error.
+    ///
+    /// # Error
+    ///
+    /// Only if the `templates_dir` itself can not be read an error is returned.
+    pub fn load_templates_with_report(
+        &mut self,
+        templates_dir: impl AsRef<Path>,
+        settings: &LoadSpecSettings
+    ) -> Result<LoadingReport<R::LoadingError>, CreatingSpecError> {
+        let CreationReport { specs, failures } =
+            TemplateSpec::from_dirs_with_report(templates_dir.as_ref(), settings)?;
+
+        let mut report = LoadingReport::default();
+        report.creation_failures = failures;
+        for (id, spec) in specs {
+            match self.insert_spec(id.clone(), spec) {
+                Ok(_) => report.loaded.push(id),
+                Err(error) => report.insertion_failures.push((id, error))
+            }
+        }
+        Ok(report)
+    }
+
+    /// like `load_templates` but all-or-nothing
+    ///
+    /// First all specs are created, if this fails the engine is not changed
+    /// at all. Then they are inserted using `insert_specs_atomic`, i.e. if
+    /// any of them can not be loaded all changes are rolled back.
+    pub fn load_templates_atomic(
+        &mut self,
+        templates_dir: impl AsRef<Path>,
+        settings: &LoadSpecSettings
+    ) -> Result<(), LoadingError<R::LoadingError>> {
+        let specs = TemplateSpec::from_dirs(templates_dir.as_ref(), settings)?;
+        self.insert_specs_atomic(specs)?;
+        Ok(())
+    }
+
+    /// inserts all specs or none of them
+    ///
+    /// Returns the specs replaced by the new specs. If the batch contains
+    /// the same id multiple times the last spec with the id is used.
+    ///
+    /// All specs are loaded into the render engine before any of them is
+    /// inserted, so that no spec is associated with an id before the whole
+    /// batch was loaded successfully.
+    ///
+    /// # Error
+    ///
+    /// If loading any spec fails the templates of all specs of the batch are
+    /// unloaded again and `LoadingError::BatchRolledBack` is returned, the
+    /// specs associated with the engine stay unchanged.
+    ///
+    /// A replaced spec stays loaded while the new spec is loaded, except if
+    /// both use the same template ids (e.g. the same template files), as the
+    /// render engine can not hold both at once. Such specs are loaded after
+    /// all other specs of the batch and the templates of the replaced spec
+    /// are unloaded before. If the batch is rolled back after this the
+    /// replaced spec is loaded again, which re-reads its template files, if
+    /// this fails (e.g. because a template file changed in between) the spec
+    /// is removed and returned in `lost_on_rollback`.
+    pub fn insert_specs_atomic(
+        &mut self,
+        specs: impl IntoIterator<Item=(String, TemplateSpec)>
+    ) -> Result<Vec<(String, TemplateSpec)>, LoadingError<R::LoadingError>> {
+        let mut batch = Vec::<(String, TemplateSpec)>::new();
+        for (id, spec) in specs {
+            batch.retain(|&(ref other, _)| *other != id);
+            batch.push((id, spec));
+        }
+
+        // specs which can be loaded while the spec they replace stays loaded come first
+        let shares_ids = batch.iter()
+            .map(|&(ref id, ref spec)| {
+                self.id2spec.get(id)
+                    .map(|old| shares_template_ids(old, spec))
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>();
+        let mut order = (0..batch.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&idx| shares_ids[idx]);
+
+        let mut loaded = Vec::new();
+        for idx in order {
+            let result = {
+                let (ref id, ref spec) = batch[idx];
+                if shares_ids[idx] {
+                    self.render_engine.unload_templates(&self.id2spec[id]);
+                }
+                self.render_engine.load_templates(spec)
+            };
+            match result {
+                Ok(()) => loaded.push(idx),
+                Err(error) => {
+                    let lost_on_rollback = self.roll_back_batch(&batch, &loaded, &shares_ids, idx);
+                    let (id, failed_new_value) = batch.swap_remove(idx);
+                    return Err(LoadingError::BatchRolledBack {
+                        id,
+                        error: InsertionError { error, failed_new_value, old_value: None },
+                        lost_on_rollback
+                    });
+                }
+            }
+        }
+
+        let mut replaced = Vec::new();
+        for ((id, spec), shares_ids) in batch.into_iter().zip(shares_ids) {
+            self.resource_cache.invalidate(&id);
+            if let Some(old) = self.id2spec.insert(id.clone(), spec) {
+                if !shares_ids {
+                    self.render_engine.unload_templates(&old);
+                }
+                replaced.push((id, old));
+            }
+        }
+        Ok(replaced)
+    }
+
+    /// unloads the `loaded` specs of the batch and loads the specs they replaced again
+    ///
+    /// Returns the replaced specs which could not be loaded again (and were removed).
+    fn roll_back_batch(
+        &mut self,
+        batch: &[(String, TemplateSpec)],
+        loaded: &[usize],
+        shares_ids: &[bool],
+        failed: usize
+    ) -> Vec<(String, TemplateSpec)> {
+        for &idx in loaded.iter().rev() {
+            self.render_engine.unload_templates(&batch[idx].1);
+        }
+
+        let mut lost_on_rollback = Vec::new();
+        let unloaded = loaded.iter().cloned()
+            .chain(Some(failed))
+            .filter(|&idx| shares_ids[idx]);
+        for idx in unloaded {
+            let id = &batch[idx].0;
+            let res = self.render_engine.load_templates(&self.id2spec[id]);
+            if res.is_err() {
+                self.resource_cache.invalidate(id);
+                // UNWRAP_SAFE: shares_ids is only true for ids associated with a spec
+                lost_on_rollback.push((id.clone(), self.id2spec.remove(id).unwrap()));
+            }
+        }
+        lost_on_rollback
+    }
+
+    /// reloads the spec associated with given id from the dir it was loaded from
+    ///
+    /// This re-runs `TemplateSpec::from_dir` on the specs `base_path` and
+    /// then swaps the old spec with the new one, returning the old spec.
+    ///
+    /// # Error
+    ///
+    /// If no spec is associated with the id or the spec has no `base_path`
+    /// an error is returned.
+    ///
+    /// If creating the new spec fails the old spec is kept (and stays loaded).
+    ///
+    /// If loading the templates of the new spec fails the old spec is kept, but
+    /// as both normally use the same template ids its templates have been unloaded
+    /// and are loaded again. Render engines re-read template files when loading
+    /// them, so **the restored spec uses the current content of its template files**,
+    /// i.e. changed files which can be loaded are used by the restored spec, too.
+    /// If the templates of the old spec can not be loaded again (e.g. because a
+    /// file used by both specs is broken), the old spec is removed and returned
+    /// as `old_value` of the `InsertionError`.
+    pub fn reload_spec(
+        &mut self,
+        id: &str,
+        settings: &LoadSpecSettings
+    ) -> Result<TemplateSpec, LoadingError<R::LoadingError>> {
+        let base_path = {
+            let spec = self.lookup_spec(id)
+                .ok_or_else(|| LoadingError::UnknownSpecId { id: id.to_owned() })?;
+            spec.base_path()
+                .ok_or_else(|| LoadingError::NotReloadable { id: id.to_owned() })?
+                .to_owned()
+        };
+
+        let new_spec = TemplateSpec::from_dir(&base_path, settings)?;
+        Ok(self.swap_spec(id, new_spec)?)
+    }
+
+    /// reloads all specs which where loaded from a dir
+    ///
+    /// Specs without a `base_path` are skipped.
+    ///
+    /// # Error
+    ///
+    /// This stops at the first spec which can not be reloaded, specs
+    /// reloaded before stay reloaded and the failed spec is handled
+    /// like described in `reload_spec`.
+    pub fn reload_all(&mut self, settings: &LoadSpecSettings)
+        -> Result<(), LoadingError<R::LoadingError>>
+    {
+        let ids = self.id2spec.iter()
+            .filter(|&(_, spec)| spec.base_path().is_some())
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+
+        for id in ids {
+            self.reload_spec(&id, settings)?;
+        }
+        Ok(())
+    }
+
+    /// replaces the spec associated with `id`, keeping the old one if loading fails
+    ///
+    /// `id` is expected to be associated with a spec.
+    fn swap_spec(
+        &mut self,
+        id: &str,
+        new_spec: TemplateSpec
+    ) -> Result<TemplateSpec, InsertionError<R::LoadingError>> {
+        let (error, restored) = {
+            let render_engine = &mut self.render_engine;
+            let old_spec = self.id2spec.get_mut(id)
+                .expect("[BUG] swap_spec called with unknown id");
+
+            render_engine.unload_templates(old_spec);
+            match render_engine.load_templates(&new_spec) {
+                Ok(()) => {
+                    self.resource_cache.invalidate(id);
+                    return Ok(replace(old_spec, new_spec));
+                },
+                Err(error) => (error, render_engine.load_templates(old_spec).is_ok())
+            }
+        };
+
+        let old_value = if restored {
+            None
+        } else {
+            self.resource_cache.invalidate(id);
+            self.id2spec.remove(id)
+        };
+        Err(InsertionError {
+            error, failed_new_value: new_spec, old_value
+        })
+    }
 }
 
-impl<C, D, R> TemplateEngine<C, D> for RenderTemplateEngine<R>
-    where C: Context, R: RenderEngine<D>
+impl<R> RenderTemplateEngine<R>
+    where R: RenderEngineBase
 {
-    type TemplateId = str;
-    type Error = <R as RenderEngineBase>::RenderError;
-
-    fn use_template(
+    /// renders all bodies of the template associated with `template_id`
+    ///
+    /// This is what `use_template` does, except that the result is
+    /// not yet turned into `MailParts`, which makes it possible to
+    /// inspect (or further process) the rendered bodies.
+    pub fn render_bodies<C, D>(
         &self,
         template_id: &str,
         data: &D,
-        ctx: &C,
-    ) -> Result<MailParts, Self::Error >
+        ctx: &C
+    ) -> Result<RenderedBodies, UseTemplateError<R::RenderError>>
+        where C: Context, R: RenderEngine<D>
     {
         let spec = self.lookup_spec(template_id)
-            .ok_or_else(|| R::unknown_template_id_error(template_id))?;
+            .ok_or_else(|| UseTemplateError::Render(R::unknown_template_id_error(template_id)))?;
 
-        //OPTIMIZE there should be a more efficient way
-        // maybe use Rc<str> as keys? and Rc<Resource> for embeddings?
-        let shared_embeddings = spec.embeddings().iter()
+        // the resources are shared by all renders, only the content ids are new
+        let resources = self.resource_cache.get_or_create(template_id, spec).current();
+        let shared_embeddings = resources.embeddings.iter()
             .map(|(key, resource)| create_embedding(key, resource, ctx))
             .collect::<HashMap<_,_>>();
 
-        let bodies = spec.sub_specs().try_mapped_ref(|sub_spec| {
+        let mut sub_embeddings = resources.sub_embeddings.iter();
+        let bodies = spec.sub_specs().try_mapped_ref(|sub_spec| -> Result<_, UseTemplateError<R::RenderError>> {
 
-            let embeddings = sub_spec.embeddings().iter()
+            let embeddings = sub_embeddings.next()
+                .expect("[BUG] cached resources do not match the spec")
+                .iter()
                 .map(|(key, resource)| create_embedding(key, resource, ctx))
                 .collect::<HashMap<_,_>>();
 
             let rendered = {
                 let embeddings = &[&embeddings, &shared_embeddings];
                 let additional_cids = AdditionalCIds::new(embeddings);
-                self.render_engine.render(sub_spec, data, additional_cids)?
+                self.render_engine.render(sub_spec, data, additional_cids)
+                    .map_err(UseTemplateError::Render)?
             };
 
+            let rendered = self.post_process(template_id, spec, sub_spec.media_type(), rendered)?;
+
             let rendered =
-                if self.fix_newlines {
+                if let Some(width) = sub_spec.flowed_width() {
+                    // produces valid newlines
+                    encode_flowed(&rendered, width.min(MAX_LINE_LENGTH))
+                } else if self.fix_newlines {
                     fix_newlines(rendered)
                 } else {
                     rendered
                 };
 
-            let buffer = FileBuffer::new(sub_spec.media_type().clone(), rendered.into());
-            let resource = Resource::sourceless_from_buffer(buffer);
+            let (media_type, content) =
+                self.enforce_line_length(template_id, sub_spec.media_type(), rendered)?;
 
-            Ok(BodyPart {
-                resource: resource,
-                embeddings: embeddings.into_iter().map(|(_,v)| v).collect()
+            Ok(RenderedBody {
+                media_type,
+                content,
+                embeddings
             })
         })?;
 
-        let attachments = spec.attachments().iter()
+        let bodies =
+            if spec.generates_text_from_html() {
+                self.add_text_from_html(template_id, spec, bodies)?
+            } else {
+                bodies
+            };
+
+        let attachments = resources.attachments.iter()
             .map(|resource| EmbeddedWithCId::attachment(resource.clone(), ctx))
             .collect();
 
-        Ok(MailParts {
-            alternative_bodies: bodies,
+        let subject = self.render_subject_of(spec, data)?;
+        let content_language = split_localized_template_id(template_id)
+            .map(|(_, locale)| locale);
+
+        Ok(RenderedBodies {
+            bodies,
+            shared_embeddings,
+            attachments,
+            subject,
+            content_language
+        })
+    }
+
+    /// like `use_template` but returns a future resolving once all resources are loaded
+    ///
+    /// The embeddings and attachments of the template are loaded (and transfer
+    /// encoded) concurrently, then the bodies are rendered. The resources are
+    /// loaded only once per spec (version), later calls reuse the loaded
+    /// resources and only create new Content-IDs (see `clear_resource_cache`).
+    /// The `MailParts` the future resolves to only contain loaded resources.
+    ///
+    /// # Error
+    ///
+    /// If rendering fails the returned future fails with the same error
+    /// `use_template` would return. If loading any of the resources fails
+    /// it fails with `ProcessingError::ResourceLoading`.
+    pub fn use_template_future<'a, C, D>(
+        &'a self,
+        template_id: &'a str,
+        data: &'a D,
+        ctx: &C
+    ) -> impl Future<Item=MailParts, Error=UseTemplateError<R::RenderError>> + 'a
+        where C: Context, R: RenderEngine<D>
+    {
+        let ctx = ctx.clone();
+        let loading = match self.lookup_spec(template_id) {
+            Some(spec) => {
+                let resources = self.resource_cache.get_or_create(template_id, spec);
+                let load_ctx = ctx.clone();
+                let loading = SpecResources::load(&resources, move |resource| {
+                    resource.create_loading_future(load_ctx.clone())
+                })
+                    .map_err(move |error| ProcessingError::ResourceLoading {
+                        template_id: template_id.to_owned(),
+                        error
+                    }.into());
+                future::Either::A(loading)
+            },
+            // rendering fails with the error for unknown template ids
+            None => future::Either::B(future::ok(()))
+        };
+
+        loading.and_then(move |()| {
+            self.render_bodies(template_id, data, &ctx)
+                .map(RenderedBodies::into_mail_parts)
+        })
+    }
+
+    /// post-processes a rendered body before newlines are fixed and the `FileBuffer` is created
+    ///
+    /// This runs the built-in processing followed by the registered post-processors.
+    fn post_process(
+        &self,
+        template_id: &str,
+        spec: &TemplateSpec,
+        media_type: &MediaType,
+        rendered: String
+    ) -> Result<String, UseTemplateError<R::RenderError>>
+    {
+        let ctx = PostProcessContext { template_id, spec, media_type };
+        let mut rendered = rendered;
+        if self.inline_css && has_full_type(media_type, "text/html") {
+            rendered = InlineCss.process(rendered, &ctx)
+                .map_err(|error| post_processing_error(&ctx, error))?;
+        }
+        for processor in self.post_processors.for_media_type(media_type) {
+            rendered = processor.process(rendered, &ctx)
+                .map_err(|error| post_processing_error(&ctx, error))?;
+        }
+        Ok(rendered)
+    }
+
+    /// applies the line length policy to a rendered body
+    ///
+    /// Returns the media type to use for the body, which differs from the
+    /// given one if a `text/plain` body was wrapped using `format=flowed`.
+    fn enforce_line_length(
+        &self,
+        template_id: &str,
+        media_type: &MediaType,
+        rendered: String
+    ) -> Result<(MediaType, String), UseTemplateError<R::RenderError>>
+    {
+        let (line, length) = match find_too_long_line(&rendered) {
+            Some(too_long) => too_long,
+            None => return Ok((media_type.clone(), rendered))
+        };
+
+        match self.line_length_policy {
+            LineLengthPolicy::Ignore => Ok((media_type.clone(), rendered)),
+            LineLengthPolicy::Error => Err(ProcessingError::LineTooLong {
+                template_id: template_id.to_owned(),
+                media_type: media_type.full_type().to_string(),
+                line, length
+            }.into()),
+            LineLengthPolicy::Wrap => {
+                if has_full_type(media_type, "text/html") {
+                    Ok((media_type.clone(), wrap_html(&rendered)))
+                } else if has_full_type(media_type, "text/plain") {
+                    Ok((with_format_flowed(media_type), wrap_text(&rendered)))
+                } else {
+                    Ok((media_type.clone(), rendered))
+                }
+            }
+        }
+    }
+
+    /// inserts a `text/plain` body generated from the (highest priority) `text/html` body
+    ///
+    /// The generated body is post-processed like a rendered `text/plain` body.
+    /// If there is no `text/html` body the bodies are returned unchanged.
+    fn add_text_from_html(
+        &self,
+        template_id: &str,
+        spec: &TemplateSpec,
+        bodies: Vec1<RenderedBody>
+    ) -> Result<Vec1<RenderedBody>, UseTemplateError<R::RenderError>>
+    {
+        let text = match bodies.iter().rev().find(|body| has_full_type(&body.media_type, "text/html")) {
+            Some(html) => html_to_text(&html.content),
+            None => return Ok(bodies)
+        };
+
+        // UNWRAP_SAFE: the media type is a valid constant
+        let media_type = MediaType::new_with_params("text", "plain", vec![ (CHARSET, "utf-8") ]).unwrap();
+        let text = fix_newlines(self.post_process(template_id, spec, &media_type, text)?);
+        let (media_type, text) = self.enforce_line_length(template_id, &media_type, text)?;
+        let mut bodies = bodies.into_vec();
+        bodies.insert(0, RenderedBody {
+            media_type,
+            content: text,
+            embeddings: HashMap::new()
+        });
+        // UNWRAP_SAFE: there is at last the just inserted body
+        Ok(Vec1::from_vec(bodies).unwrap())
+    }
+
+    /// renders the bodies, the subject and the headers of the template associated with `template_id`
+    ///
+    /// Unlike `use_template` this also returns the rendered subject and headers.
+    pub fn render_mail<C, D>(
+        &self,
+        template_id: &str,
+        data: &D,
+        ctx: &C
+    ) -> Result<RenderedMail, UseTemplateError<R::RenderError>>
+        where C: Context, R: RenderEngine<D>
+    {
+        let headers = self.render_headers(template_id, data)?;
+        let rendered = self.render_bodies(template_id, data, ctx)?;
+        Ok(rendered.into_rendered_mail(headers))
+    }
+
+    /// renders the header values of the template associated with `template_id`
+    ///
+    /// Each value is rendered on its own (see `HeaderTemplate`) and trimmed.
+    /// Headers with an empty value are omitted, so that e.g.
+    /// `List-Unsubscribe: {{data.unsubscribe_url}}` can be used with data
+    /// which has no `unsubscribe_url`.
+    ///
+    /// The headers are returned in the order they are specified. If the
+    /// template has no headers template no headers are returned.
+    ///
+    /// # Error
+    ///
+    /// If a rendered value contains a line break (e.g. from the data)
+    /// `ProcessingError::InvalidHeader` is returned, as it would
+    /// allow injecting additional headers.
+    pub fn render_headers<D>(&self, template_id: &str, data: &D)
+        -> Result<Vec<(String, String)>, UseTemplateError<R::RenderError>>
+        where R: RenderEngine<D>
+    {
+        let spec = self.lookup_spec(template_id)
+            .ok_or_else(|| UseTemplateError::Render(R::unknown_template_id_error(template_id)))?;
+
+        let mut headers = Vec::with_capacity(spec.headers().len());
+        for header in spec.headers() {
+            let rendered = self.render_engine.render(header.value(), data, AdditionalCIds::new(&[]))
+                .map_err(UseTemplateError::Render)?;
+            let value = rendered.trim();
+            if !is_valid_header_value(value) {
+                return Err(ProcessingError::InvalidHeader {
+                    template_id: template_id.to_owned(),
+                    line: format!("{}: {}", header.name(), value)
+                }.into());
+            }
+            if !value.is_empty() {
+                headers.push((header.name().to_owned(), value.to_owned()));
+            }
+        }
+        Ok(headers)
+    }
+
+    /// renders only the subject of the template associated with `template_id`
+    ///
+    /// Returns `None` if the template has no subject template.
+    pub fn render_subject<D>(&self, template_id: &str, data: &D)
+        -> Result<Option<String>, UseTemplateError<R::RenderError>>
+        where R: RenderEngine<D>
+    {
+        let spec = self.lookup_spec(template_id)
+            .ok_or_else(|| UseTemplateError::Render(R::unknown_template_id_error(template_id)))?;
+        self.render_subject_of(spec, data)
+    }
+
+    /// renders the subject template of the spec (if there is one)
+    ///
+    /// As a subject is a single line, line breaks (and the whitespace
+    /// around them) are replaced by a single space.
+    fn render_subject_of<D>(&self, spec: &TemplateSpec, data: &D)
+        -> Result<Option<String>, UseTemplateError<R::RenderError>>
+        where R: RenderEngine<D>
+    {
+        let subject_spec = match spec.subject() {
+            Some(subject_spec) => subject_spec,
+            None => return Ok(None)
+        };
+
+        let rendered = self.render_engine.render(subject_spec, data, AdditionalCIds::new(&[]))
+            .map_err(UseTemplateError::Render)?;
+        let subject = rendered.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(Some(subject))
+    }
+}
+
+impl<R> RenderTemplateEngine<R>
+    where R: RenderEngineBase
+{
+    /// returns the id of the spec to use for the template in the given locale
+    ///
+    /// The ids `<template_id>.<locale>` are tried for the locale and its
+    /// parents (e.g. `de-AT` then `de`), then for each fallback locale (and
+    /// its parents) and at last the unlocalized `template_id` is tried.
+    ///
+    /// The locale of the found variant is returned with the id, it is
+    /// `None` if the unlocalized spec is used.
+    pub fn resolve_localized_id(&self, template_id: &str, locale: &Locale)
+        -> Option<(String, Option<Locale>)>
+    {
+        let mut tried = HashSet::new();
+        let candidates = locale.fallback_chain().into_iter()
+            .chain(self.fallback_locales.iter().flat_map(Locale::fallback_chain));
+
+        for candidate in candidates {
+            if !tried.insert(candidate.clone()) {
+                continue;
+            }
+            let id = localized_template_id(template_id, &candidate);
+            if self.id2spec.contains_key(&id) {
+                return Some((id, Some(candidate)));
+            }
+        }
+
+        if self.id2spec.contains_key(template_id) {
+            Some((template_id.to_owned(), None))
+        } else {
+            None
+        }
+    }
+
+    /// like `use_template` but uses the variant of the template best matching `locale`
+    ///
+    /// See `resolve_localized_id` for how the variant is selected. Like
+    /// `render_mail` the subject and headers are rendered, too.
+    ///
+    /// The locale of the used variant is returned as `content_language` and
+    /// set as `Content-Language` header in the rendered `headers` (like it
+    /// is for any rendering of a localized variant, see `RenderedMail`).
+    pub fn use_localized_template<C, D>(
+        &self,
+        template_id: &str,
+        locale: &Locale,
+        data: &D,
+        ctx: &C
+    ) -> Result<RenderedMail, UseTemplateError<R::RenderError>>
+        where C: Context, R: RenderEngine<D>
+    {
+        let (id, _) = self.resolve_localized_id(template_id, locale)
+            .ok_or_else(|| UseTemplateError::Render(R::unknown_template_id_error(template_id)))?;
+
+        self.render_mail(&id, data, ctx)
+    }
+}
+
+/// A rendered mail (see `RenderTemplateEngine::render_mail`).
+///
+/// If a localized variant of a template (i.e. a template with a id like
+/// `welcome.de`, see `localized_template_id`) was rendered the `headers`
+/// contain a `Content-Language` header with its locale. The parts of
+/// `MailParts` can not carry headers, so the `headers` have to be set on
+/// the mail the parts are used for (`render_preview` does so).
+#[derive(Debug)]
+pub struct RenderedMail {
+    pub parts: MailParts,
+    /// the rendered subject, `None` if the template has no subject template
+    pub subject: Option<String>,
+    /// the rendered headers (`name, value`) in the order they were rendered
+    ///
+    /// This are defaults of the template, it is up to the caller
+    /// which of them are set on the mail, except for the `Content-Language`
+    /// header of localized variants which is always last.
+    pub headers: Vec<(String, String)>,
+    /// the locale of the used template variant, to be used as `Content-Language`
+    ///
+    /// `None` if no localized variant was used.
+    pub content_language: Option<Locale>
+}
+
+impl RenderedMail {
+
+    /// sets `content_language` and the `Content-Language` header (if there is a locale)
+    fn set_content_language(&mut self, content_language: Option<Locale>) {
+        if let Some(locale) = content_language.as_ref() {
+            self.headers.retain(|&(ref name, _)| !name.eq_ignore_ascii_case(CONTENT_LANGUAGE));
+            self.headers.push((CONTENT_LANGUAGE.to_owned(), locale.to_string()));
+        }
+        self.content_language = content_language;
+    }
+}
+
+/// The rendered bodies of a template (see `RenderTemplateEngine::render_bodies`).
+#[derive(Debug)]
+pub struct RenderedBodies {
+    /// one rendered body for each sub-template (alternate body)
+    pub bodies: Vec1<RenderedBody>,
+    /// embeddings shared between all bodies
+    pub shared_embeddings: HashMap<String, EmbeddedWithCId>,
+    /// attachments of the template
+    pub attachments: Vec<EmbeddedWithCId>,
+    /// the rendered subject, `None` if the template has no subject template
+    pub subject: Option<String>,
+    /// the locale of the template if it is a localized variant
+    pub content_language: Option<Locale>
+}
+
+impl RenderedBodies {
+
+    /// rewrites the links in all `text/html` and `text/plain` bodies (e.g. for click-tracking)
+    ///
+    /// The function gets each distinct url once and returns the url to use
+    /// instead, or `None` to keep it. `cid:`, `mailto:` and fragment links are
+    /// not passed to it. As the function is passed per call it can e.g. create
+    /// urls with a per recipient token. Returns the rewritten links.
+    pub fn rewrite_links<F>(&mut self, rewrite: F) -> Vec<RewrittenLink>
+        where F: FnMut(&str) -> Option<String>
+    {
+        let mut rewriter = LinkRewriter::new(rewrite);
+        for body in self.bodies.iter_mut() {
+            if has_full_type(&body.media_type, "text/html") {
+                body.content = rewriter.rewrite_html(&body.content);
+            } else if has_full_type(&body.media_type, "text/plain") {
+                body.content = rewriter.rewrite_text(&body.content);
+            }
+        }
+        rewriter.into_links()
+    }
+
+    /// turns the rendered bodies into `MailParts` as returned by `use_template`
+    ///
+    /// The `Content-Language` of a localized variant is lost, as `MailParts`
+    /// can not carry headers, use `into_rendered_mail` to keep it.
+    pub fn into_mail_parts(self) -> MailParts {
+        self.into_rendered_mail(Vec::new()).parts
+    }
+
+    /// turns the rendered bodies into a `RenderedMail` with the given (rendered) headers
+    ///
+    /// If `content_language` is set the `Content-Language` header is added,
+    /// replacing one from `headers`.
+    pub fn into_rendered_mail(self, headers: Vec<(String, String)>) -> RenderedMail {
+        let RenderedBodies { bodies, shared_embeddings, attachments, subject, content_language } = self;
+        let parts = MailParts {
+            alternative_bodies: bodies.mapped(RenderedBody::into_body_part),
             //TODO collpas embeddings and attachments and use their disposition parma
             // instead
             shared_embeddings: shared_embeddings.into_iter().map(|(_, v)| v).collect(),
             attachments,
-        })
+        };
+        let mut mail = RenderedMail { parts, subject, headers, content_language: None };
+        mail.set_content_language(content_language);
+        mail
+    }
+}
+
+/// A single rendered body.
+#[derive(Debug)]
+pub struct RenderedBody {
+    /// the media type of the body as specified by the sub-template
+    pub media_type: MediaType,
+    /// the rendered text
+    pub content: String,
+    /// the embeddings specific to this body
+    pub embeddings: HashMap<String, EmbeddedWithCId>
+}
+
+impl RenderedBody {
+    pub fn into_body_part(self) -> BodyPart {
+        let RenderedBody { media_type, content, embeddings } = self;
+        let buffer = FileBuffer::new(media_type, content.into());
+        BodyPart {
+            resource: Resource::sourceless_from_buffer(buffer),
+            embeddings: embeddings.into_iter().map(|(_,v)| v).collect()
+        }
+    }
+}
+
+impl<C, D, R> TemplateEngine<C, D> for RenderTemplateEngine<R>
+    where C: Context, R: RenderEngine<D>
+{
+    type TemplateId = str;
+    type Error = <R as RenderEngineBase>::RenderError;
+
+    fn use_template(
+        &self,
+        template_id: &str,
+        data: &D,
+        ctx: &C,
+    ) -> Result<MailParts, Self::Error >
+    {
+        let rendered = self.render_bodies(template_id, data, ctx)
+            .map_err(UseTemplateError::into_render_error)?;
+        Ok(rendered.into_mail_parts())
     }
 }
 
+fn post_processing_error<E: Fail>(ctx: &PostProcessContext, error: Error) -> UseTemplateError<E> {
+    ProcessingError::PostProcessing {
+        template_id: ctx.template_id.to_owned(),
+        media_type: ctx.media_type.full_type().to_string(),
+        error
+    }.into()
+}
+
+/// true if both specs have a template with the same id
+fn shares_template_ids(left: &TemplateSpec, right: &TemplateSpec) -> bool {
+    let left_ids = left.render_templates()
+        .map(|sub_spec| sub_spec.source().id())
+        .collect::<HashSet<_>>();
+    right.render_templates().any(|sub_spec| left_ids.contains(sub_spec.source().id()))
+}
+
 fn create_embedding(
     key: &str,
     resource: &Resource,
@@ -0,0 +1,260 @@
+//! Snapshot testing of templates using their sample data files.
+//!
+//! Each sample of a template (see `TemplateSpec::samples`) is rendered and
+//! every body is compared with the expected output stored next to the sample
+//! as `<sample>.expected.<ext>`, where `<ext>` is the extension of the template
+//! file of the body (e.g. `txt` for `text/mail.txt`). If the template has a
+//! subject template the subject is compared with `<sample>.expected.subject.txt`.
+//!
+//! As content ids are generated new each time a template is rendered they
+//! are replaced with `[cid:<embedding name>]` before comparing. Newlines are
+//! normalized to `\n`, so expected outputs can be edited with any editor.
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use failure::Fail;
+use serde_json::{self, Value};
+use toml;
+
+use mail::Context;
+use template::EmbeddedWithCId;
+
+use ::error::{PreviewError, PreviewErrorKind};
+use ::spec::{Sample, TemplateSource};
+use ::utils::has_full_type;
+use ::traits::RenderEngine;
+use ::rte::RenderTemplateEngine;
+use ::preview::PreviewData;
+
+/// loads the data of a sample (JSON or TOML, depending on the file extension)
+pub fn load_sample_data(sample: &Sample) -> Result<PreviewData, PreviewError> {
+    let mut content = String::new();
+    File::open(sample.path())?.read_to_string(&mut content)?;
+
+    let is_toml = sample.path().extension()
+        .map(|extension| extension == "toml")
+        .unwrap_or(false);
+
+    let invalid_data = || PreviewErrorKind::InvalidSampleData { file: sample.path().into() };
+    let value: Value =
+        if is_toml {
+            toml::from_str(&content).map_err(|err| err.context(invalid_data()))?
+        } else {
+            serde_json::from_str(&content).map_err(|err| err.context(invalid_data()))?
+        };
+
+    Ok(PreviewData(value))
+}
+
+/// The result of comparing one body rendered with one sample.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SampleCheck {
+    pub sample: String,
+    pub expected_file: PathBuf,
+    pub outcome: SampleOutcome
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SampleOutcome {
+    /// the output matches the expected output
+    Matched,
+    /// the output differs from the expected output
+    Mismatched { expected: String, actual: String },
+    /// there is no expected output
+    Missing { actual: String },
+    /// the expected output was (over-)written with the output
+    Blessed
+}
+
+/// The result of `check_samples`.
+#[derive(Debug, Clone, Default)]
+pub struct SampleReport {
+    pub checks: Vec<SampleCheck>
+}
+
+impl SampleReport {
+
+    /// returns true if all outputs matched (or where blessed)
+    pub fn is_ok(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item=&SampleCheck> {
+        self.checks.iter().filter(|check| match check.outcome {
+            SampleOutcome::Matched | SampleOutcome::Blessed => false,
+            _ => true
+        })
+    }
+}
+
+impl Display for SampleReport {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fter, "checked {} output(s), {} failure(s)", self.checks.len(), self.failures().count())?;
+        for check in self.failures() {
+            match check.outcome {
+                SampleOutcome::Missing { .. } => {
+                    writeln!(fter, "{}: expected output missing: {}",
+                        check.sample, check.expected_file.display())?;
+                },
+                SampleOutcome::Mismatched { ref expected, ref actual } => {
+                    writeln!(fter, "{}: output differs from {}",
+                        check.sample, check.expected_file.display())?;
+                    write_first_difference(fter, expected, actual)?;
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// renders each sample of the template and compares the output with the expected output
+///
+/// If `bless` is true missing or differing expected outputs are
+/// (over-)written with the actual output instead of being reported
+/// as failure.
+///
+/// # Error
+///
+/// An error is returned if the template does not exist, a sample can not
+/// be loaded or rendered or (if blessing) writing a expected output fails.
+pub fn check_samples<R, C>(
+    engine: &RenderTemplateEngine<R>,
+    template_id: &str,
+    ctx: &C,
+    bless: bool
+) -> Result<SampleReport, PreviewError>
+    where R: RenderEngine<PreviewData>, C: Context
+{
+    let spec = engine.lookup_spec(template_id)
+        .ok_or_else(|| R::unknown_template_id_error(template_id).context(PreviewErrorKind::Rendering))?;
+
+    let mut extensions = spec.sub_specs().iter().enumerate()
+        .map(|(idx, sub_spec)| body_extension(sub_spec.source(), idx))
+        .collect::<Vec<_>>();
+    let has_html = spec.sub_specs().iter()
+        .any(|sub_spec| has_full_type(sub_spec.media_type(), "text/html"));
+    if spec.generates_text_from_html() && has_html {
+        // the generated text body is the first body
+        extensions.insert(0, GENERATED_TEXT_EXTENSION.to_owned());
+    }
+
+    let mut report = SampleReport::default();
+    for sample in spec.samples() {
+        let data = load_sample_data(sample)?;
+        let rendered = engine.render_bodies(template_id, &data, ctx)
+            .map_err(|err| err.context(PreviewErrorKind::Rendering))?;
+
+        for (body, extension) in rendered.bodies.iter().zip(extensions.iter()) {
+            let actual = normalize_output(&body.content, &[&body.embeddings, &rendered.shared_embeddings]);
+            let expected_file = sample.expected_output_path(extension);
+            let outcome = compare_with_expected(&expected_file, actual, bless)?;
+            report.checks.push(SampleCheck {
+                sample: sample.name().to_owned(),
+                expected_file,
+                outcome
+            });
+        }
+
+        if let Some(subject) = rendered.subject {
+            let expected_file = sample.expected_output_path(SUBJECT_EXTENSION);
+            let outcome = compare_with_expected(&expected_file, subject, bless)?;
+            report.checks.push(SampleCheck {
+                sample: sample.name().to_owned(),
+                expected_file,
+                outcome
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// extension used for the expected output of a generated text body (`<sample>.expected.generated.txt`)
+const GENERATED_TEXT_EXTENSION: &str = "generated.txt";
+
+/// extension used for the expected output of the subject (`<sample>.expected.subject.txt`)
+const SUBJECT_EXTENSION: &str = "subject.txt";
+
+fn compare_with_expected(expected_file: &PathBuf, actual: String, bless: bool)
+    -> Result<SampleOutcome, PreviewError>
+{
+    let expected = match fs::read_to_string(expected_file) {
+        Ok(expected) => Some(expected.replace("\r\n", "\n")),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into())
+    };
+
+    if expected.as_ref() == Some(&actual) {
+        return Ok(SampleOutcome::Matched);
+    }
+
+    if bless {
+        fs::write(expected_file, &actual)?;
+        return Ok(SampleOutcome::Blessed);
+    }
+
+    Ok(match expected {
+        Some(expected) => SampleOutcome::Mismatched { expected, actual },
+        None => SampleOutcome::Missing { actual }
+    })
+}
+
+/// the extension of the template file, used to name the expected output
+fn body_extension(source: &TemplateSource, idx: usize) -> String {
+    let id = source.id();
+    let file_name = id.rsplit(|ch| ch == '/' || ch == '\\').next().unwrap_or(id);
+    match file_name.rfind('.') {
+        Some(dot_idx) if dot_idx + 1 < file_name.len() => file_name[dot_idx + 1..].to_owned(),
+        _ => format!("body{}", idx)
+    }
+}
+
+/// replaces content ids with `[cid:<name>]` and normalizes newlines to `\n`
+fn normalize_output(content: &str, embeddings: &[&HashMap<String, EmbeddedWithCId>]) -> String {
+    let mut out = content.replace("\r\n", "\n");
+    for embeddings in embeddings {
+        for (name, embedded) in embeddings.iter() {
+            // use the content id the way the templates see it
+            if let Ok(Value::String(cid)) = serde_json::to_value(embedded.content_id()) {
+                out = out.replace(&*cid, &format!("[cid:{}]", name));
+            }
+        }
+    }
+    out
+}
+
+fn write_first_difference(fter: &mut fmt::Formatter, expected: &str, actual: &str) -> fmt::Result {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line_nr = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return writeln!(fter, "    (outputs differ only in trailing newlines)"),
+            (expected, actual) if expected != actual => {
+                writeln!(fter, "    first difference in line {}:", line_nr)?;
+                writeln!(fter, "    expected: {:?}", expected.unwrap_or("<end of output>"))?;
+                return writeln!(fter, "    actual:   {:?}", actual.unwrap_or("<end of output>"));
+            },
+            _ => line_nr += 1
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use ::spec::TemplateSource;
+    use super::body_extension;
+
+    #[test]
+    fn body_extension_from_template_file() {
+        let source = TemplateSource::Path("./templates/a/html/mail.html".to_owned());
+        assert_eq!(body_extension(&source, 1), "html");
+
+        let source = TemplateSource::Source { id: "a_text".to_owned(), content: String::new() };
+        assert_eq!(body_extension(&source, 0), "body0");
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::path::Path;
+use std::mem::replace;
+use std::sync::Arc;
 
 use failure::Fail;
 use media_type::CHARSET;
@@ -8,7 +10,7 @@ use vec1::Vec1;
 use headers::components::MediaType;
 
 use ::error::{CreatingSpecError, CreatingSpecErrorVariant};
-use ::utils;
+use ::resolver::{MediaTypeResolver, StrictResolver};
 
 
 //Type::find_media_type(Path)
@@ -29,24 +31,28 @@ lazy_static! {
             base_subtype: "html".to_owned(),
             suffixes: vec1![ ".html".to_owned(), ".htm".to_owned() ],
             charset: Some("utf-8".to_owned()),
+            format_flowed: None,
         };
         let xhtml = Type {
             base_type: "application".to_owned(),
             base_subtype: "xhtml+xml".to_owned(),
             suffixes: vec1![ ".xhtml".to_owned(), ".xml".to_owned() ],
             charset: Some("utf-8".to_owned()),
+            format_flowed: None,
         };
         let enriched = Type {
             base_type: "text".to_owned(),
             base_subtype: "enriched".to_owned(),
             suffixes: vec1![ ".txt".to_owned(), ".text".to_owned() ],
             charset: Some("utf-8".to_owned()),
+            format_flowed: None,
         };
         let text = Type {
             base_type: "text".to_owned(),
             base_subtype: "plain".to_owned(),
             suffixes: vec1![ ".txt".to_owned(), ".text".to_owned() ],
             charset: Some("utf-8".to_owned()),
+            format_flowed: None,
         };
 
         let mut se = LoadSpecSettings::new();
@@ -60,23 +66,109 @@ lazy_static! {
 }
 
 
+/// name of the (optional) folder in a template folder which contains attachments
+pub(crate) const ATTACHMENTS_DIR_NAME: &str = "attachments";
+
+/// name of the (optional) folder in a template folder which contains sample data
+pub(crate) const SAMPLES_DIR_NAME: &str = "samples";
+
+/// name of the (optional) file in a template folder which is the template for the subject
+pub(crate) const SUBJECT_FILE_NAME: &str = "subject.txt";
+
+/// name of the (optional) file in a template folder which is the template for additional headers
+pub(crate) const HEADERS_FILE_NAME: &str = "headers.txt";
+
+/// name of the (optional) file in a template folder which contains css rules to inline into html bodies
+pub(crate) const STYLESHEET_FILE_NAME: &str = "style.css";
+
+/// names of folders in a template folder which have a special meaning
+///
+/// They can not be used as names for type lookups, as this would make
+/// them ambiguous with sub-template folders.
+pub(crate) const RESERVED_DIR_NAMES: &[&str] = &[ATTACHMENTS_DIR_NAME, SAMPLES_DIR_NAME];
+
 //IMPLEMENTATION NOTE: for now this is a simple configurabe think,
-// BUT in the future it can either be
-// 1. extended to support more stuff
-// 2. made into a trait with multiple impl. where the current impl. is just the default
+// BUT in the future it can be extended to support more stuff,
+// the media type detection already is pluggable through `MediaTypeResolver`
 
 #[derive(Debug, Clone)]
 pub struct LoadSpecSettings {
     type_lookup: HashMap<String, (usize, Type)>,
+    media_type_resolver: Arc<MediaTypeResolver>,
+    generate_text_from_html: bool,
 }
 
 impl LoadSpecSettings {
 
     pub fn new() -> Self {
-        LoadSpecSettings { type_lookup: HashMap::new() }
+        LoadSpecSettings {
+            type_lookup: HashMap::new(),
+            media_type_resolver: Arc::new(StrictResolver::new()),
+            generate_text_from_html: false,
+        }
+    }
+
+    /// sets the resolver used to determine the media type of embeddings and attachments
+    ///
+    /// By default a `StrictResolver` is used, which fails if the media type
+    /// implied by the file extension differs from the one detected from the content.
+    pub fn set_media_type_resolver<R>(&mut self, resolver: R) -> Arc<MediaTypeResolver>
+        where R: MediaTypeResolver + 'static
+    {
+        replace(&mut self.media_type_resolver, Arc::new(resolver))
+    }
+
+    pub fn media_type_resolver(&self) -> &Arc<MediaTypeResolver> {
+        &self.media_type_resolver
+    }
+
+    /// sets if the `file` command should be used for files with an unknown content format
+    ///
+    /// Media types are detected in-process by looking at the content of the file,
+    /// if this fails and this option is enabled the `file` command (`file -b --mime`)
+    /// is used as a fallback. If it is disabled (the default) only the file extension
+    /// is used for such files.
+    ///
+    /// This replaces the media type resolver (and with it any configuration
+    /// of it) with a `StrictResolver`.
+    #[deprecated(
+        since = "0.3.0",
+        note = "replaces the media type resolver, use `set_media_type_resolver` with e.g. `StrictResolver::with_file_cmd_fallback()` instead"
+    )]
+    pub fn set_file_cmd_fallback(&mut self, use_file_cmd: bool) {
+        let mut resolver = StrictResolver::new();
+        resolver.set_file_cmd_fallback(use_file_cmd);
+        self.set_media_type_resolver(resolver);
     }
 
+    pub fn uses_file_cmd_fallback(&self) -> bool {
+        self.media_type_resolver.uses_file_cmd_fallback()
+    }
 
+    /// if set specs with a `text/html` but no `text/plain` sub-template generate a text body
+    ///
+    /// The generated body is created by converting the rendered html body
+    /// to plain text and is used as the lowest priority alternative body
+    /// (see `TemplateSpec::generates_text_from_html`). This is off by default.
+    pub fn set_generate_text_from_html(&mut self, generate: bool) {
+        self.generate_text_from_html = generate
+    }
+
+    pub fn generates_text_from_html(&self) -> bool {
+        self.generate_text_from_html
+    }
+
+    /// sets `Type::format_flowed` for all currently registered `text/plain` types
+    ///
+    /// E.g. `settings.set_format_flowed(Some(FLOWED_LINE_WIDTH))` makes all
+    /// text bodies `format=flowed` bodies, `None` disables it again.
+    pub fn set_format_flowed(&mut self, width: Option<usize>) {
+        for &mut (_, ref mut type_) in self.type_lookup.values_mut() {
+            if type_.base_type().eq_ignore_ascii_case("text") && type_.base_subtype().eq_ignore_ascii_case("plain") {
+                type_.set_format_flowed(width);
+            }
+        }
+    }
 
     pub fn get_type(&self, name: &str) -> Option<&Type> {
         self.type_lookup.get(name)
@@ -104,6 +196,10 @@ impl LoadSpecSettings {
     fn _set_type_lookup(&mut self, name: String, type_: Type, prioritize_over: Option<&str>)
         -> Result<(), CreatingSpecError>
     {
+        if RESERVED_DIR_NAMES.contains(&&*name) {
+            return Err(CreatingSpecErrorVariant::ReservedTypeName { name }.into());
+        }
+
         let new_priority =
             if let Some(other) = prioritize_over {
                 let other_prio = self.get_priority_idx(other)
@@ -175,7 +271,7 @@ impl LoadSpecSettings {
     pub fn determine_media_type<P>(&self, path: P) -> Result<MediaType, CreatingSpecError>
         where P: AsRef<Path>
     {
-        utils::sniff_media_type(path.as_ref())
+        self.media_type_resolver.resolve_media_type(path.as_ref())
     }
 }
 
@@ -185,11 +281,29 @@ pub struct Type {
     base_subtype: String,
     //TODO remove
     suffixes: Vec1<String>,
-    charset: Option<String>
+    charset: Option<String>,
+    /// the line width to reflow `text/plain` bodies to as `format=flowed`
+    format_flowed: Option<usize>
 }
 
 impl Type {
 
+    pub fn new<T, S>(
+        base_type: T,
+        base_subtype: S,
+        suffixes: Vec1<String>,
+        charset: Option<String>
+    ) -> Self
+        where T: Into<String>, S: Into<String>
+    {
+        Type {
+            base_type: base_type.into(),
+            base_subtype: base_subtype.into(),
+            suffixes, charset,
+            format_flowed: None
+        }
+    }
+
     pub fn to_media_type_for<P>(&self, path: P) -> Result<MediaType, CreatingSpecError>
         where P: AsRef<Path>
     {
@@ -201,13 +315,18 @@ impl Type {
         // unusual bodies
         // for now this is just creating a media type and set a preset charset,
         // not trying to verify the charset or anything else
+        let mut params = Vec::new();
+        if let Some(charset) = self.charset.as_ref() {
+            params.push((CHARSET, &**charset));
+        }
+        if self.is_format_flowed() {
+            params.push(("format", "flowed"));
+        }
         let media_type_res =
-            if let Some(charset) = self.charset.as_ref() {
-                MediaType::new_with_params(&self.base_type, &self.base_subtype, vec![
-                    (CHARSET, charset)
-                ])
-            } else {
+            if params.is_empty() {
                 MediaType::new(&self.base_type, &self.base_subtype)
+            } else {
+                MediaType::new_with_params(&self.base_type, &self.base_subtype, params)
             };
 
         let media_type = media_type_res
@@ -216,10 +335,47 @@ impl Type {
         Ok(media_type)
     }
 
+    pub fn base_type(&self) -> &str {
+        &self.base_type
+    }
+
+    pub fn base_subtype(&self) -> &str {
+        &self.base_subtype
+    }
+
     pub fn suffixes(&self) -> &Vec1<String> {
         &self.suffixes
     }
 
+    pub fn charset(&self) -> Option<&str> {
+        self.charset.as_ref().map(|s| &**s)
+    }
+
+    pub fn set_charset(&mut self, charset: Option<String>) -> Option<String> {
+        replace(&mut self.charset, charset)
+    }
+
+    /// the line width `text/plain` bodies of this type are reflowed to as `format=flowed`
+    ///
+    /// If set (and this is the `text/plain` type) `to_media_type_for` adds
+    /// a `format=flowed` parameter and the rendered bodies are encoded as
+    /// described in RFC 3676, i.e. each line is reflowed into soft lines
+    /// of at most this width (see `SubTemplateSpec::flowed_width`).
+    pub fn format_flowed(&self) -> Option<usize> {
+        self.format_flowed
+    }
+
+    pub fn set_format_flowed(&mut self, width: Option<usize>) -> Option<usize> {
+        replace(&mut self.format_flowed, width)
+    }
+
+    /// true if `format_flowed` is set and this is the `text/plain` type
+    pub fn is_format_flowed(&self) -> bool {
+        self.format_flowed.is_some()
+            && self.base_type.eq_ignore_ascii_case("text")
+            && self.base_subtype.eq_ignore_ascii_case("plain")
+    }
+
     pub fn template_base_name(&self) -> &str {
         "mail"
     }
@@ -228,6 +384,8 @@ impl Type {
 
 #[cfg(test)]
 mod test {
+    use ::error::CreatingSpecErrorVariant;
+    use ::resolver::{StrictResolver, ContentResolver, MappingResolver};
     use super::{LoadSpecSettings, Type};
 
     fn dumy_settings() -> LoadSpecSettings {
@@ -247,9 +405,20 @@ mod test {
             base_subtype: subtype.to_owned(),
             suffixes: vec1![ suffix.to_owned() ],
             charset: Some("utf-8".to_owned()),
+            format_flowed: None,
         }
     }
 
+    #[test]
+    fn file_cmd_fallback_is_configured_on_the_resolver() {
+        let mut se = LoadSpecSettings::new();
+        assert!(!se.uses_file_cmd_fallback());
+        se.set_media_type_resolver(StrictResolver::with_file_cmd_fallback());
+        assert!(se.uses_file_cmd_fallback());
+        se.set_media_type_resolver(MappingResolver::with_fallback(ContentResolver::with_file_cmd_fallback()));
+        assert!(se.uses_file_cmd_fallback());
+    }
+
     #[test]
     fn add_types_and_aliases() {
         let mut se = LoadSpecSettings::new();
@@ -284,6 +453,36 @@ mod test {
 
     }
 
+    #[test]
+    fn reserved_names_can_not_be_used_as_type_names() {
+        let mut se = dumy_settings();
+        let err = se.set_type_lookup("attachments", dumy_type("plain", "txt"), None).unwrap_err();
+        if let &CreatingSpecErrorVariant::ReservedTypeName { ref name } = err.variant() {
+            assert_eq!(name, "attachments");
+        } else {
+            panic!("unexpected error: {}", err);
+        }
+        assert_eq!(se.get_type("attachments"), None);
+    }
+
+    #[test]
+    fn format_flowed_is_set_for_text_types_only() {
+        let mut se = dumy_settings();
+        let mut plain = dumy_type("plain", "txt");
+        plain.set_format_flowed(Some(72));
+        assert!(plain.is_format_flowed());
+        se.set_type_lookup("plain", dumy_type("plain", "txt"), None).unwrap();
+
+        se.set_format_flowed(Some(72));
+        assert_eq!(se.get_type("plain"), Some(&plain));
+        assert_eq!(se.get_type("html").unwrap().format_flowed(), None);
+
+        let media_type = plain.to_media_type_for("mail.txt").unwrap();
+        assert_eq!(media_type.as_str_repr(), "text/plain; charset=utf-8; format=flowed");
+        let media_type = se.get_type("html").unwrap().to_media_type_for("mail.html").unwrap();
+        assert_eq!(media_type.as_str_repr(), "text/html; charset=utf-8");
+    }
+
     #[test]
     fn remove_type() {
         let mut se = dumy_settings();
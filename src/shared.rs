@@ -0,0 +1,135 @@
+//! A `RenderTemplateEngine` which can be shared between threads.
+//!
+//! Readers render using an immutable snapshot of the engine, writers
+//! build a new version of the engine and publish it. Publishing does
+//! not block readers, renders which already started keep using the
+//! snapshot they started with.
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+
+use mail::Context;
+use template::{TemplateEngine, MailParts};
+
+use ::error::InsertionError;
+use ::traits::{RenderEngine, RenderEngineBase};
+use ::rte::RenderTemplateEngine;
+
+/// A thread-safe wrapper around a `RenderTemplateEngine`
+///
+/// Reading (rendering) is lock-free, it uses the snapshot returned by
+/// `snapshot`. Writes are serialized, a writer either publishes a newly
+/// build engine with `publish` or changes a copy of the current engine
+/// with `update` (which requires the render engine to be `Clone`) or
+/// `update_with` (which builds a new render engine instead, e.g. for the
+/// tera and handlebars render engines which are not `Clone`).
+///
+/// `TemplateEngine` is implemented by rendering with the current snapshot,
+/// so a `&SharedRenderTemplateEngine` can be used e.g. with `MailSendData`.
+#[derive(Debug)]
+pub struct SharedRenderTemplateEngine<R>
+    where R: RenderEngineBase
+{
+    current: ArcSwap<RenderTemplateEngine<R>>,
+    /// serializes writers, so that no update is lost
+    write_lock: Mutex<()>
+}
+
+impl<R> SharedRenderTemplateEngine<R>
+    where R: RenderEngineBase
+{
+    pub fn new(engine: RenderTemplateEngine<R>) -> Self {
+        SharedRenderTemplateEngine {
+            current: ArcSwap::from(Arc::new(engine)),
+            write_lock: Mutex::new(())
+        }
+    }
+
+    /// returns the currently published engine
+    ///
+    /// The snapshot is not affected by later writes, e.g. all mails of
+    /// a batch can be rendered using the same version of the templates.
+    pub fn snapshot(&self) -> Arc<RenderTemplateEngine<R>> {
+        self.current.load()
+    }
+
+    /// publishes a new engine, returning the previously published one
+    ///
+    /// If an `update` is running at the same time this waits for it to
+    /// complete (and then replaces its result).
+    pub fn publish(&self, engine: RenderTemplateEngine<R>) -> Arc<RenderTemplateEngine<R>> {
+        let _guard = self.write_lock.lock()
+            .expect("[BUG] panic while holding the lock");
+        self.current.swap(Arc::new(engine))
+    }
+
+    /// changes a copy of the current engine and publishes it if `update` succeeds
+    ///
+    /// Readers keep using the current engine while the copy is changed, e.g.
+    /// `update(|engine| engine.reload_all(&settings))` does not block rendering
+    /// while the templates are reloaded. If `update` returns an error nothing
+    /// is published.
+    pub fn update<F, T, E>(&self, update: F) -> Result<T, E>
+        where F: FnOnce(&mut RenderTemplateEngine<R>) -> Result<T, E>, R: Clone
+    {
+        let _guard = self.write_lock.lock()
+            .expect("[BUG] panic while holding the lock");
+        let mut engine = (*self.current.load()).clone();
+        let result = update(&mut engine)?;
+        self.current.store(Arc::new(engine));
+        Ok(result)
+    }
+
+    /// like `update` but changes a rebuild of the current engine instead of a copy
+    ///
+    /// `new_render_engine` is called with the current render engine and has to
+    /// return a render engine without the templates of the specs, e.g. a new
+    /// one with the same base templates. The settings and specs of the current
+    /// engine are then transferred to it (see `with_render_engine`) before
+    /// `update` is called. E.g. with the tera render engine:
+    ///
+    /// ```ignore
+    /// shared.update_with(
+    ///     |_| TeraRenderEngine::new("./base_templates/**/*").expect("base templates are valid"),
+    ///     |engine| engine.reload_all(&settings)
+    /// )?;
+    /// ```
+    pub fn update_with<B, F, T, E>(&self, new_render_engine: B, update: F) -> Result<T, E>
+        where B: FnOnce(&R) -> R,
+              F: FnOnce(&mut RenderTemplateEngine<R>) -> Result<T, E>,
+              E: From<InsertionError<R::LoadingError>>
+    {
+        let _guard = self.write_lock.lock()
+            .expect("[BUG] panic while holding the lock");
+        let current = self.current.load();
+        let mut engine = current.with_render_engine(new_render_engine(current.render_engine()))?;
+        let result = update(&mut engine)?;
+        self.current.store(Arc::new(engine));
+        Ok(result)
+    }
+}
+
+impl<R> From<RenderTemplateEngine<R>> for SharedRenderTemplateEngine<R>
+    where R: RenderEngineBase
+{
+    fn from(engine: RenderTemplateEngine<R>) -> Self {
+        SharedRenderTemplateEngine::new(engine)
+    }
+}
+
+impl<C, D, R> TemplateEngine<C, D> for SharedRenderTemplateEngine<R>
+    where C: Context, R: RenderEngine<D>
+{
+    type TemplateId = str;
+    type Error = <R as RenderEngineBase>::RenderError;
+
+    fn use_template(
+        &self,
+        template_id: &str,
+        data: &D,
+        ctx: &C,
+    ) -> Result<MailParts, Self::Error>
+    {
+        self.snapshot().use_template(template_id, data, ctx)
+    }
+}
@@ -0,0 +1,231 @@
+//! In-process media type detection based on the content of a file.
+//!
+//! This recognizes common formats by their "magic bytes" and detects
+//! the charset of text files. It is by far not as complete as the
+//! `file` command, but it covers the formats normally used as
+//! embeddings/attachments in mails.
+use std::str;
+
+/// how many bytes are inspected at most (`file` has the same default limit)
+pub(crate) const MAX_SNIFF_LEN: usize = 1 << 20;
+
+const BINARY: &str = "charset=binary";
+
+/// returns the media type (with charset parameter) of the given content
+///
+/// If the content is not of a known format and not text `None` is returned.
+///
+/// If `truncated` is true `data` is assumed to be only the start of the
+/// content, i.e. a incomplete utf-8 sequence at the end is not seen as a
+/// encoding error.
+pub(crate) fn sniff_bytes(data: &[u8], truncated: bool) -> Option<String> {
+    if let Some(media_type) = sniff_magic(data) {
+        return Some(format!("{}; {}", media_type, BINARY));
+    }
+
+    if data.starts_with(b"PK\x03\x04") {
+        return Some(format!("{}; {}", sniff_zip(data), BINARY));
+    }
+
+    sniff_text(data, truncated)
+}
+
+fn sniff_magic(data: &[u8]) -> Option<&'static str> {
+    static MAGIC: &[(&[u8], &str)] = &[
+        (b"%PDF-", "application/pdf"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"\x1F\x8B", "application/gzip"),
+    ];
+
+    for &(magic, media_type) in MAGIC {
+        if data.starts_with(magic) {
+            return Some(media_type);
+        }
+    }
+
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    None
+}
+
+/// differentiates between plain zip files and zip based office documents
+fn sniff_zip(data: &[u8]) -> &str {
+    // ODF: the first (uncompressed) entry is named "mimetype" and contains the media type
+    const ODF_NAME_OFFSET: usize = 30;
+    if data.len() > ODF_NAME_OFFSET && &data[26..28] == b"\x08\x00" {
+        let size = read_u32_le(&data[18..22]) as usize;
+        let extra_len = read_u16_le(&data[28..30]) as usize;
+        let name_end = ODF_NAME_OFFSET + 8;
+        let content_start = name_end + extra_len;
+        if data.len() >= content_start + size && &data[ODF_NAME_OFFSET..name_end] == b"mimetype" {
+            if let Ok(media_type) = str::from_utf8(&data[content_start..content_start + size]) {
+                if media_type.starts_with("application/vnd.oasis.opendocument.") {
+                    return media_type;
+                }
+            }
+        }
+    }
+
+    // OOXML: contains a `[Content_Types].xml` and a folder specific for the kind of document
+    if contains(data, b"[Content_Types].xml") {
+        if contains(data, b"word/") {
+            return "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+        } else if contains(data, b"xl/") {
+            return "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+        } else if contains(data, b"ppt/") {
+            return "application/vnd.openxmlformats-officedocument.presentationml.presentation";
+        }
+    }
+
+    "application/zip"
+}
+
+fn read_u16_le(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    read_u16_le(bytes) as u32 | (read_u16_le(&bytes[2..]) as u32) << 16
+}
+
+fn sniff_text(data: &[u8], truncated: bool) -> Option<String> {
+    if data.starts_with(b"\xFF\xFE") {
+        return Some("text/plain; charset=utf-16le".to_owned());
+    } else if data.starts_with(b"\xFE\xFF") {
+        return Some("text/plain; charset=utf-16be".to_owned());
+    }
+
+    let data =
+        if data.starts_with(b"\xEF\xBB\xBF") { &data[3..] } else { data };
+
+    if data.iter().any(|&bch| is_binary_control(bch)) {
+        return None;
+    }
+
+    let charset =
+        if data.is_ascii() {
+            "us-ascii"
+        } else {
+            match str::from_utf8(data) {
+                Ok(_) => "utf-8",
+                // the input ended in the middle of a utf-8 sequence
+                Err(ref err) if truncated && err.error_len().is_none() => "utf-8",
+                // text without control characters, which isn't utf-8
+                Err(_) => "iso-8859-1"
+            }
+        };
+
+    Some(format!("{}; charset={}", text_media_type(data), charset))
+}
+
+/// determines the media type of (ascii compatible) text
+fn text_media_type(data: &[u8]) -> &'static str {
+    let start = data.iter()
+        .position(|bch| !bch.is_ascii_whitespace())
+        .unwrap_or(data.len());
+    let head = &data[start..];
+
+    if starts_with_ignore_case(head, b"<svg") {
+        "image/svg+xml"
+    } else if starts_with_ignore_case(head, b"<?xml") {
+        let prefix = &head[..head.len().min(4096)];
+        if contains(prefix, b"<svg") { "image/svg+xml" } else { "text/xml" }
+    } else if [&b"<!doctype html"[..], b"<html", b"<head", b"<body"].iter()
+        .any(|tag| starts_with_ignore_case(head, tag))
+    {
+        "text/html"
+    } else {
+        "text/plain"
+    }
+}
+
+/// control characters which (normally) do not appear in text files
+fn is_binary_control(bch: u8) -> bool {
+    match bch {
+        // \t, \n, \x0b, \x0c, \r
+        0x09..=0x0D => false,
+        // escape, used by some terminal formats
+        0x1B => false,
+        0x00..=0x1F | 0x7F => true,
+        _ => false
+    }
+}
+
+fn starts_with_ignore_case(data: &[u8], prefix: &[u8]) -> bool {
+    data.len() >= prefix.len() && data[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::sniff_bytes;
+
+    fn sniff(data: &[u8]) -> Option<String> {
+        sniff_bytes(data, false)
+    }
+
+    #[test]
+    fn sniff_magic_bytes() {
+        assert_eq!(sniff(b"%PDF-1.5\n%\xD0\xD4").unwrap(), "application/pdf; charset=binary");
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR").unwrap(), "image/png; charset=binary");
+        assert_eq!(sniff(b"\xFF\xD8\xFF\xE0\0\x10JFIF").unwrap(), "image/jpeg; charset=binary");
+        assert_eq!(sniff(b"GIF89a\x01\0\x01\0").unwrap(), "image/gif; charset=binary");
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 ").unwrap(), "image/webp; charset=binary");
+    }
+
+    #[test]
+    fn sniff_zip_based_formats() {
+        assert_eq!(sniff(b"PK\x03\x04\x14\0\0\0").unwrap(), "application/zip; charset=binary");
+
+        let docx = b"PK\x03\x04\x14\0\x06\0\x08\0\0\0!\0\0\0\0\0\0\0\0\0\0\0\0\0\x13\0\0\0\
+            [Content_Types].xml\0\0PK\x03\x04\0\0word/document.xml";
+        assert_eq!(
+            sniff(docx).unwrap(),
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document; charset=binary"
+        );
+
+        // size: 39 (0x27), file name length: 8, extra field length: 0
+        let odt = b"PK\x03\x04\x14\0\0\0\0\0\0\0\0\0\0\0\0\0\x27\0\0\0\x27\0\0\0\x08\0\0\0\
+            mimetypeapplication/vnd.oasis.opendocument.textPK\x03\x04";
+        assert_eq!(sniff(odt).unwrap(), "application/vnd.oasis.opendocument.text; charset=binary");
+    }
+
+    #[test]
+    fn sniff_text_charsets() {
+        assert_eq!(sniff(b"This is ascii\n").unwrap(), "text/plain; charset=us-ascii");
+        assert_eq!(sniff("This \u{2190} is utf8\n".as_bytes()).unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(sniff(b"caf\xE9\n").unwrap(), "text/plain; charset=iso-8859-1");
+        assert_eq!(sniff(b"\xFF\xFEa\0").unwrap(), "text/plain; charset=utf-16le");
+    }
+
+    #[test]
+    fn truncated_utf8_is_still_utf8() {
+        let data = "ab\u{2190}".as_bytes();
+        let truncated = &data[..data.len() - 1];
+        assert_eq!(sniff_bytes(truncated, true).unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(sniff_bytes(truncated, false).unwrap(), "text/plain; charset=iso-8859-1");
+    }
+
+    #[test]
+    fn sniff_markup() {
+        assert_eq!(sniff(b"\n<!DOCTYPE html>\n<html>").unwrap(), "text/html; charset=us-ascii");
+        assert_eq!(sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>").unwrap(),
+            "image/svg+xml; charset=us-ascii");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>\n<svg/>").unwrap(), "image/svg+xml; charset=us-ascii");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>\n<doc/>").unwrap(), "text/xml; charset=us-ascii");
+    }
+
+    #[test]
+    fn unknown_binary_is_not_sniffed() {
+        assert_eq!(sniff(b"\0\x01\x02\x03"), None);
+    }
+}
@@ -1,6 +1,7 @@
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
-use std::fs::DirEntry;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, DirEntry};
+use std::borrow::Cow;
 
 use vec1::Vec1;
 
@@ -8,11 +9,18 @@ use mail::context::Source;
 use mail::{Resource, IRI};
 
 use ::error::{CreatingSpecError, CreatingSpecErrorVariant};
-use ::utils::{new_string_path, new_str_path};
-use ::{TemplateSpec, SubTemplateSpec};
-use ::settings::{LoadSpecSettings, Type};
+//circular dependency (spec <-> report) but ok here
+use ::report::{CreationReport, SpecFailure};
+use ::utils::{new_string_path, new_str_path, has_full_type};
+use ::locale::{Locale, localized_template_id, split_localized_template_id};
+use ::{TemplateSpec, SubTemplateSpec, HeaderTemplate, Sample};
+use ::settings::{
+    LoadSpecSettings, Type,
+    ATTACHMENTS_DIR_NAME, SAMPLES_DIR_NAME, SUBJECT_FILE_NAME, HEADERS_FILE_NAME,
+    STYLESHEET_FILE_NAME, RESERVED_DIR_NAMES
+};
 
-//TODO missing global template level embeddings
+use super::manifest::{Manifest, BodyManifest, MANIFEST_FILE_NAME, split_media_type};
 
 pub(crate) fn from_dirs(
     templates_dir: &Path,
@@ -20,57 +28,389 @@ pub(crate) fn from_dirs(
 ) -> Result<Vec<(String, TemplateSpec)>, CreatingSpecError>
 {
     let mut specs = Vec::new();
+    for (dir, id) in template_dirs(templates_dir)? {
+        specs.push((id?, TemplateSpec::from_dir(dir, settings)?));
+    }
+    Ok(specs)
+}
+
+pub(crate) fn from_dirs_with_report(
+    templates_dir: &Path,
+    settings: &LoadSpecSettings
+) -> Result<CreationReport, CreatingSpecError>
+{
+    let mut report = CreationReport::default();
+    for (dir, id) in template_dirs(templates_dir)? {
+        let result = id.and_then(|id| {
+            TemplateSpec::from_dir(&dir, settings).map(|spec| (id, spec))
+        });
+        match result {
+            Ok(spec) => report.specs.push(spec),
+            Err(error) => report.failures.push(SpecFailure { dir: dir.into(), error })
+        }
+    }
+    Ok(report)
+}
+
+/// returns the path of each template dir in `templates_dir` together with its id
+///
+/// Localized variants are included with the id `<id>.<locale>`, this are
+/// dirs named e.g. `welcome.de-AT` as well as locale folders in a template
+/// dir like `welcome/de-AT` (if listed in the manifest). A template dir only containing locale folders
+/// (and reserved folders) is not a template dir itself.
+///
+/// Only failing to read `templates_dir` itself is returned as error, failing
+/// to read one of its entries is returned as the id of the entry.
+fn template_dirs(templates_dir: &Path)
+    -> Result<Vec<(PathBuf, Result<String, CreatingSpecError>)>, CreatingSpecError>
+{
+    let mut dirs = Vec::new();
     for entry in templates_dir.read_dir()? {
-        let entry = entry?;
-        if entry.metadata()?.is_dir() {
-            let id = entry.file_name()
-                .into_string()
-                .map_err(|file_name| CreatingSpecErrorVariant::NonStringPath(file_name.into()))?;
+        // failures are returned with the dir instead of aborting, so that a
+        // report can still list all other template dirs
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                dirs.push((templates_dir.to_owned(), Err(err.into())));
+                continue;
+            }
+        };
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(metadata) => if !metadata.is_dir() { continue; },
+            Err(err) => {
+                dirs.push((path, Err(err.into())));
+                continue;
+            }
+        }
+        let file_name = match entry.file_name().into_string() {
+            Ok(file_name) => file_name,
+            Err(file_name) => {
+                let err = CreatingSpecErrorVariant::NonStringPath(file_name.into()).into();
+                dirs.push((path, Err(err)));
+                continue;
+            }
+        };
+
+        let id = match split_localized_template_id(&file_name) {
+            Some((id, locale)) => localized_template_id(id, &locale),
+            None => file_name
+        };
 
-            specs.push((id, TemplateSpec::from_dir(entry.path(), settings)?));
+        let (locale_dirs, has_sub_templates) = locale_dirs(&path);
+        for (locale_path, locale) in locale_dirs {
+            dirs.push((locale_path, Ok(localized_template_id(&id, &locale))));
+        }
+        if has_sub_templates {
+            dirs.push((path, Ok(id)));
         }
     }
-    Ok(specs)
+    // make the order (and with it e.g. which error is returned first) deterministic
+    dirs.sort_by(|left, right| left.0.cmp(&right.0));
+    Ok(dirs)
+}
+
+/// returns the locale folders in the template dir and if it (might) contain sub-templates
+///
+/// If the dir or its manifest can not be read it is assumed to contain
+/// sub-templates, so that `from_dir` reports the error.
+fn locale_dirs(template_dir: &Path) -> (Vec<(PathBuf, Locale)>, bool) {
+    let manifest = match Manifest::load(template_dir) {
+        Ok(manifest) => manifest.unwrap_or_default(),
+        Err(_) => return (Vec::new(), true)
+    };
+    let entries = match template_dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return (Vec::new(), true)
+    };
+
+    let mut locale_dirs = Vec::new();
+    let mut has_sub_templates = false;
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => return (Vec::new(), true)
+        };
+        if !entry.path().is_dir() {
+            continue;
+        }
+        match entry.file_name().into_string() {
+            Ok(ref name) if RESERVED_DIR_NAMES.contains(&&**name) => {},
+            Ok(ref name) if is_locale_dir(name, &manifest) => {
+                // UNWRAP_SAFE: the manifest validation checks that it is a valid locale
+                locale_dirs.push((entry.path(), Locale::parse_opt(name).unwrap()));
+            },
+            _ => has_sub_templates = true
+        }
+    }
+    let has_sub_templates = has_sub_templates || locale_dirs.is_empty();
+    (locale_dirs, has_sub_templates)
+}
+
+/// returns true if the sub-folder with the given name contains a localized variant of the template
+///
+/// Locale folders are opt-in, they have to be listed in the `locales` of the
+/// manifest, so that e.g. a `img/` folder is not mistaken for a locale.
+fn is_locale_dir(name: &str, manifest: &Manifest) -> bool {
+    manifest.locales.iter().any(|locale| locale == name)
 }
 
 pub(crate) fn from_dir(base_path: &Path, settings: &LoadSpecSettings) -> Result<TemplateSpec, CreatingSpecError> {
+    let manifest = Manifest::load(base_path)?.unwrap_or_default();
+    let embedding_names = manifest.embedding_names();
+
+    let mut used_files = HashSet::new();
     let mut glob_embeddings = HashMap::new();
+    let mut attachments = Vec::new();
+    let mut samples = Vec::new();
+    let mut subject = None;
+    let mut headers = None;
+    let mut stylesheet = None;
     let mut sub_template_dirs = Vec::new();
+    let mut locale_folders = Vec::new();
     for folder in base_path.read_dir()? {
         let entry = folder?;
-        if entry.file_type()?.is_dir() {
-            let type_name = entry.file_name()
-                .into_string().map_err(|_| CreatingSpecErrorVariant::NonStringPath(entry.path().into()))?;
-            let (prio, type_) = settings.get_type_with_priority(&*type_name)
-                .ok_or_else(|| CreatingSpecErrorVariant::MissingTypeInfo { type_name: type_name.clone() })?;
-            sub_template_dirs.push((prio, entry.path(), type_));
+        let file_name = entry.file_name()
+            .into_string().map_err(|_| CreatingSpecErrorVariant::NonStringPath(entry.path().into()))?;
+        if entry.file_type()?.is_dir() && file_name == ATTACHMENTS_DIR_NAME {
+            for (name, resource) in attachments_from_dir(&entry.path(), settings)? {
+                attachments.push((format!("{}/{}", ATTACHMENTS_DIR_NAME, name), resource));
+            }
+        } else if entry.file_type()?.is_dir() && file_name == SAMPLES_DIR_NAME {
+            samples = samples_from_dir(&entry.path())?;
+        } else if entry.file_type()?.is_dir() && is_locale_dir(&file_name, &manifest) {
+            locale_folders.push(file_name);
+            // localized variants are separate specs (see `from_dirs`)
+            continue;
+        } else if entry.file_type()?.is_dir() {
+            let prio = sub_template_priority(&file_name, &manifest, settings)?;
+            sub_template_dirs.push((prio, entry.path(), file_name));
+        } else if file_name == MANIFEST_FILE_NAME {
+            continue;
+        } else if file_name == SUBJECT_FILE_NAME {
+            subject = Some(plain_text_template(entry.path())?);
+        } else if file_name == HEADERS_FILE_NAME {
+            headers = Some(header_templates(&entry.path())?);
+        } else if file_name == STYLESHEET_FILE_NAME {
+            stylesheet = Some(fs::read_to_string(entry.path())?);
+        } else if manifest.attachments.contains(&file_name) {
+            attachments.push((file_name.clone(), resource_from_path(entry.path(), settings)?));
+            used_files.insert(file_name);
         } else {
-            let (name, resource_spec) = embedding_from_path(entry.path(), settings)?;
-            glob_embeddings.insert(name, resource_spec);
+            let name = embedding_names.get(&*file_name).map(|name| (*name).to_owned());
+            let (name, resource) = embedding_from_path(entry.path(), name, settings)?;
+            insert_embedding(&mut glob_embeddings, name, resource)?;
+            used_files.insert(file_name);
         }
     }
 
+    check_files_exist(base_path, manifest.attachments.iter().chain(manifest.embeddings.values()), &used_files)?;
+    check_sub_templates_exist(&manifest, &sub_template_dirs)?;
+    check_locales_exist(&manifest, &locale_folders)?;
+
     sub_template_dirs.sort_by_key(|data| data.0);
 
     let mut sub_specs = Vec::with_capacity(sub_template_dirs.len());
-    for (_, dir_path, type_) in sub_template_dirs {
-        sub_specs.push(sub_template_from_dir(&*dir_path, type_, settings)?);
+    for (_, dir_path, type_name) in sub_template_dirs {
+        let body = manifest.body(&type_name);
+        sub_specs.push(sub_template_from_dir(&*dir_path, &type_name, body, settings)?);
     }
 
     let sub_specs = Vec1::from_vec(sub_specs)
         .map_err(|_| CreatingSpecErrorVariant::NoSubTemplatesFound { dir: base_path.into() })?;
-    TemplateSpec::new_with_embeddings_and_base_path(
-        sub_specs, glob_embeddings, base_path.to_owned())
+    let mut spec = TemplateSpec::new_with_embeddings_and_base_path(
+        sub_specs, glob_embeddings, base_path.to_owned())?;
+
+    // keep the attachment order independent of the order `read_dir` returns entries in,
+    // sorting by the path relative to the template folder
+    attachments.sort_by(|left, right| left.0.cmp(&right.0));
+    spec.attachments_mut().extend(attachments.into_iter().map(|(_, resource)| resource));
+    *spec.samples_mut() = samples;
+    spec.set_subject(subject);
+    spec.set_headers(headers.unwrap_or_default());
+    spec.set_stylesheet(stylesheet);
+
+    if settings.generates_text_from_html() {
+        let has_type = |full_type| spec.sub_specs().iter()
+            .any(|sub_spec| has_full_type(sub_spec.media_type(), full_type));
+        let generate = has_type("text/html") && !has_type("text/plain");
+        spec.set_generate_text_from_html(generate);
+    }
+    Ok(spec)
 }
 
+/// creates a sub-template for a text which is not a body, like the subject
+fn plain_text_template(path: PathBuf) -> Result<SubTemplateSpec, CreatingSpecError> {
+    let type_ = Type::new("text", "plain", vec1![ ".txt".to_owned() ], Some("utf-8".to_owned()));
+    let media_type = type_.to_media_type_for(&path)?;
+    SubTemplateSpec::new(path, media_type, HashMap::new())
+}
+
+/// reads the headers template file and creates a template for each header value
+fn header_templates(path: &Path) -> Result<Vec<HeaderTemplate>, CreatingSpecError> {
+    let source = fs::read_to_string(path)?;
+    HeaderTemplate::from_lines(new_str_path(&path)?, &source)
+}
+
+/// returns a sample for each `.json`/`.toml` file in the given folder (sorted by name)
+///
+/// Other files, like the `<name>.expected.<ext>` files containing
+/// expected outputs, are ignored.
+fn samples_from_dir(dir: &Path) -> Result<Vec<Sample>, CreatingSpecError> {
+    let mut samples = Vec::new();
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let is_data_file = path.extension()
+            .map(|extension| extension == "json" || extension == "toml")
+            .unwrap_or(false);
+        if !is_data_file {
+            continue;
+        }
+        let name = new_string_path(path.file_stem().unwrap_or_default())?;
+        // expected outputs (`<sample>.expected.<ext>`) of e.g. json bodies
+        if name.ends_with(".expected") || name.contains(".expected.") {
+            continue;
+        }
+        samples.push(Sample::new(name, &path));
+    }
+    samples.sort_by(|left, right| left.name().cmp(right.name()));
+    Ok(samples)
+}
 
-fn sub_template_from_dir(dir: &Path, type_: &Type, settings: &LoadSpecSettings)
-    -> Result<SubTemplateSpec, CreatingSpecError>
+/// creates a attachment for each file in the given folder
+fn attachments_from_dir(dir: &Path, settings: &LoadSpecSettings)
+    -> Result<Vec<(String, Resource)>, CreatingSpecError>
 {
-    let FindResult { template_file, other_files:embeddings } = find_files(dir, settings)?;
+    let mut attachments = Vec::new();
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let file_name = new_string_path(entry.file_name())?;
+        attachments.push((file_name, resource_from_path(entry.path(), settings)?));
+    }
+    Ok(attachments)
+}
+
+/// returns the priority of the sub-template in the given folder
+///
+/// If the manifest specifies a order the priority is the index in it,
+/// else the priority of the type with the same name in the settings is used.
+fn sub_template_priority(type_name: &str, manifest: &Manifest, settings: &LoadSpecSettings)
+    -> Result<usize, CreatingSpecError>
+{
+    if let Some(order) = manifest.order.as_ref() {
+        order.iter().position(|name| name == type_name)
+            .ok_or_else(|| CreatingSpecErrorVariant::ManifestUnlistedSubTemplate {
+                name: type_name.to_owned()
+            }.into())
+    } else {
+        settings.get_priority_idx(type_name)
+            .ok_or_else(|| CreatingSpecErrorVariant::MissingTypeInfo {
+                type_name: type_name.to_owned()
+            }.into())
+    }
+}
+
+/// returns the type for the sub-template in the given folder
+///
+/// The type from the settings can be overridden through the manifest, in which
+/// case the settings do not need to contain a type for the folder name.
+fn sub_template_type<'a>(
+    type_name: &str,
+    body: Option<&BodyManifest>,
+    template_file: &Path,
+    settings: &'a LoadSpecSettings
+) -> Result<Cow<'a, Type>, CreatingSpecError> {
+    let settings_type = settings.get_type(type_name);
+    let mut type_ =
+        if let Some(media_type) = body.and_then(|body| body.media_type.as_ref()) {
+            let (base_type, base_subtype) = split_media_type(media_type)?;
+            let os_file_name = template_file.file_name().unwrap_or_default();
+            let file_name = new_str_path(&os_file_name)?;
+            // the template file name starts with "mail." so there is always a suffix
+            let suffix = file_name.splitn(2, ".").nth(1).unwrap_or("");
+            let mut type_ = Type::new(
+                base_type, base_subtype,
+                vec1![ format!(".{}", suffix) ],
+                settings_type.and_then(|type_| type_.charset()).map(|charset| charset.to_owned())
+            );
+            type_.set_format_flowed(settings_type.and_then(Type::format_flowed));
+            Cow::Owned(type_)
+        } else {
+            Cow::Borrowed(settings_type
+                .ok_or_else(|| CreatingSpecErrorVariant::MissingTypeInfo { type_name: type_name.to_owned() })?)
+        };
+
+    if let Some(charset) = body.and_then(|body| body.charset.as_ref()) {
+        type_.to_mut().set_charset(Some(charset.clone()));
+    }
+    Ok(type_)
+}
+
+fn check_locales_exist(manifest: &Manifest, folders: &[String]) -> Result<(), CreatingSpecError> {
+    for name in manifest.locales.iter() {
+        if !folders.contains(name) {
+            return Err(CreatingSpecErrorVariant::ManifestUnknownLocale { name: name.clone() }.into());
+        }
+    }
+    Ok(())
+}
+
+fn check_sub_templates_exist(manifest: &Manifest, dirs: &[(usize, PathBuf, String)])
+    -> Result<(), CreatingSpecError>
+{
+    let referenced = manifest.order.iter().flat_map(|order| order.iter())
+        .chain(manifest.bodies.keys());
+
+    for name in referenced {
+        if !dirs.iter().any(|&(_, _, ref type_name)| type_name == name) {
+            return Err(CreatingSpecErrorVariant::ManifestUnknownSubTemplate { name: name.clone() }.into());
+        }
+    }
+    Ok(())
+}
+
+fn check_files_exist<'a>(
+    dir: &Path,
+    referenced: impl Iterator<Item=&'a String>,
+    used_files: &HashSet<String>
+) -> Result<(), CreatingSpecError> {
+    for file in referenced {
+        if !used_files.contains(file) {
+            return Err(CreatingSpecErrorVariant::ManifestMissingFile { file: dir.join(file).into() }.into());
+        }
+    }
+    Ok(())
+}
+
+fn sub_template_from_dir(
+    dir: &Path,
+    type_name: &str,
+    body: Option<&BodyManifest>,
+    settings: &LoadSpecSettings
+) -> Result<SubTemplateSpec, CreatingSpecError> {
+    let embedding_names = body.map(|body| body.embedding_names()).unwrap_or_default();
+    let FindResult { template_file, other_files:embeddings } = find_files(dir, &embedding_names, settings)?;
+    if let Some(body) = body {
+        let used_files = embeddings.values()
+            .map(|&(ref file_name, _)| file_name.clone())
+            .collect();
+        check_files_exist(dir, body.embeddings.values(), &used_files)?;
+    }
+    let type_ = sub_template_type(type_name, body, &template_file, settings)?;
     let media_type = type_.to_media_type_for(&template_file)?;
+    let embeddings = embeddings.into_iter()
+        .map(|(name, (_, resource))| (name, resource))
+        .collect();
 
-    SubTemplateSpec::new(template_file, media_type, embeddings)
+    let mut sub_spec = SubTemplateSpec::new(template_file, media_type, embeddings)?;
+    if type_.is_format_flowed() {
+        sub_spec.set_flowed_width(type_.format_flowed());
+    }
+    Ok(sub_spec)
 }
 
 
@@ -83,11 +423,12 @@ fn is_template_file(entry: &DirEntry) -> bool {
 
 struct FindResult {
     template_file: PathBuf,
-    other_files: HashMap<String, Resource>,
+    /// `in-template name => (file name, resource)`
+    other_files: HashMap<String, (String, Resource)>,
 
 }
 
-fn find_files(in_dir: &Path, settings: &LoadSpecSettings)
+fn find_files(in_dir: &Path, embedding_names: &HashMap<&str, &str>, settings: &LoadSpecSettings)
     -> Result<FindResult, CreatingSpecError>
 {
     use std::collections::hash_map::Entry::*;
@@ -103,12 +444,14 @@ fn find_files(in_dir: &Path, settings: &LoadSpecSettings)
                 return Err(CreatingSpecErrorVariant::MultipleTemplateFiles { dir: in_dir.into() }.into());
             }
         } else {
-            let (key, value) = embedding_from_path(entry.path(), settings)?;
+            let file_name = new_string_path(entry.file_name())?;
+            let name = embedding_names.get(&*file_name).map(|name| (*name).to_owned());
+            let (key, value) = embedding_from_path(entry.path(), name, settings)?;
              match other_files.entry(key) {
                 Occupied(oe) => {
                     return Err(CreatingSpecErrorVariant::DuplicateEmbeddingName { name: oe.key().clone() }.into());
                 },
-                Vacant(ve) => {ve.insert(value);}
+                Vacant(ve) => {ve.insert((file_name, value));}
             }
         }
     }
@@ -123,25 +466,56 @@ fn find_files(in_dir: &Path, settings: &LoadSpecSettings)
     }
 }
 
-fn embedding_from_path(path: PathBuf, settings: &LoadSpecSettings)
+fn insert_embedding(embeddings: &mut HashMap<String, Resource>, name: String, resource: Resource)
+    -> Result<(), CreatingSpecError>
+{
+    use std::collections::hash_map::Entry::*;
+    match embeddings.entry(name) {
+        Occupied(oe) => {
+            Err(CreatingSpecErrorVariant::DuplicateEmbeddingName { name: oe.key().clone() }.into())
+        },
+        Vacant(ve) => {
+            ve.insert(resource);
+            Ok(())
+        }
+    }
+}
+
+/// creates a embedding from the given file
+///
+/// If no name is given the file name up to the first "." is used as name.
+fn embedding_from_path(path: PathBuf, name: Option<String>, settings: &LoadSpecSettings)
                        -> Result<(String, Resource), CreatingSpecError>
+{
+    let name =
+        if let Some(name) = name {
+            name
+        } else {
+            let file_name = new_string_path(
+                path.file_name()
+                // UNWRAP_SAFE: file_name returns the file (,dir,symlink) name which
+                // has to exist for a dir_entry
+                .unwrap())?;
+
+            file_name.split(".")
+                .next()
+                //UNWRAP_SAFE: Split iterator has always at last one element
+                .unwrap()
+                .to_owned()
+        };
+
+    let resource = resource_from_path(path, settings)?;
+
+    Ok((name, resource))
+}
+
+fn resource_from_path(path: PathBuf, settings: &LoadSpecSettings)
+    -> Result<Resource, CreatingSpecError>
 {
     if !path.is_file() {
         return Err(CreatingSpecErrorVariant::NotAFile(path.into()).into());
     }
 
-    let file_name = new_string_path(
-        path.file_name()
-        // UNWRAP_SAFE: file_name returns the file (,dir,symlink) name which
-        // has to exist for a dir_entry
-        .unwrap())?;
-
-    let name = file_name.split(".")
-        .next()
-        //UNWRAP_SAFE: Split iterator has always at last one element
-        .unwrap()
-        .to_owned();
-
     //TODO we can remove the media type sniffing from here
     let media_type = settings.determine_media_type(&path)?;
 
@@ -151,9 +525,7 @@ fn embedding_from_path(path: PathBuf, settings: &LoadSpecSettings)
         use_media_type: Some(media_type)
     };
 
-    let resource = Resource::new(source);
-
-    Ok((name, resource))
+    Ok(Resource::new(source))
 }
 
 fn iri_from_path<IP: AsRef<Path> + Into<PathBuf>>(path: IP) -> Result<IRI, CreatingSpecError> {
@@ -0,0 +1,166 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, Component};
+use std::fs;
+
+use failure::Fail;
+use toml;
+
+use ::error::{CreatingSpecError, CreatingSpecErrorVariant};
+use ::locale::Locale;
+
+/// name of the (optional) manifest file in a template folder
+pub(crate) const MANIFEST_FILE_NAME: &str = "__spec__.toml";
+
+/// The parsed content of a `__spec__.toml` file.
+///
+/// All fields are optional, a missing manifest behaves exactly
+/// like an empty one.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Manifest {
+    /// names of the sub-template folders to use, lowest priority first
+    #[serde(default)]
+    pub order: Option<Vec<String>>,
+
+    /// template level embeddings (`in-template name => file name`)
+    #[serde(default)]
+    pub embeddings: HashMap<String, String>,
+
+    /// file names of files in the template folder which are attachments
+    #[serde(default)]
+    pub attachments: Vec<String>,
+
+    /// sub-template specific settings (`folder name => settings`)
+    #[serde(default)]
+    pub bodies: HashMap<String, BodyManifest>,
+
+    /// names of the sub-folders containing localized variants (e.g. `de-AT`)
+    #[serde(default)]
+    pub locales: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BodyManifest {
+    /// media type of the body in the form `type/subtype`
+    pub media_type: Option<String>,
+
+    /// charset of the body, overrides the charset from the settings
+    pub charset: Option<String>,
+
+    /// sub-template specific embeddings (`in-template name => file name`)
+    #[serde(default)]
+    pub embeddings: HashMap<String, String>,
+}
+
+impl Manifest {
+
+    /// loads and validates the manifest in `template_dir` if there is one
+    pub(crate) fn load(template_dir: &Path) -> Result<Option<Manifest>, CreatingSpecError> {
+        let path = template_dir.join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)?;
+        let manifest: Manifest = toml::from_str(&content)
+            .map_err(|err| err.context(CreatingSpecErrorVariant::InvalidManifest { file: path.into() }))?;
+        manifest.validate()?;
+        Ok(Some(manifest))
+    }
+
+    fn validate(&self) -> Result<(), CreatingSpecError> {
+        if let Some(order) = self.order.as_ref() {
+            let mut seen = HashSet::new();
+            for name in order {
+                if !seen.insert(name) {
+                    return Err(CreatingSpecErrorVariant::ManifestDuplicateSubTemplate {
+                        name: name.clone()
+                    }.into());
+                }
+            }
+        }
+
+        // a file can either be an attachment or (one) embedding
+        let mut used_files = HashSet::new();
+        for file in self.attachments.iter().chain(self.embeddings.values()) {
+            check_file_name(file)?;
+            if !used_files.insert(file) {
+                return Err(CreatingSpecErrorVariant::ManifestDuplicateFileUse { file: file.clone() }.into());
+            }
+        }
+
+        for body in self.bodies.values() {
+            body.validate()?;
+        }
+
+        let mut seen = HashSet::new();
+        for name in self.locales.iter() {
+            let is_sub_template = self.bodies.contains_key(name)
+                || self.order.iter().flat_map(|order| order.iter()).any(|type_name| type_name == name);
+            if Locale::parse_opt(name).is_none() || is_sub_template || !seen.insert(name) {
+                return Err(CreatingSpecErrorVariant::ManifestInvalidLocale { name: name.clone() }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// returns a `file name => in-template name` mapping for the template level embeddings
+    pub(crate) fn embedding_names(&self) -> HashMap<&str, &str> {
+        invert(&self.embeddings)
+    }
+
+    pub(crate) fn body(&self, name: &str) -> Option<&BodyManifest> {
+        self.bodies.get(name)
+    }
+}
+
+impl BodyManifest {
+
+    fn validate(&self) -> Result<(), CreatingSpecError> {
+        if let Some(media_type) = self.media_type.as_ref() {
+            split_media_type(media_type)?;
+        }
+
+        let mut used_files = HashSet::new();
+        for file in self.embeddings.values() {
+            check_file_name(file)?;
+            if !used_files.insert(file) {
+                return Err(CreatingSpecErrorVariant::ManifestDuplicateFileUse { file: file.clone() }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// returns a `file name => in-template name` mapping for the embeddings
+    pub(crate) fn embedding_names(&self) -> HashMap<&str, &str> {
+        invert(&self.embeddings)
+    }
+}
+
+/// splits a `type/subtype` media type into it's type and subtype
+pub(crate) fn split_media_type(media_type: &str) -> Result<(&str, &str), CreatingSpecError> {
+    let mut parts = media_type.splitn(2, "/");
+    //UNWRAP_SAFE: Split iterator has always at last one element
+    let type_ = parts.next().unwrap().trim();
+    let subtype = parts.next().map(str::trim).unwrap_or("");
+    if type_.is_empty() || subtype.is_empty() || subtype.contains(";") {
+        Err(CreatingSpecErrorVariant::ManifestInvalidMediaType { media_type: media_type.to_owned() }.into())
+    } else {
+        Ok((type_, subtype))
+    }
+}
+
+/// files referenced in a manifest have to be directly in the folder they are used in
+fn check_file_name(file: &str) -> Result<(), CreatingSpecError> {
+    let mut components = Path::new(file).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(CreatingSpecErrorVariant::ManifestInvalidFileName { file: file.to_owned() }.into())
+    }
+}
+
+fn invert(name_to_file: &HashMap<String, String>) -> HashMap<&str, &str> {
+    name_to_file.iter()
+        .map(|(name, file)| (&**file, &**name))
+        .collect()
+}
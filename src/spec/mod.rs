@@ -7,11 +7,15 @@ use vec1::Vec1;
 use mail::Resource;
 use headers::components::MediaType;
 
-use ::error::CreatingSpecError;
+use ::error::{CreatingSpecError, CreatingSpecErrorVariant};
+//circular dependency (spec <-> report) but ok here
+use ::report::CreationReport;
 use ::utils::{new_string_path, check_string_path};
+use ::header_lines::parse_header_lines;
 use ::settings::LoadSpecSettings;
 
 mod from_dir;
+mod manifest;
 
 /// A type representing a (mail) Template
 ///
@@ -29,7 +33,7 @@ mod from_dir;
 ///
 /// - It also has an optional `base_path` which is
 ///   the root folder it was loaded from using `from_dir`.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TemplateSpec {
     /// the `base_path` which was used to construct the template from,
     /// e.g. with `TemplateSpec::from_dir` and which is used for reloading
@@ -39,7 +43,17 @@ pub struct TemplateSpec {
     /// template level embeddings, i.e. embeddings shared between alternative bodies
     embeddings: HashMap<String, Resource>,
     /// attachments to always add if this template is used
-    attachments: Vec<Resource>
+    attachments: Vec<Resource>,
+    /// sample data files e.g. used for snapshot testing
+    samples: Vec<Sample>,
+    /// template for the subject of the mail
+    subject: Option<SubTemplateSpec>,
+    /// templates for additional headers of the mail
+    headers: Vec<HeaderTemplate>,
+    /// css rules to inline into html bodies
+    stylesheet: Option<String>,
+    /// generate a `text/plain` body from the `text/html` body when rendering
+    text_from_html: bool
 }
 
 impl TemplateSpec {
@@ -59,9 +73,32 @@ impl TemplateSpec {
     /// Additional files in the templates folder are interpreted
     /// as additional non body specific embeddings.
     ///
-    /// Currently the implementation is slightly limited, in the
-    /// future it should be extended to allow some configuration
-    /// through something like `__spec__.toml` in the templates folder.
+    /// The sub-folder `attachments` is reserved, it is not used as
+    /// a sub-template but each file in it is added as an attachment
+    /// which is always attached when the template is used.
+    ///
+    /// A `subject.txt` file in the templates folder is not used as embedding,
+    /// instead it is used as template for the subject of the mail, which is
+    /// rendered with the same render engine and data as the bodies.
+    /// Similar a `headers.txt` file is used for additional headers, it has
+    /// to consist of lines of the form `Name: value` where each value is
+    /// a template (see `HeaderTemplate`).
+    ///
+    /// A `style.css` file in the templates folder is not used as embedding
+    /// either, it's content becomes the `stylesheet` of the template, which
+    /// is inlined into `text/html` bodies if the render engine is configured
+    /// to do so (see `RenderTemplateEngine::set_inline_css`).
+    ///
+    /// The sub-folder `samples` is reserved too, each `.json` or `.toml`
+    /// file in it is exposed as a `Sample` through `samples()`, all other
+    /// files in it (e.g. expected outputs) are ignored.
+    ///
+    /// Sub-folders listed in the `locales` of the manifest (e.g. `de` or
+    /// `de-AT`) are skipped, they contain localized variants of the
+    /// template which are created as separate specs by `from_dirs`.
+    ///
+    /// This can be configured through an optional `__spec__.toml`
+    /// manifest in the templates folder (see below).
     ///
     /// # Example
     ///
@@ -71,11 +108,20 @@ impl TemplateSpec {
     /// ```no_rust
     /// templates/
     ///  templateA/
+    ///   subject.txt
+    ///   headers.txt
+    ///   style.css
     ///   html/
     ///     mail.html
     ///     emb_logo.png
     ///   text/
     ///     mail.text
+    ///   attachments/
+    ///     terms_of_service.pdf
+    ///   samples/
+    ///     new_customer.json
+    ///     new_customer.expected.html
+    ///     new_customer.expected.text
     /// ```
     ///
     /// # Uniqueness of names
@@ -97,6 +143,39 @@ impl TemplateSpec {
     /// This is also needed as the used render template engine might not
     /// support names containing a ".".
     ///
+    /// # The `__spec__.toml` manifest
+    ///
+    /// All fields of the manifest are optional:
+    ///
+    /// ```toml
+    /// # the sub-template folders to use, lowest priority first,
+    /// # if given it overrides the priorities from the settings
+    /// # and every sub-template folder has to be listed
+    /// order = ["text", "html"]
+    ///
+    /// # files in the template folder which are attachments,
+    /// # instead of shared embeddings
+    /// attachments = ["terms.pdf"]
+    ///
+    /// # custom names for shared embeddings (name = file)
+    /// [embeddings]
+    /// portfolio = "portfolio_2018.pdf"
+    ///
+    /// # overrides for a specific sub-template folder
+    /// [bodies.html]
+    /// # if given the folder name does not need to be known to the settings
+    /// media_type = "text/html"
+    /// charset = "utf-8"
+    ///
+    /// # custom names for sub-template specific embeddings (name = file)
+    /// [bodies.html.embeddings]
+    /// logo = "logo_small.png"
+    /// ```
+    ///
+    /// Files not mentioned in the manifest are handled as described above.
+    /// A malformed or inconsistent manifest (e.g. one referring to missing
+    /// files or folders) makes `from_dir` fail with a `Manifest*` or
+    /// `InvalidManifest` error variant.
     ///
     #[inline]
     pub fn from_dir<P>(base_path: P, settings: &LoadSpecSettings)
@@ -107,6 +186,27 @@ impl TemplateSpec {
     }
 
     /// Derive a template from each dir in the dir specified by `templates_dir`
+    ///
+    /// The dir name is used as id. Localized variants of a template are
+    /// returned with the id `<id>.<locale>` (see `localized_template_id`),
+    /// they can either be placed in a dir like `welcome.de-AT/` or in a
+    /// locale sub-folder like `welcome/de-AT/`. Locale sub-folders have to
+    /// be listed in the `locales` of the templates manifest:
+    ///
+    /// ```no_rust
+    /// templates/
+    ///  welcome/           => "welcome"
+    ///   __spec__.toml     (locales = ["de"])
+    ///   html/
+    ///   text/
+    ///   de/               => "welcome.de"
+    ///    html/
+    ///    text/
+    ///  welcome.de-AT/     => "welcome.de-AT"
+    ///   text/
+    /// ```
+    ///
+    /// A template dir containing only locale sub-folders is not a spec itself.
     pub fn from_dirs<P>(templates_dir: P, settings: &LoadSpecSettings)
         -> Result<Vec<(String, TemplateSpec)>, CreatingSpecError>
         where P: AsRef<Path>
@@ -114,6 +214,21 @@ impl TemplateSpec {
         self::from_dir::from_dirs(templates_dir.as_ref(), settings)
     }
 
+    /// Like `from_dirs` but does not stop at the first template dir which fails.
+    ///
+    /// The returned report contains all successfully created specs and
+    /// the error for each template dir from which no spec could be created.
+    ///
+    /// # Error
+    ///
+    /// Only if the `templates_dir` itself can not be read an error is returned.
+    pub fn from_dirs_with_report<P>(templates_dir: P, settings: &LoadSpecSettings)
+        -> Result<CreationReport, CreatingSpecError>
+        where P: AsRef<Path>
+    {
+        self::from_dir::from_dirs_with_report(templates_dir.as_ref(), settings)
+    }
+
     /// creates a new Template from a list of sub-templates (for alternate bodies)
     pub fn new(templates: Vec1<SubTemplateSpec>) -> Self {
         Self::new_with_embeddings(templates, Default::default())
@@ -127,7 +242,12 @@ impl TemplateSpec {
         TemplateSpec {
             base_path: None,
             templates, embeddings,
-            attachments: Vec::new()
+            attachments: Vec::new(),
+            samples: Vec::new(),
+            subject: None,
+            headers: Vec::new(),
+            stylesheet: None,
+            text_from_html: false
         }
     }
 
@@ -154,7 +274,12 @@ impl TemplateSpec {
         Ok(TemplateSpec {
             base_path: Some(path),
             templates, embeddings,
-            attachments: Vec::new()
+            attachments: Vec::new(),
+            samples: Vec::new(),
+            subject: None,
+            headers: Vec::new(),
+            stylesheet: None,
+            text_from_html: false
         })
     }
 
@@ -194,6 +319,160 @@ impl TemplateSpec {
         &mut self.attachments
     }
 
+    /// sample data files for this template (see `from_dir`)
+    pub fn samples(&self) -> &Vec<Sample> {
+        &self.samples
+    }
+
+    pub fn samples_mut(&mut self) -> &mut Vec<Sample> {
+        &mut self.samples
+    }
+
+    /// the template for the subject of the mail (see `from_dir`)
+    pub fn subject(&self) -> Option<&SubTemplateSpec> {
+        self.subject.as_ref()
+    }
+
+    pub fn set_subject(&mut self, subject: Option<SubTemplateSpec>) -> Option<SubTemplateSpec> {
+        replace(&mut self.subject, subject)
+    }
+
+    /// the templates for additional headers of the mail (see `from_dir`)
+    pub fn headers(&self) -> &[HeaderTemplate] {
+        &self.headers
+    }
+
+    pub fn set_headers(&mut self, headers: Vec<HeaderTemplate>) -> Vec<HeaderTemplate> {
+        replace(&mut self.headers, headers)
+    }
+
+    /// css rules to inline into the `text/html` bodies (see `from_dir`)
+    pub fn stylesheet(&self) -> Option<&str> {
+        self.stylesheet.as_ref().map(|css| &**css)
+    }
+
+    pub fn set_stylesheet(&mut self, stylesheet: Option<String>) -> Option<String> {
+        replace(&mut self.stylesheet, stylesheet)
+    }
+
+    /// true if a `text/plain` body is generated from the `text/html` body when rendering
+    ///
+    /// The generated body is inserted as the first (i.e. lowest priority)
+    /// alternative body. `from_dir` enables this if the settings ask for
+    /// it (see `LoadSpecSettings::set_generate_text_from_html`) and the
+    /// spec has a `text/html` but no `text/plain` sub-template.
+    pub fn generates_text_from_html(&self) -> bool {
+        self.text_from_html
+    }
+
+    pub fn set_generate_text_from_html(&mut self, generate: bool) {
+        self.text_from_html = generate
+    }
+
+    /// iterates over all templates a render engine has to load for this spec
+    ///
+    /// This are the sub-templates for the bodies followed by the subject
+    /// template and the templates of the header values (if there are any).
+    pub fn render_templates(&self) -> impl Iterator<Item=&SubTemplateSpec> {
+        self.templates.iter()
+            .chain(self.subject.iter())
+            .chain(self.headers.iter().map(HeaderTemplate::value))
+    }
+
+}
+
+/// A header of a template, only the value is a template.
+///
+/// The header name is fixed and each value is rendered on its own, so that
+/// rendered data (e.g. containing a line break) can not add other headers.
+#[derive(Debug, Clone)]
+pub struct HeaderTemplate {
+    name: String,
+    value: SubTemplateSpec
+}
+
+impl HeaderTemplate {
+
+    pub fn new<N>(name: N, value: SubTemplateSpec) -> Self
+        where N: Into<String>
+    {
+        HeaderTemplate { name: name.into(), value }
+    }
+
+    /// creates a header template for each `Name: value` line of `source`
+    ///
+    /// Empty lines are ignored, lines starting with whitespace continue the
+    /// value of the previous header and headers with an empty value are
+    /// omitted. The value templates use `<id>#<idx>` as id, where `idx`
+    /// is the index of the header.
+    ///
+    /// # Error
+    ///
+    /// If a line is neither empty, a continuation nor a valid header line
+    /// `CreatingSpecErrorVariant::InvalidHeaderLine` is returned.
+    pub fn from_lines(id: &str, source: &str) -> Result<Vec<Self>, CreatingSpecError> {
+        let lines = parse_header_lines(source)
+            .map_err(|line| CreatingSpecErrorVariant::InvalidHeaderLine { template: id.to_owned(), line })?;
+
+        // UNWRAP_SAFE: the media type is a valid constant
+        let media_type = MediaType::parse("text/plain; charset=utf-8").unwrap();
+        let headers = lines.into_iter()
+            .enumerate()
+            .map(|(idx, (name, value))| {
+                let source = TemplateSource::Source { id: format!("{}#{}", id, idx), content: value };
+                let value = SubTemplateSpec::new_with_template_source(source, media_type.clone(), HashMap::new());
+                HeaderTemplate::new(name, value)
+            })
+            .collect();
+        Ok(headers)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// the template of the header value
+    pub fn value(&self) -> &SubTemplateSpec {
+        &self.value
+    }
+}
+
+/// A sample data file (JSON or TOML) of a template
+///
+/// Samples are not used when rendering mails, but can be used to render
+/// a template with realistic data e.g. for snapshot testing it with
+/// `check_samples`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sample {
+    name: String,
+    path: PathBuf
+}
+
+impl Sample {
+
+    pub fn new<P>(name: String, path: P) -> Self
+        where P: AsRef<Path>
+    {
+        Sample { name, path: path.as_ref().to_owned() }
+    }
+
+    /// the name of the sample, i.e. the file name without extension
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// the path of the sample data file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// the path of the file containing the expected output for a body
+    ///
+    /// The file is placed next to the sample and named
+    /// `<name>.expected.<body_extension>`.
+    pub fn expected_output_path(&self, body_extension: &str) -> PathBuf {
+        self.path.with_file_name(format!("{}.expected.{}", self.name, body_extension))
+    }
 }
 
 /// A type representing the part of a template which represents a alternate mail body
@@ -202,7 +481,7 @@ impl TemplateSpec {
 /// a the content of an specific handlebars file) the media type which
 /// this alternate body should have, and a mappings of embeddings specific
 /// to this alternate body
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubTemplateSpec {
     media_type: MediaType,
     source: TemplateSource,
@@ -210,6 +489,8 @@ pub struct SubTemplateSpec {
     // resource spec use_name which would
     //  e.g. be logo.png but referring to the file long_logo_name.png
     embeddings: HashMap<String, Resource>,//todo use insert order keeping map
+    /// the line width the rendered body is reflowed to as `format=flowed`
+    flowed_width: Option<usize>
 }
 
 impl SubTemplateSpec {
@@ -232,7 +513,7 @@ impl SubTemplateSpec {
         media_type: MediaType,
         embeddings: HashMap<String, Resource>
     ) -> Self {
-        SubTemplateSpec { source, media_type, embeddings }
+        SubTemplateSpec { source, media_type, embeddings, flowed_width: None }
     }
 
     pub fn source(&self) -> &TemplateSource {
@@ -260,6 +541,18 @@ impl SubTemplateSpec {
         &mut self.embeddings
     }
 
+    /// the line width the rendered body is reflowed to, if it's a `format=flowed` body
+    ///
+    /// `from_dir` sets this from `Type::format_flowed`, the media type
+    /// should have a `format=flowed` parameter if this is set.
+    pub fn flowed_width(&self) -> Option<usize> {
+        self.flowed_width
+    }
+
+    pub fn set_flowed_width(&mut self, width: Option<usize>) -> Option<usize> {
+        replace(&mut self.flowed_width, width)
+    }
+
 }
 
 
@@ -1,6 +1,8 @@
 use failure::Backtrace;
 use tera_crate;
 
+use ::error::ProcessingError;
+
 
 #[derive(Debug, Fail)]
 pub enum TeraError {
@@ -15,6 +17,15 @@ pub enum TeraError {
     RenderError {
         kind: tera_crate::ErrorKind,
         backtrace: Backtrace
+    },
+
+    #[fail(display="{}", _0)]
+    Processing(#[cause] ProcessingError)
+}
+
+impl From<ProcessingError> for TeraError {
+    fn from(err: ProcessingError) -> Self {
+        TeraError::Processing(err)
     }
 }
 
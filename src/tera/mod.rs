@@ -8,6 +8,10 @@ use self::error::TeraError;
 
 pub mod error;
 
+/// Render Engine using Tera for rendering
+///
+/// It is not `Clone`, so with a `SharedRenderTemplateEngine` it has to be
+/// used with `update_with` (or `publish`) instead of `update`.
 pub struct TeraRenderEngine {
     tera: Tera
 }
@@ -52,6 +56,13 @@ impl TeraRenderEngine {
 
 }
 
+impl Default for TeraRenderEngine {
+    /// create a new TeraRenderEngine without any base templates
+    fn default() -> Self {
+        TeraRenderEngine { tera: Tera::default() }
+    }
+}
+
 impl RenderEngineBase for TeraRenderEngine {
     // nothing gurantees that the templates use \r\n, so by default fix newlines
     // but it can be disabled
@@ -75,7 +86,7 @@ impl RenderEngineBase for TeraRenderEngine {
 
     /// This can be used to reload a templates.
     fn unload_templates(&mut self, spec: &TemplateSpec) {
-        for sub_spec in spec.sub_specs() {
+        for sub_spec in spec.render_templates() {
             let id = sub_spec.source().id();
             self.tera.templates.remove(id);
         }
@@ -6,6 +6,7 @@ use headers::components::ContentId;
 use template::EmbeddedWithCId;
 
 use ::spec::{TemplateSpec, SubTemplateSpec};
+use ::error::ProcessingError;
 
 /// Trait implemented by any `RenderEngine`
 ///
@@ -22,7 +23,11 @@ pub trait RenderEngineBase {
 
     /// Error which can be produced when rendering a
     /// template (through the `RenderEngine` trait)
-    type RenderError: Fail;
+    ///
+    /// It is also the `TemplateEngine::Error` of a `RenderTemplateEngine`
+    /// using this engine, so it has to be able to represent errors which
+    /// are not caused by the render engine (e.g. a failing post-processor).
+    type RenderError: Fail + From<ProcessingError>;
 
     /// Error which can be produced when loading a
     /// template.
@@ -164,7 +169,7 @@ macro_rules! implement_load_helper {
     ) => ({
         let mut loaded = Vec::new();
 
-        for sub_spec in $spec.sub_specs() {
+        for sub_spec in $spec.render_templates() {
             match *sub_spec.source() {
                 TemplateSource::Path(ref path) => {
                     let $path = path;
@@ -0,0 +1,29 @@
+use ::error::ProcessingError;
+
+#[derive(Debug, Fail)]
+pub enum TypedError {
+
+    #[fail(display="unknown template id: {}", id)]
+    UnknownTemplateId { id: String },
+
+    #[fail(display="template id is used multiple times for different templates: {}", id)]
+    TemplateIdCollision { id: String },
+
+    #[fail(display="no compiled template was registered for template id: {}", id)]
+    NotCompiled { id: String },
+
+    #[fail(display="templates have to be compiled, can not load template from path: {}", path)]
+    PathNotSupported { path: String },
+
+    #[fail(display="the data does not implement the template: {}", id)]
+    NotImplementedByData { id: String },
+
+    #[fail(display="{}", _0)]
+    Processing(#[cause] ProcessingError)
+}
+
+impl From<ProcessingError> for TypedError {
+    fn from(err: ProcessingError) -> Self {
+        TypedError::Processing(err)
+    }
+}
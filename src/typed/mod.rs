@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+use ::traits::{RenderEngine, RenderEngineBase, AdditionalCIds};
+use ::spec::{TemplateSpec, SubTemplateSpec, TemplateSource};
+
+use self::error::TypedError;
+
+pub mod error;
+
+/// Trait implemented by data types which can render compiled templates
+///
+/// This is normally not implemented by hand but through the
+/// `typed_templates!` macro.
+pub trait TypedTemplates {
+
+    /// the ids of all templates implemented by this type
+    const TEMPLATE_IDS: &'static [&'static str];
+
+    /// renders the template with the given id
+    ///
+    /// Returns `None` if the type doesn't implement a template with the id.
+    fn render_template(&self, id: &str, cids: &AdditionalCIds) -> Option<String>;
+}
+
+/// Generates a trait with a method for each compiled template.
+///
+/// Each template is implemented by implementing the generated
+/// trait for the data type given after `for`. The methods have
+/// the signature `fn name(&self, cids: &AdditionalCIds) -> String`
+/// and are normally implemented using `format!` or similar, so
+/// accessing a field the data type doesn't have is a compiler
+/// error. Through the macro the data type also implements
+/// `TypedTemplates` (which makes it usable with the `TypedRenderEngine`),
+/// so each data type can only be used with one generated trait.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate mail_render_template_engine;
+/// # use mail_render_template_engine::AdditionalCIds;
+/// struct UserData { name: String }
+///
+/// typed_templates! {
+///     /// templates of the welcome mail
+///     pub trait WelcomeTemplates for UserData {
+///         "welcome.txt" => fn text_body;
+///         "welcome.html" => fn html_body;
+///     }
+/// }
+///
+/// impl WelcomeTemplates for UserData {
+///     fn text_body(&self, _cids: &AdditionalCIds) -> String {
+///         format!("Hy {}.", self.name)
+///     }
+///
+///     fn html_body(&self, _cids: &AdditionalCIds) -> String {
+///         format!("<p>Hy {}.</p>", self.name)
+///     }
+/// }
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! typed_templates {
+    (
+        $(#[$attr:meta])*
+        $vis:vis trait $name:ident for $data:ty {
+            $(
+                $(#[$fn_attr:meta])*
+                $id:literal => fn $fn_name:ident;
+            )*
+        }
+    ) => (
+        $(#[$attr])*
+        $vis trait $name {
+            $(
+                $(#[$fn_attr])*
+                fn $fn_name(&self, cids: &$crate::AdditionalCIds) -> String;
+            )*
+        }
+
+        impl $crate::typed::TypedTemplates for $data {
+            const TEMPLATE_IDS: &'static [&'static str] = &[ $($id),* ];
+
+            fn render_template(&self, id: &str, cids: &$crate::AdditionalCIds) -> Option<String> {
+                $(
+                    if id == $id {
+                        return Some(<Self as $name>::$fn_name(self, cids));
+                    }
+                )*
+                None
+            }
+        }
+    );
+}
+
+/// Render Engine for templates compiled into the program
+///
+/// Templates are implemented by data types through a trait generated
+/// with `typed_templates!`, so e.g. missing fields are compiler errors
+/// instead of render errors. Before a `TemplateSpec` using such templates
+/// can be loaded the data types implementing them have to be registered
+/// with `register_templates`.
+///
+/// Templates are loaded through `TemplateSource::Source` ids, the
+/// content of the source is ignored. `TemplateSource::Path` is not
+/// supported (and fails loading).
+///
+/// Rendering a template with a data type not implementing it
+/// is a `NotImplementedByData` render error.
+#[derive(Debug, Clone, Default)]
+pub struct TypedRenderEngine {
+    /// the ids of all registered compiled templates
+    compiled: HashSet<&'static str>,
+    /// the ids of all templates loaded from specs
+    loaded: HashSet<String>
+}
+
+impl TypedRenderEngine {
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// registers the templates implemented by `D`
+    ///
+    /// Registering the same template ids multiple times
+    /// (e.g. through different data types) is fine.
+    pub fn register_templates<D>(&mut self)
+        where D: TypedTemplates
+    {
+        self.compiled.extend(D::TEMPLATE_IDS.iter().cloned());
+    }
+
+    /// true if a template with the given id was registered
+    pub fn is_compiled(&self, id: &str) -> bool {
+        self.compiled.contains(id)
+    }
+
+    fn has_template(&self, id: &str) -> bool {
+        self.loaded.contains(id)
+    }
+
+    fn add_template(&mut self, id: &str) -> Result<(), TypedError> {
+        if !self.is_compiled(id) {
+            return Err(TypedError::NotCompiled { id: id.to_owned() });
+        }
+        self.loaded.insert(id.to_owned());
+        Ok(())
+    }
+
+    fn remove_template(&mut self, id: &str) {
+        self.loaded.remove(id);
+    }
+}
+
+impl RenderEngineBase for TypedRenderEngine {
+    // the templates are normal rust code, so nothing guarantees \r\n newlines
+    const PRODUCES_VALID_NEWLINES: bool = false;
+
+    type RenderError = TypedError;
+    type LoadingError = TypedError;
+
+    fn load_templates(&mut self, spec: &TemplateSpec) -> Result<(), Self::LoadingError> {
+        implement_load_helper! {
+            input::<TypedRenderEngine>(spec, self);
+            error(TypedError);
+            collision_error_fn(|id| { TypedError::TemplateIdCollision { id } });
+            has_template_fn(|engine, id| { engine.has_template(id) });
+            remove_fn(|engine, id| { engine.remove_template(id) });
+            add_file_fn(|_engine, path| { Err(TypedError::PathNotSupported { path: path.clone() }) });
+            add_content_fn(|engine, id, _content| { engine.add_template(id) });
+        }
+    }
+
+    /// This can be used to reload a templates.
+    fn unload_templates(&mut self, spec: &TemplateSpec) {
+        for sub_spec in spec.render_templates() {
+            self.remove_template(sub_spec.source().id());
+        }
+    }
+
+    fn unknown_template_id_error(id: &str) -> Self::RenderError {
+        TypedError::UnknownTemplateId { id: id.to_owned() }
+    }
+}
+
+impl<D> RenderEngine<D> for TypedRenderEngine
+    where D: TypedTemplates
+{
+    fn render(
+        &self,
+        spec: &SubTemplateSpec,
+        data: &D,
+        cids: AdditionalCIds
+    ) -> Result<String, Self::RenderError> {
+        let id = spec.source().id();
+        if !self.has_template(id) {
+            return Err(Self::unknown_template_id_error(id));
+        }
+        data.render_template(id, &cids)
+            .ok_or_else(|| TypedError::NotImplementedByData { id: id.to_owned() })
+    }
+}
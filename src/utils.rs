@@ -1,7 +1,8 @@
 use std::path::Path;
 use std::ffi::OsStr;
 use std::process::Command;
-use std::io;
+use std::fs::File;
+use std::io::{self, Read};
 
 use failure::Fail;
 
@@ -10,6 +11,10 @@ use conduit_mime_types::Types as TypesBySuffix;
 use headers::components::MediaType;
 
 use ::error::{CreatingSpecError, CreatingSpecErrorVariant};
+use ::sniff::{sniff_bytes, MAX_SNIFF_LEN};
+
+/// the maximal length of a line in octets, excluding the line break (see RFC 5322)
+pub const MAX_LINE_LENGTH: usize = 998;
 
 lazy_static! {
     static ref TYPES_BY_SUFFIX: TypesBySuffix = {
@@ -50,40 +55,46 @@ pub(crate) fn check_string_path(path: &Path) -> Result<(), CreatingSpecError> {
 }
 
 
-pub(crate) fn sniff_media_type(path: &Path) -> Result<MediaType, CreatingSpecError> {
+/// returns the media type associated with the extension of the given file
+pub(crate) fn media_type_str_by_extension(path: &Path) -> Result<&'static str, CreatingSpecError> {
     //this does not work for
     // 1. multi part extensions like .tar.gz
     // 2. types not supported by conduit-media-types (which, btw. include .tar.gz /.tgz)
-    let extension = path.extension()
-        .and_then(|extension| extension.to_str())
-        .ok_or_else(|| CreatingSpecErrorVariant::NoValidFileStem { file: path.into() })?;
+    let extension = file_extension(path)?;
 
-    // 1. determine media type by file ending
-    let by_extension_str_media_type = TYPES_BY_SUFFIX
+    TYPES_BY_SUFFIX
         .get_mime_type(extension)
-        .ok_or_else(|| CreatingSpecErrorVariant::NoMediaTypeFor { stem: extension.to_owned() })?;
-
-    // 2. determine media type by file
-    let media_type = sniff_with_file_cmd(path)?;
-
-    // 3. compare (FEAT: validate instead of 2+3, as it only needs to run some tests)
-    if media_type.full_type() != by_extension_str_media_type {
-        //FEAT if they are close by in the subtyping base media tree it's still fine
-        // e.g. if tp by extension is text/plain and tp by file is text/x-tex we can go with
-        // text/plain (the file ending). But we might also do so the other way around _iff_
-        // we could get the actual probabilities for both the extension based and the file ending based
-        // one
-        return Err(CreatingSpecErrorVariant::FileStemAndContentDifferInMediaType {
-            path: path.into(),
-            by_extension: by_extension_str_media_type.to_owned(),
-            by_content: media_type.full_type().to_string()
-        }.into());
-    }
-
-    Ok(media_type)
+        .ok_or_else(|| CreatingSpecErrorVariant::NoMediaTypeFor { stem: extension.to_owned() }.into())
+}
 
+pub(crate) fn file_extension(path: &Path) -> Result<&str, CreatingSpecError> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .ok_or_else(|| CreatingSpecErrorVariant::NoValidFileStem { file: path.into() }.into())
 }
 
+/// determines the media type of a file based on it's content
+///
+/// If the content has a unknown format `None` is returned, or if
+/// `file_cmd_fallback` is true the `file` command is used to determine
+/// the media type.
+pub(crate) fn sniff_content(path: &Path, file_cmd_fallback: bool) -> Result<Option<MediaType>, CreatingSpecError> {
+    let mut data = Vec::new();
+    File::open(path)?
+        .take(MAX_SNIFF_LEN as u64)
+        .read_to_end(&mut data)?;
+
+    let truncated = data.len() >= MAX_SNIFF_LEN;
+    match sniff_bytes(&data, truncated) {
+        Some(media_type) => {
+            MediaType::parse(&*media_type)
+                .map(Some)
+                .map_err(|err| err.context(CreatingSpecErrorVariant::NotAMediaType).into())
+        },
+        None if file_cmd_fallback => sniff_with_file_cmd(path).map(Some),
+        None => Ok(None)
+    }
+}
 
 pub(crate) fn sniff_with_file_cmd(path: &Path) -> Result<MediaType, CreatingSpecError> {
     let out = Command::new("file")
@@ -119,6 +130,11 @@ pub(crate) fn sniff_with_file_cmd(path: &Path) -> Result<MediaType, CreatingSpec
     Ok(media_type)
 }
 
+/// returns true if the media type is `full_type` (ignoring parameters)
+pub(crate) fn has_full_type(media_type: &MediaType, full_type: &str) -> bool {
+    media_type.full_type().to_string().eq_ignore_ascii_case(full_type)
+}
+
 /// replace any orphan \r,\n chars with \r\n if needed
 ///
 /// If the there is no need to replace anything the input String will be returned,
@@ -245,7 +261,13 @@ mod test {
     mod sniff_media_type {
         use std::path::Path;
         use ::error::CreatingSpecErrorVariant;
-        use super::super::sniff_media_type;
+        use ::error::CreatingSpecError;
+        use ::resolver::{MediaTypeResolver, StrictResolver};
+        use headers::components::MediaType;
+
+        fn sniff_media_type(path: &Path) -> Result<MediaType, CreatingSpecError> {
+            StrictResolver::new().resolve_media_type(path)
+        }
 
         #[test]
         fn sniff_pdf() {
@@ -271,6 +293,12 @@ mod test {
             assert_eq!(mt.as_str_repr(), "text/plain; charset=utf-8")
         }
 
+        #[test]
+        fn unknown_content_uses_the_extension() {
+            let mt = sniff_media_type(Path::new("./test_resources/icon.ico")).unwrap();
+            assert_eq!(mt.as_str_repr(), "image/x-icon")
+        }
+
         #[test]
         fn sniff_conflicting_image() {
             let _path = Path::new("./test_resources/jpg_image.png");
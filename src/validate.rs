@@ -0,0 +1,254 @@
+//! Validation of template dirs before they are used in production.
+//!
+//! Besides creating each `TemplateSpec` and loading (i.e. compiling)
+//! its templates with a render engine, the templates are scanned for
+//! references to content ids (`cids.<name>` or `cids["<name>"]`) which
+//! are checked against the embeddings available to the template.
+//!
+//! Note that references in templates not bound to a spec (e.g. base
+//! templates used for inheritance) are not seen.
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use failure::Fail;
+
+use ::error::CreatingSpecError;
+use ::report::{CreationReport, SpecFailure};
+use ::settings::LoadSpecSettings;
+use ::spec::{TemplateSpec, TemplateSource};
+use ::traits::RenderEngineBase;
+
+/// The result of validating all templates in a templates dir.
+#[derive(Debug)]
+pub struct ValidationReport<E: Fail> {
+    /// number of specs which could be created and where checked
+    pub checked_specs: usize,
+    /// the template dirs from which no spec could be created
+    pub creation_failures: Vec<SpecFailure>,
+    /// issues found in created specs together with the id of the spec
+    pub issues: Vec<(String, ValidationIssue<E>)>
+}
+
+impl<E> ValidationReport<E>
+    where E: Fail
+{
+    /// returns true if there is any issue or creation failure
+    pub fn has_problems(&self) -> bool {
+        !self.creation_failures.is_empty() || !self.issues.is_empty()
+    }
+}
+
+impl<E> Display for ValidationReport<E>
+    where E: Fail
+{
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fter, "checked {} spec(s), {} failed to be created, {} issue(s)",
+            self.checked_specs, self.creation_failures.len(), self.issues.len())?;
+        for failure in self.creation_failures.iter() {
+            writeln!(fter, "{}", failure)?;
+        }
+        for &(ref id, ref issue) in self.issues.iter() {
+            writeln!(fter, "{}: {}", id, issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// A problem found in a (successfully created) spec.
+#[derive(Debug)]
+pub enum ValidationIssue<E: Fail> {
+    /// the templates of the spec can not be loaded by the render engine
+    LoadingFailed(E),
+    /// the template file can not be read to scan it for cid references
+    UnreadableTemplate { template: String, error: io::Error },
+    /// the template refers to a cid of a embedding which does not exist
+    DanglingCId { template: String, name: String },
+    /// a embedding is not referenced by any template which can use it
+    ///
+    /// `template` is `None` for embeddings shared between all sub-templates.
+    UnusedEmbedding { template: Option<String>, name: String }
+}
+
+impl<E> Display for ValidationIssue<E>
+    where E: Fail
+{
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        use self::ValidationIssue::*;
+        match *self {
+            LoadingFailed(ref error) =>
+                write!(fter, "loading templates failed: {}", error),
+            UnreadableTemplate { ref template, ref error } =>
+                write!(fter, "can not read template {}: {}", template, error),
+            DanglingCId { ref template, ref name } =>
+                write!(fter, "template {} refers to unknown embedding {:?}", template, name),
+            UnusedEmbedding { template: Some(ref template), ref name } =>
+                write!(fter, "embedding {:?} is not used by template {}", name, template),
+            UnusedEmbedding { template: None, ref name } =>
+                write!(fter, "shared embedding {:?} is not used by any template", name),
+        }
+    }
+}
+
+/// validates all template dirs in `templates_dir`
+///
+/// Each spec is loaded into the given `render_engine`, as specs are not
+/// unloaded afterwards this also detects template id collisions between
+/// specs.
+///
+/// # Error
+///
+/// Only if the `templates_dir` itself can not be read an error is returned.
+pub fn validate_templates<R>(
+    templates_dir: impl AsRef<Path>,
+    settings: &LoadSpecSettings,
+    render_engine: &mut R
+) -> Result<ValidationReport<R::LoadingError>, CreatingSpecError>
+    where R: RenderEngineBase
+{
+    let CreationReport { specs, failures } =
+        TemplateSpec::from_dirs_with_report(templates_dir.as_ref(), settings)?;
+
+    let mut report = ValidationReport {
+        checked_specs: specs.len(),
+        creation_failures: failures,
+        issues: Vec::new()
+    };
+
+    for (id, spec) in specs {
+        for issue in validate_spec(&spec, render_engine) {
+            report.issues.push((id.clone(), issue));
+        }
+    }
+    Ok(report)
+}
+
+/// loads the templates of the spec and checks their cid references
+pub fn validate_spec<R>(spec: &TemplateSpec, render_engine: &mut R)
+    -> Vec<ValidationIssue<R::LoadingError>>
+    where R: RenderEngineBase
+{
+    let mut issues = Vec::new();
+    if let Err(error) = render_engine.load_templates(spec) {
+        issues.push(ValidationIssue::LoadingFailed(error));
+    }
+
+    let mut used_shared = HashSet::new();
+    for sub_spec in spec.sub_specs() {
+        let template = sub_spec.source().id();
+        let content = match *sub_spec.source() {
+            TemplateSource::Path(ref path) => match fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(error) => {
+                    issues.push(ValidationIssue::UnreadableTemplate {
+                        template: template.to_owned(), error
+                    });
+                    continue;
+                }
+            },
+            TemplateSource::Source { ref content, .. } => content.clone()
+        };
+
+        let references = cid_references(&content);
+        let mut sorted_references = references.iter().collect::<Vec<_>>();
+        sorted_references.sort();
+        for name in sorted_references {
+            if sub_spec.embeddings().contains_key(name) {
+                continue;
+            }
+            if spec.embeddings().contains_key(name) {
+                used_shared.insert(name.clone());
+                continue;
+            }
+            issues.push(ValidationIssue::DanglingCId {
+                template: template.to_owned(),
+                name: name.clone()
+            });
+        }
+
+        let mut unused = sub_spec.embeddings().keys()
+            .filter(|name| !references.contains(*name))
+            .collect::<Vec<_>>();
+        unused.sort();
+        for name in unused {
+            issues.push(ValidationIssue::UnusedEmbedding {
+                template: Some(template.to_owned()),
+                name: name.clone()
+            });
+        }
+    }
+
+    let mut unused = spec.embeddings().keys()
+        .filter(|name| !used_shared.contains(*name))
+        .collect::<Vec<_>>();
+    unused.sort();
+    for name in unused {
+        issues.push(ValidationIssue::UnusedEmbedding { template: None, name: name.clone() });
+    }
+
+    issues
+}
+
+/// returns the names of all embeddings referred to through `cids.<name>` or `cids["<name>"]`
+pub(crate) fn cid_references(template: &str) -> HashSet<String> {
+    const CIDS: &str = "cids";
+
+    let mut names = HashSet::new();
+    let mut rest = template;
+    while let Some(idx) = rest.find(CIDS) {
+        // `cids` has to be the start of the path, e.g. not `data.cids.x`
+        let is_word_start = rest[..idx].chars().next_back()
+            .map(|ch| !is_ident_char(ch) && ch != '.')
+            .unwrap_or(true);
+
+        rest = &rest[idx + CIDS.len()..];
+        if !is_word_start {
+            continue;
+        }
+
+        if rest.starts_with('.') {
+            let name_len = rest[1..].find(|ch| !is_ident_char(ch)).unwrap_or(rest.len() - 1);
+            if name_len > 0 {
+                names.insert(rest[1..1 + name_len].to_owned());
+            }
+        } else if rest.starts_with('[') {
+            let inner = rest[1..].trim_start();
+            if let Some(quote) = inner.chars().next().filter(|&ch| ch == '"' || ch == '\'') {
+                if let Some(end) = inner[1..].find(quote) {
+                    names.insert(inner[1..1 + end].to_owned());
+                }
+            }
+        }
+    }
+    names
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::cid_references;
+
+    #[test]
+    fn finds_dot_and_index_references() {
+        let refs = cid_references(r#"<img src="cid:{{cids.logo}}"> {{ cids["the banner"] }} {{cids['x']}}"#);
+        let mut refs = refs.into_iter().collect::<Vec<_>>();
+        refs.sort();
+        assert_eq!(refs, &["logo", "the banner", "x"]);
+    }
+
+    #[test]
+    fn ignores_other_identifiers() {
+        assert!(cid_references("{{ data.mycids.logo }} {{ cids_x.y }} cids").is_empty());
+    }
+
+    #[test]
+    fn ignores_cids_fields_of_other_values() {
+        assert!(cid_references(r#"{{ data.cids.logo }} {{ data.cids["x"] }}"#).is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::{HashMap, BTreeMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use std::io;
+
+use ::error::LoadingError;
+use ::settings::LoadSpecSettings;
+use ::traits::RenderEngineBase;
+use ::rte::RenderTemplateEngine;
+
+/// modification time and size of each file in a template dir
+type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// A polling based watcher reloading specs if files in their template dir changed.
+///
+/// The watcher does not spawn any thread, instead `poll` (or `poll_if_due`)
+/// has to be called regularly e.g. from a timer or before handling a request.
+/// This allows designers to iterate on templates without restarting the
+/// service using them.
+///
+/// The first poll for a spec only records the state of its template dir,
+/// later polls reload the spec if any file was added, removed or modified.
+/// Only specs with a `base_path` (i.e. which were loaded from a dir) are
+/// watched.
+///
+/// # Example
+///
+/// ```no_run
+/// # extern crate mail_render_template_engine;
+/// # use std::time::Duration;
+/// # use mail_render_template_engine::{RenderTemplateEngine, RenderEngineBase, TemplateWatcher, DEFAULT_SETTINGS};
+/// # fn example<R: RenderEngineBase>(engine: &mut RenderTemplateEngine<R>) {
+/// let mut watcher = TemplateWatcher::new(Duration::from_secs(2));
+/// // e.g. called before each use of the engine
+/// for (id, result) in watcher.poll_if_due(engine, &*DEFAULT_SETTINGS) {
+///     if let Err(err) = result {
+///         eprintln!("reloading {} failed: {}", id, err);
+///     }
+/// }
+/// # }
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct TemplateWatcher {
+    interval: Duration,
+    last_poll: Option<Instant>,
+    snapshots: HashMap<String, Snapshot>
+}
+
+impl TemplateWatcher {
+
+    /// create a new watcher which polls at most once per `interval` when using `poll_if_due`
+    pub fn new(interval: Duration) -> Self {
+        TemplateWatcher {
+            interval,
+            last_poll: None,
+            snapshots: HashMap::new()
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// calls `poll` if the last poll is at last `interval` ago
+    ///
+    /// If it is not yet time to poll an empty vector is returned.
+    pub fn poll_if_due<R>(
+        &mut self,
+        engine: &mut RenderTemplateEngine<R>,
+        settings: &LoadSpecSettings
+    ) -> Vec<(String, Result<(), LoadingError<R::LoadingError>>)>
+        where R: RenderEngineBase
+    {
+        let is_due = self.last_poll
+            .map(|last_poll| last_poll.elapsed() >= self.interval)
+            .unwrap_or(true);
+
+        if is_due {
+            self.poll(engine, settings)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// checks all template dirs for changes reloading the specs of changed dirs
+    ///
+    /// Returns the ids of all reloaded specs together with the result of
+    /// reloading them. If reloading a spec fails, the spec is handled like
+    /// described in `RenderTemplateEngine::reload_spec` and it won't be
+    /// reloaded again until its dir changes again.
+    pub fn poll<R>(
+        &mut self,
+        engine: &mut RenderTemplateEngine<R>,
+        settings: &LoadSpecSettings
+    ) -> Vec<(String, Result<(), LoadingError<R::LoadingError>>)>
+        where R: RenderEngineBase
+    {
+        self.last_poll = Some(Instant::now());
+
+        let watched = engine.specs().iter()
+            .filter_map(|(id, spec)| spec.base_path().map(|path| (id.clone(), path.to_owned())))
+            .collect::<Vec<_>>();
+
+        self.snapshots.retain(|id, _| watched.iter().any(|&(ref watched_id, _)| watched_id == id));
+
+        let mut reloaded = Vec::new();
+        for (id, base_path) in watched {
+            // if the dir can not be read it is treated as empty, so that
+            // the (failing) reload reports the actual problem
+            let snapshot = snapshot_dir(&base_path).unwrap_or_default();
+            let changed = match self.snapshots.get(&id) {
+                Some(old) => *old != snapshot,
+                None => false
+            };
+            self.snapshots.insert(id.clone(), snapshot);
+
+            if changed {
+                let result = engine.reload_spec(&id, settings).map(|_| ());
+                reloaded.push((id, result));
+            }
+        }
+        reloaded
+    }
+
+    /// forget all recorded template dir states
+    ///
+    /// The next poll will only record the state of the template dirs
+    /// and not reload any spec.
+    pub fn reset(&mut self) {
+        self.last_poll = None;
+        self.snapshots.clear();
+    }
+}
+
+fn snapshot_dir(dir: &Path) -> Result<Snapshot, io::Error> {
+    let mut snapshot = Snapshot::new();
+    add_to_snapshot(dir, &mut snapshot)?;
+    Ok(snapshot)
+}
+
+fn add_to_snapshot(dir: &Path, snapshot: &mut Snapshot) -> Result<(), io::Error> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        let path = entry.path();
+        if meta.is_dir() {
+            add_to_snapshot(&path, snapshot)?;
+        } else {
+            snapshot.insert(path, (meta.modified().ok(), meta.len()));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,168 @@
+extern crate mail_common as common;
+extern crate mail_headers as headers;
+extern crate mail_types as mail;
+#[macro_use]
+extern crate mail_template as template;
+extern crate mail_render_template_engine as render_template_engine;
+extern crate soft_ascii_string;
+extern crate futures;
+extern crate regex;
+#[macro_use]
+extern crate serde_derive;
+
+//TODO use custom integration test target for this
+#[cfg(not(feature = "minijinja-engine"))]
+compile_error!("need feature \"minijinja-engine\" to run minijinja integration tests");
+
+
+use std::result::{Result as StdResult};
+use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::collections::HashMap;
+use std::borrow::Cow;
+
+use regex::Regex;
+use futures::Future;
+use soft_ascii_string::SoftAsciiString;
+
+use common::MailType;
+use common::encoder::EncodingBuffer;
+use mail::{Mail, Context};
+use mail::default_impl::simple_context;
+use headers::components::{Email, Domain};
+use headers::HeaderTryFrom;
+use template::{MailSendData, InspectEmbeddedResources, Embedded};
+
+use render_template_engine::{
+    RenderTemplateEngine, DEFAULT_SETTINGS,
+    TemplateSpec, HeaderTemplate, PreviewData, PreviewOptions,
+    render_preview
+};
+use render_template_engine::error::{UseTemplateError, ProcessingError};
+use render_template_engine::minijinja::MiniJinjaRenderEngine;
+
+
+#[derive(Serialize, InspectEmbeddedResources)]
+struct UserData {
+    name: &'static str
+}
+
+#[derive(Serialize)]
+struct UnsubscribeData {
+    unsubscribe_url: &'static str
+}
+
+
+fn setup_context() -> simple_context::Context {
+    let msg_id_domain = Domain::try_from("company_a.test").unwrap();
+    let unique_part = SoftAsciiString::from_string("r73rc20").unwrap();
+    simple_context::new(msg_id_domain, unique_part).unwrap()
+}
+
+fn setup_template_engine() -> RenderTemplateEngine<MiniJinjaRenderEngine> {
+    let minijinja = MiniJinjaRenderEngine::new("./test_resources/minijinja_base");
+    let mut rte = RenderTemplateEngine::new(minijinja);
+    let specs = TemplateSpec
+        ::from_dirs("./test_resources/templates",  &*DEFAULT_SETTINGS)
+        .unwrap();
+
+    for (id, spec) in specs {
+        rte.insert_spec(id, spec).unwrap();
+    }
+
+    rte
+}
+
+fn send_mail_to_string(mail: Mail, ctx: impl Context) -> String {
+    let mut encoder = EncodingBuffer::new( MailType::Ascii );
+    let encodable_mail = mail.into_encodeable_mail(ctx).wait().unwrap();
+    encodable_mail.encode( &mut encoder ).unwrap();
+    encoder.to_string().unwrap()
+}
+
+#[test]
+fn use_minijinja_template_a() {
+    let context = setup_context();
+    let engine = setup_template_engine();
+
+    let from        = Email::try_from("a@b.c").unwrap().into();
+    let to          = Email::try_from("d@e.f").unwrap().into();
+    let subject     = "Dear randomness";
+    let template_id = Cow::Borrowed("template_a");
+    let data        = UserData { name: "Liz" };
+
+    let send_data = MailSendData::simple_new(
+        from, to, subject,
+        template_id, data
+    );
+
+    let mail = send_data.compose(&context, &engine).unwrap();
+
+    // context's are meant to be cheaply cloneable,
+    // e.g. in this case it just cloning a `Arc`
+    let out_string = send_mail_to_string(mail, context.clone());
+
+    assert_mail_out_is_as_expected(out_string);
+}
+
+#[test]
+fn preview_minijinja_template_a() {
+    let context = setup_context();
+    let engine = setup_template_engine();
+    let data = PreviewData::from_json_str(r#"{"name": "Liz"}"#).unwrap();
+
+    let preview = render_preview(&engine, "template_a", data, &context, &PreviewOptions::default())
+        .unwrap();
+
+    assert_eq!(preview.bodies.len(), 2);
+    assert_eq!(preview.bodies[0].content, "Hy Liz.");
+    assert!(preview.bodies[1].content.contains("Hy Liz."));
+    assert!(preview.eml.contains("Subject: Preview of template_a"));
+}
+
+#[test]
+fn rendered_header_values_can_not_inject_headers() {
+    let mut engine = setup_template_engine();
+    let mut spec = TemplateSpec::from_dir("./test_resources/templates/template_a", &*DEFAULT_SETTINGS).unwrap();
+    let headers = HeaderTemplate::from_lines("unsubscribe_headers", "List-Unsubscribe: <{{ data.unsubscribe_url }}>\n");
+    spec.set_headers(headers.unwrap());
+    engine.insert_spec("unsubscribe".to_owned(), spec).unwrap();
+
+    let data = UnsubscribeData { unsubscribe_url: "https://example.com/unsub" };
+    let headers = engine.render_headers("unsubscribe", &data).unwrap();
+    assert_eq!(headers, vec![
+        ("List-Unsubscribe".to_owned(), "<https://example.com/unsub>".to_owned())
+    ]);
+
+    let data = UnsubscribeData { unsubscribe_url: "x>\nBcc: victim@evil" };
+    match engine.render_headers("unsubscribe", &data) {
+        Err(UseTemplateError::Processing(ProcessingError::InvalidHeader { ref line, .. })) => {
+            assert_eq!(line, "List-Unsubscribe: <x>\nBcc: victim@evil>");
+        },
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(headers) => panic!("header injection was not detected: {:?}", headers)
+    }
+}
+
+fn assert_mail_out_is_as_expected(mail_out: String) {
+    let mut line_iter = mail_out.lines();
+    let mut capture_map = HashMap::new();
+
+    let fd = File::open("./test_resources/template_a.out.regex").unwrap();
+    let fd_line_iter = BufReader::new(fd).lines().map(StdResult::unwrap).enumerate();
+    for (line_nr, mut template_line) in fd_line_iter {
+        template_line.insert(0, '^');
+        template_line.push('$');
+        let mut line_regex = Regex::new(&*template_line).unwrap();
+        let res_line = line_iter.next().unwrap();
+        let captures = line_regex.captures(res_line).unwrap_or_else(|| {
+            panic!("[{}] no match, regex: {:?}, line: {:?}", line_nr, line_regex, res_line);
+        });
+        for name in line_regex.capture_names().filter_map(|e|e){
+            let value = captures.name(name).unwrap().as_str();
+            let value2 = capture_map.entry(name.to_owned()).or_insert(value);
+            assert_eq!(value, *value2)
+        }
+    }
+    assert_eq!(line_iter.next(), None);
+}
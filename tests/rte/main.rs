@@ -1,11 +1,47 @@
 extern crate mail_template as compos;
 extern crate mail_types as mail;
+extern crate mail_headers as headers;
 extern crate mail_render_template_engine;
+#[macro_use]
+extern crate vec1;
+extern crate soft_ascii_string;
+extern crate futures;
+#[macro_use]
+extern crate failure;
 
+use std::fs;
 use std::path::Path;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::sync::{Arc, Barrier};
+use std::thread;
 
-use mail_render_template_engine::{TemplateSpec, DEFAULT_SETTINGS};
+use soft_ascii_string::SoftAsciiString;
+use futures::Future;
 
+use compos::TemplateEngine;
+use mail::default_impl::simple_context;
+use headers::HeaderTryFrom;
+use headers::components::{Domain, MediaType};
+
+use mail_render_template_engine::{
+    TemplateSpec, RenderTemplateEngine, SharedRenderTemplateEngine, TemplateWatcher, DEFAULT_SETTINGS,
+    TemplateSource, SubTemplateSpec, HeaderTemplate, SampleOutcome, Locale, PostProcessContext, RewrittenLink,
+    LineLengthPolicy, MAX_LINE_LENGTH, FLOWED_LINE_WIDTH,
+    validate_templates, check_samples
+};
+use mail_render_template_engine::error::{
+    CreatingSpecErrorVariant, LoadingError, UseTemplateError, ProcessingError
+};
+
+use self::mock_engine::{MockEngine, MockError, copy_to_temp_dir};
+
+mod mock_engine;
+
+fn setup_context() -> simple_context::Context {
+    let domain = Domain::try_from("rte.test").unwrap();
+    simple_context::new(domain, SoftAsciiString::from_string("s4mpl3").unwrap()).unwrap()
+}
 
 #[test]
 fn load_template_a() {
@@ -44,3 +80,721 @@ fn load_template_a() {
 
 }
 
+#[test]
+fn load_template_with_manifest() {
+    let settings = &*DEFAULT_SETTINGS;
+    let b_spec = TemplateSpec::from_dir("./test_resources/manifest_templates/template_b", settings).unwrap();
+
+    let attachments = b_spec.attachments();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(
+        attachments[0].source().unwrap().iri.as_str(),
+        "path:./test_resources/manifest_templates/template_b/terms.pdf"
+    );
+
+    let embeddings = b_spec.embeddings();
+    assert_eq!(embeddings.len(), 1);
+    let header = embeddings.get("header").unwrap();
+    assert_eq!(
+        header.source().unwrap().iri.as_str(),
+        "path:./test_resources/manifest_templates/template_b/banner.png"
+    );
+
+    let sub_specs = b_spec.sub_specs();
+    assert_eq!(sub_specs.len(), 2);
+    let plain = &sub_specs[0];
+    let html = &sub_specs[1];
+
+    assert_eq!(plain.source().id(), "./test_resources/manifest_templates/template_b/plain/mail.txt");
+    assert_eq!(plain.media_type().as_str_repr(), "text/plain; charset=us-ascii");
+    assert!(plain.embeddings().is_empty());
+
+    assert_eq!(html.source().id(), "./test_resources/manifest_templates/template_b/html/mail.html");
+    assert_eq!(html.media_type().as_str_repr(), "text/html; charset=utf-8");
+    let logo = html.embeddings().get("logo").unwrap();
+    assert_eq!(
+        logo.source().unwrap().iri.as_str(),
+        "path:./test_resources/manifest_templates/template_b/html/logo_small.png"
+    );
+}
+
+#[test]
+fn manifest_referring_to_missing_sub_template_fails() {
+    let settings = &*DEFAULT_SETTINGS;
+    let err = TemplateSpec::from_dir("./test_resources/bad_manifests/unknown_body", settings).unwrap_err();
+
+    if let &CreatingSpecErrorVariant::ManifestUnknownSubTemplate { ref name } = err.variant() {
+        assert_eq!(name, "rich");
+    } else {
+        panic!("unexpected error: {}", err);
+    }
+}
+
+#[test]
+fn manifest_locales_have_to_exist() {
+    let settings = &*DEFAULT_SETTINGS;
+    let err = TemplateSpec::from_dir("./test_resources/bad_manifests/unknown_locale", settings).unwrap_err();
+
+    if let &CreatingSpecErrorVariant::ManifestUnknownLocale { ref name } = err.variant() {
+        assert_eq!(name, "de");
+    } else {
+        panic!("unexpected error: {}", err);
+    }
+}
+
+#[test]
+fn load_template_with_attachments_folder() {
+    let settings = &*DEFAULT_SETTINGS;
+    let c_spec = TemplateSpec::from_dir("./test_resources/attachment_templates/template_c", settings).unwrap();
+
+    assert!(c_spec.embeddings().is_empty());
+    assert_eq!(c_spec.sub_specs().len(), 1);
+
+    let attachments = c_spec.attachments();
+    assert_eq!(attachments.len(), 2);
+    assert_eq!(
+        attachments[0].source().unwrap().iri.as_str(),
+        "path:./test_resources/attachment_templates/template_c/attachments/about.txt"
+    );
+    assert_eq!(
+        attachments[1].source().unwrap().iri.as_str(),
+        "path:./test_resources/attachment_templates/template_c/attachments/terms.pdf"
+    );
+}
+
+#[test]
+fn reload_spec_picks_up_changes() {
+    let settings = &*DEFAULT_SETTINGS;
+    let dir = copy_to_temp_dir(Path::new("./test_resources/templates/template_a"), "reload");
+    let text_file = dir.join("text/mail.txt");
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("a".to_owned(), TemplateSpec::from_dir(&dir, settings).unwrap()).unwrap();
+
+    fs::write(&text_file, "Hello {{data.name}}.").unwrap();
+    engine.reload_spec("a", settings).unwrap();
+    let id = text_file.to_str().unwrap();
+    assert_eq!(engine.render_engine().loaded[id], "Hello {{data.name}}.");
+
+    // if the new spec can not be created the old spec is kept
+    fs::remove_file(&text_file).unwrap();
+    match engine.reload_spec("a", settings) {
+        Err(LoadingError::SpecCreation(_)) => {},
+        other => panic!("unexpected result: {:?}", other.map(|_| ()))
+    }
+    assert!(engine.lookup_spec("a").is_some());
+    assert_eq!(engine.render_engine().loaded[id], "Hello {{data.name}}.");
+
+    // loading the new spec fails (because of the new subject template), the old
+    // spec is restored by loading it again, i.e. it uses the changed text template
+    fs::write(&text_file, "Hy {{data.name}}.").unwrap();
+    fs::write(dir.join("subject.txt"), "{{ broken").unwrap();
+    match engine.reload_spec("a", settings) {
+        Err(LoadingError::SpecUsage(ref err)) => assert!(err.old_value.is_none()),
+        other => panic!("unexpected result: {:?}", other.map(|_| ()))
+    }
+    assert!(engine.lookup_spec("a").unwrap().subject().is_none());
+    assert_eq!(engine.render_engine().loaded[id], "Hy {{data.name}}.");
+    fs::remove_file(dir.join("subject.txt")).unwrap();
+
+    // the old template file is broken too, so the old spec can not be restored
+    fs::write(&text_file, "{{ broken").unwrap();
+    let err = engine.reload_spec("a", settings).unwrap_err();
+    if let LoadingError::SpecUsage(ref err) = err {
+        assert!(err.old_value.is_some());
+    } else {
+        panic!("unexpected error: {}", err);
+    }
+    assert!(engine.lookup_spec("a").is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reload_requires_a_known_dir_based_spec() {
+    let settings = &*DEFAULT_SETTINGS;
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+
+    match engine.reload_spec("unknown", settings) {
+        Err(LoadingError::UnknownSpecId { ref id }) if id == "unknown" => {},
+        other => panic!("unexpected result: {:?}", other.map(|_| ()))
+    }
+}
+
+#[test]
+fn watcher_reloads_changed_specs() {
+    let settings = &*DEFAULT_SETTINGS;
+    let dir = copy_to_temp_dir(Path::new("./test_resources/templates/template_a"), "watch");
+    let text_file = dir.join("text/mail.txt");
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("a".to_owned(), TemplateSpec::from_dir(&dir, settings).unwrap()).unwrap();
+
+    let mut watcher = TemplateWatcher::new(Duration::from_secs(0));
+    assert!(watcher.poll(&mut engine, settings).is_empty());
+    assert!(watcher.poll(&mut engine, settings).is_empty());
+
+    // change the size so that the change is detected even with a coarse mtime
+    fs::write(&text_file, "Hello there {{data.name}}.").unwrap();
+    let reloaded = watcher.poll(&mut engine, settings);
+    assert_eq!(reloaded.len(), 1);
+    assert_eq!(reloaded[0].0, "a");
+    assert!(reloaded[0].1.is_ok());
+    assert_eq!(engine.render_engine().loaded[text_file.to_str().unwrap()], "Hello there {{data.name}}.");
+
+    assert!(watcher.poll(&mut engine, settings).is_empty());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn atomic_loading_rolls_back_the_whole_batch() {
+    let settings = &*DEFAULT_SETTINGS;
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    let a_spec = TemplateSpec::from_dir("./test_resources/templates/template_a", settings).unwrap();
+    engine.insert_spec("good".to_owned(), a_spec).unwrap();
+
+    let err = engine.load_templates_atomic("./test_resources/batch_templates", settings).unwrap_err();
+    if let LoadingError::BatchRolledBack { ref id, ref lost_on_rollback, .. } = err {
+        assert_eq!(id, "broken");
+        assert!(lost_on_rollback.is_empty());
+    } else {
+        panic!("unexpected error: {}", err);
+    }
+
+    assert_eq!(engine.specs().len(), 1);
+    let good = engine.lookup_spec("good").unwrap();
+    assert_eq!(good.base_path().unwrap(), Path::new("./test_resources/templates/template_a"));
+    assert_eq!(engine.render_engine().loaded.len(), 2);
+    assert!(engine.render_engine().loaded.contains_key("./test_resources/templates/template_a/text/mail.txt"));
+}
+
+#[test]
+fn atomic_loading_keeps_replaced_specs_until_the_batch_is_loaded() {
+    let settings = &*DEFAULT_SETTINGS;
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    let a_spec = TemplateSpec::from_dir("./test_resources/templates/template_a", settings).unwrap();
+    engine.insert_spec("broken".to_owned(), a_spec).unwrap();
+    let good_spec = TemplateSpec::from_dir("./test_resources/batch_templates/good", settings).unwrap();
+    engine.insert_spec("good".to_owned(), good_spec).unwrap();
+    let loaded_before = engine.render_engine().loaded.clone();
+
+    let err = engine.load_templates_atomic("./test_resources/batch_templates", settings).unwrap_err();
+    if let LoadingError::BatchRolledBack { ref id, ref lost_on_rollback, .. } = err {
+        assert_eq!(id, "broken");
+        assert!(lost_on_rollback.is_empty());
+    } else {
+        panic!("unexpected error: {}", err);
+    }
+
+    let broken = engine.lookup_spec("broken").unwrap();
+    assert_eq!(broken.base_path().unwrap(), Path::new("./test_resources/templates/template_a"));
+    assert!(engine.lookup_spec("good").is_some());
+    assert_eq!(engine.render_engine().loaded, loaded_before);
+
+    let specs = vec![
+        ("good".to_owned(), TemplateSpec::from_dir("./test_resources/batch_templates/good", settings).unwrap()),
+        ("broken".to_owned(), TemplateSpec::from_dir("./test_resources/report_templates/template_a", settings).unwrap()),
+    ];
+    let replaced = engine.insert_specs_atomic(specs).unwrap();
+    let replaced_ids = replaced.iter().map(|&(ref id, _)| &**id).collect::<Vec<_>>();
+    assert_eq!(replaced_ids, &["good", "broken"]);
+    assert!(!engine.render_engine().loaded.contains_key("./test_resources/templates/template_a/text/mail.txt"));
+    assert!(engine.render_engine().loaded.contains_key("./test_resources/batch_templates/good/text/mail.txt"));
+}
+
+#[test]
+fn report_lists_all_failing_template_dirs() {
+    let settings = &*DEFAULT_SETTINGS;
+    let report = TemplateSpec::from_dirs_with_report("./test_resources/report_templates", settings).unwrap();
+
+    let ids = report.specs.iter().map(|&(ref id, _)| &**id).collect::<Vec<_>>();
+    assert_eq!(ids, &["broken", "template_a"]);
+    assert_eq!(report.failures.len(), 1);
+    let failure = &report.failures[0];
+    assert_eq!(failure.dir, Path::new("./test_resources/report_templates/no_bodies"));
+    if let &CreatingSpecErrorVariant::NoSubTemplatesFound { .. } = failure.error.variant() {
+    } else {
+        panic!("unexpected error: {}", failure.error);
+    }
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    let report = engine.load_templates_with_report("./test_resources/report_templates", settings).unwrap();
+    assert!(report.has_failures());
+    assert_eq!(report.loaded, &["template_a"]);
+    assert_eq!(report.creation_failures.len(), 1);
+    assert_eq!(report.insertion_failures.len(), 1);
+    assert_eq!(report.insertion_failures[0].0, "broken");
+    assert!(engine.lookup_spec("template_a").is_some());
+
+    let text = report.to_string();
+    assert!(text.starts_with("loaded 1 spec(s), 2 failure(s)\n"));
+    assert!(text.contains("./test_resources/report_templates/no_bodies: "));
+    assert!(text.contains("broken: "));
+}
+
+#[test]
+fn validation_reports_dangling_cids_and_unused_embeddings() {
+    let settings = &*DEFAULT_SETTINGS;
+    let mut engine = MockEngine::default();
+    let report = validate_templates("./test_resources/validate_templates", settings, &mut engine).unwrap();
+
+    assert_eq!(report.checked_specs, 1);
+    assert!(report.creation_failures.is_empty());
+    let issues = report.issues.iter()
+        .map(|&(ref id, ref issue)| format!("{}: {}", id, issue))
+        .collect::<Vec<_>>();
+    assert_eq!(issues, &[
+        "cid_issues: template ./test_resources/validate_templates/cid_issues/html/mail.html \
+            refers to unknown embedding \"banner\"",
+        "cid_issues: embedding \"icon\" is not used by template \
+            ./test_resources/validate_templates/cid_issues/html/mail.html",
+        "cid_issues: shared embedding \"portfolio\" is not used by any template",
+    ]);
+    assert!(report.has_problems());
+}
+
+#[test]
+fn samples_are_picked_up_but_not_used_as_sub_templates() {
+    let settings = &*DEFAULT_SETTINGS;
+    let spec = TemplateSpec::from_dir("./test_resources/sample_templates/greeting", settings).unwrap();
+
+    assert_eq!(spec.sub_specs().len(), 1);
+    let samples = spec.samples().iter()
+        .map(|sample| (sample.name(), sample.path()))
+        .collect::<Vec<_>>();
+    assert_eq!(samples, &[
+        ("bob", Path::new("./test_resources/sample_templates/greeting/samples/bob.toml")),
+        ("liz", Path::new("./test_resources/sample_templates/greeting/samples/liz.json")),
+    ]);
+}
+
+#[test]
+fn check_samples_compares_with_and_blesses_expected_outputs() {
+    let settings = &*DEFAULT_SETTINGS;
+    let dir = copy_to_temp_dir(Path::new("./test_resources/sample_templates/greeting"), "samples");
+    let ctx = setup_context();
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("greeting".to_owned(), TemplateSpec::from_dir(&dir, settings).unwrap()).unwrap();
+
+    let report = check_samples(&engine, "greeting", &ctx, false).unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report.checks.len(), 2);
+    assert_eq!(report.checks[0].sample, "bob");
+    assert_eq!(report.checks[0].expected_file, dir.join("samples/bob.expected.txt"));
+    assert_eq!(report.checks[0].outcome, SampleOutcome::Missing { actual: "Hy {{data.name}}.\n".to_owned() });
+    assert_eq!(report.checks[1].outcome, SampleOutcome::Matched);
+
+    let report = check_samples(&engine, "greeting", &ctx, true).unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.checks[0].outcome, SampleOutcome::Blessed);
+    assert_eq!(fs::read_to_string(dir.join("samples/bob.expected.txt")).unwrap(), "Hy {{data.name}}.\n");
+
+    let report = check_samples(&engine, "greeting", &ctx, false).unwrap();
+    assert!(report.is_ok());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn localized_variants_are_loaded_with_locale_suffixed_ids() {
+    let settings = &*DEFAULT_SETTINGS;
+    let specs = TemplateSpec::from_dirs("./test_resources/localized_templates", settings).unwrap();
+
+    let ids = specs.iter().map(|&(ref id, _)| &**id).collect::<Vec<_>>();
+    assert_eq!(ids, &["farewell.en", "farewell.fr", "welcome", "welcome.de", "welcome.de-AT"]);
+
+    let welcome = &specs[2].1;
+    assert_eq!(welcome.sub_specs().len(), 1);
+    assert_eq!(welcome.sub_specs()[0].source().id(), "./test_resources/localized_templates/welcome/text/mail.txt");
+    let welcome_de = &specs[3].1;
+    assert_eq!(welcome_de.base_path().unwrap(), Path::new("./test_resources/localized_templates/welcome/de"));
+}
+
+#[test]
+fn localized_lookup_uses_fallback_chain() {
+    let settings = &*DEFAULT_SETTINGS;
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.load_templates("./test_resources/localized_templates", settings).unwrap();
+
+    let resolve = |engine: &RenderTemplateEngine<MockEngine>, id: &str, locale: &str| {
+        engine.resolve_localized_id(id, &Locale::parse(locale).unwrap())
+            .map(|(id, locale)| (id, locale.map(|locale| locale.to_string())))
+    };
+
+    assert_eq!(resolve(&engine, "welcome", "de-AT"), Some(("welcome.de-AT".to_owned(), Some("de-AT".to_owned()))));
+    assert_eq!(resolve(&engine, "welcome", "de-CH"), Some(("welcome.de".to_owned(), Some("de".to_owned()))));
+    assert_eq!(resolve(&engine, "welcome", "fr"), Some(("welcome".to_owned(), None)));
+    assert_eq!(resolve(&engine, "farewell", "de"), None);
+    assert_eq!(resolve(&engine, "unknown", "de"), None);
+
+    engine.set_fallback_locales(vec![ Locale::parse("en-US").unwrap() ]);
+    assert_eq!(resolve(&engine, "farewell", "de"), Some(("farewell.en".to_owned(), Some("en".to_owned()))));
+    assert_eq!(resolve(&engine, "farewell", "fr-CA"), Some(("farewell.fr".to_owned(), Some("fr".to_owned()))));
+
+    let ctx = setup_context();
+    let rendered = engine.use_localized_template("welcome", &Locale::parse("de-de").unwrap(), &(), &ctx).unwrap();
+    assert_eq!(rendered.content_language, Some(Locale::parse("de").unwrap()));
+    assert_eq!(rendered.headers, &[("Content-Language".to_owned(), "de".to_owned())]);
+    assert_eq!(rendered.subject.unwrap(), "Willkommen {{data.name}}!");
+    assert!(engine.use_localized_template("unknown", &Locale::parse("de").unwrap(), &(), &ctx).is_err());
+
+    // the variant has the `Content-Language` however it is rendered
+    let rendered = engine.render_mail("welcome.de-AT", &(), &ctx).unwrap();
+    assert_eq!(rendered.headers, &[("Content-Language".to_owned(), "de-AT".to_owned())]);
+    let rendered = engine.render_bodies("welcome.de-AT", &(), &ctx).unwrap();
+    assert_eq!(rendered.content_language, Some(Locale::parse("de-AT").unwrap()));
+    let rendered = engine.render_bodies("welcome", &(), &ctx).unwrap();
+    assert!(rendered.content_language.is_none());
+}
+
+#[test]
+fn subject_template_is_loaded_and_rendered_as_single_line() {
+    let settings = &*DEFAULT_SETTINGS;
+    let spec = TemplateSpec::from_dir("./test_resources/localized_templates/welcome", settings).unwrap();
+    assert!(spec.embeddings().is_empty());
+    let subject = spec.subject().unwrap();
+    assert_eq!(subject.source().id(), "./test_resources/localized_templates/welcome/subject.txt");
+    assert_eq!(subject.media_type().as_str_repr(), "text/plain; charset=utf-8");
+    assert_eq!(spec.render_templates().count(), 4);
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("welcome".to_owned(), spec).unwrap();
+    assert!(engine.render_engine().loaded.contains_key("./test_resources/localized_templates/welcome/subject.txt"));
+
+    let rendered = engine.render_mail("welcome", &(), &setup_context()).unwrap();
+    assert_eq!(rendered.subject.unwrap(), "Welcome {{data.name}}!");
+    assert!(rendered.content_language.is_none());
+    assert_eq!(rendered.headers.len(), 2);
+
+    engine.remove_spec("welcome");
+    assert!(engine.render_engine().loaded.is_empty());
+}
+
+#[test]
+fn header_templates_are_rendered_and_parsed() {
+    let settings = &*DEFAULT_SETTINGS;
+    let dir = "./test_resources/localized_templates/welcome";
+    let spec = TemplateSpec::from_dir(dir, settings).unwrap();
+    let names = spec.headers().iter().map(HeaderTemplate::name).collect::<Vec<_>>();
+    assert_eq!(names, &["List-Unsubscribe", "X-Campaign"]);
+    assert_eq!(spec.headers()[0].value().source().id(), format!("{}/headers.txt#0", dir));
+
+    let err = HeaderTemplate::from_lines("broken_headers", "X-Campaign: welcome\nnot a header\n").unwrap_err();
+    if let &CreatingSpecErrorVariant::InvalidHeaderLine { ref template, ref line } = err.variant() {
+        assert_eq!(template, "broken_headers");
+        assert_eq!(line, "not a header");
+    } else {
+        panic!("unexpected error: {}", err);
+    }
+
+    // the mock engine "renders" the source, like a engine would render data containing a line break
+    let mut broken = TemplateSpec::from_dir(dir, settings).unwrap();
+    let source = TemplateSource::Source {
+        id: "injecting_header".to_owned(),
+        content: "<x>\nBcc: victim@evil".to_owned()
+    };
+    let media_type = MediaType::parse("text/plain; charset=utf-8").unwrap();
+    let value = SubTemplateSpec::new_with_template_source(source, media_type, HashMap::new());
+    broken.set_headers(vec![ HeaderTemplate::new("List-Unsubscribe", value) ]);
+    broken.set_subject(None);
+    *broken.sub_specs_mut() = vec1![ SubTemplateSpec::new_with_template_source(
+        TemplateSource::Source { id: "broken_body".to_owned(), content: "Hy".to_owned() },
+        MediaType::parse("text/plain; charset=utf-8").unwrap(),
+        HashMap::new()
+    ) ];
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("welcome".to_owned(), spec).unwrap();
+    engine.insert_spec("broken".to_owned(), broken).unwrap();
+
+    let headers = engine.render_headers("welcome", &()).unwrap();
+    assert_eq!(headers, vec![
+        ("List-Unsubscribe".to_owned(), "<{{data.unsubscribe_url}}>".to_owned()),
+        ("X-Campaign".to_owned(), "welcome".to_owned())
+    ]);
+
+    match engine.render_mail("broken", &(), &setup_context()) {
+        Err(UseTemplateError::Processing(ProcessingError::InvalidHeader { ref template_id, ref line })) => {
+            assert_eq!(template_id, "broken");
+            assert_eq!(line, "List-Unsubscribe: <x>\nBcc: victim@evil");
+        },
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("rendering invalid headers did not fail")
+    }
+}
+
+#[test]
+fn text_body_can_be_generated_from_html() {
+    let dir = "./test_resources/html_only_templates/newsletter";
+    let spec = TemplateSpec::from_dir(dir, &*DEFAULT_SETTINGS).unwrap();
+    assert!(!spec.generates_text_from_html());
+
+    let mut settings = DEFAULT_SETTINGS.clone();
+    settings.set_generate_text_from_html(true);
+    let spec = TemplateSpec::from_dir(dir, &settings).unwrap();
+    assert!(spec.generates_text_from_html());
+    assert_eq!(spec.sub_specs().len(), 1);
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("newsletter".to_owned(), spec).unwrap();
+    let rendered = engine.render_bodies("newsletter", &(), &setup_context()).unwrap();
+
+    assert_eq!(rendered.bodies.len(), 2);
+    let text = &rendered.bodies[0];
+    assert_eq!(text.media_type.as_str_repr(), "text/plain; charset=utf-8");
+    assert_eq!(text.content, "News\r\n====\r\n\r\nRead all news[1].\r\n\r\n[1] https://example.com/news\r\n");
+    assert_eq!(rendered.bodies[1].media_type.as_str_repr(), "text/html; charset=utf-8");
+}
+
+#[test]
+fn css_is_inlined_into_html_bodies() {
+    let dir = "./test_resources/styled_templates/promo";
+    let spec = TemplateSpec::from_dir(dir, &*DEFAULT_SETTINGS).unwrap();
+    assert!(spec.embeddings().is_empty());
+    assert_eq!(spec.stylesheet(), Some("body { margin: 0 }\np { font-family: Arial; color: #333 }\n"));
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("promo".to_owned(), spec).unwrap();
+    let ctx = setup_context();
+
+    let rendered = engine.render_bodies("promo", &(), &ctx).unwrap();
+    assert!(rendered.bodies[0].content.contains("<style>"));
+
+    engine.set_inline_css(true);
+    let rendered = engine.render_bodies("promo", &(), &ctx).unwrap();
+    assert_eq!(rendered.bodies[0].content, concat!(
+        "<html><head><style>@media (max-width: 600px) { p { font-size: 18px } }</style></head>\r\n",
+        "<body style=\"margin: 0\"><p class=\"intro\" style=\"font-family: Arial; color: #333\">Spring sale</p>",
+        "<a class=\"cta\" href=\"https://example.com/sale\" style=\"color: white; background: #0a0; padding: 4px\">",
+        "Shop now</a></body></html>\r\n"
+    ));
+}
+
+#[test]
+fn post_processors_run_in_order_for_their_media_type() {
+    let dir = "./test_resources/html_only_templates/newsletter";
+    let mut settings = DEFAULT_SETTINGS.clone();
+    settings.set_generate_text_from_html(true);
+    let spec = TemplateSpec::from_dir(dir, &settings).unwrap();
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("newsletter".to_owned(), spec).unwrap();
+    engine.add_post_processor("text/html", |content: String, _: &PostProcessContext| {
+        Ok(content.replace("<h1>", "<h1 class=\"title\">"))
+    });
+    engine.add_post_processor("TEXT/HTML", |content: String, ctx: &PostProcessContext| {
+        Ok(format!("{}<img src=\"https://example.com/open/{}\">", content.trim_end(), ctx.template_id))
+    });
+    engine.add_post_processor("text/plain", |content: String, _: &PostProcessContext| {
+        Ok(content.to_uppercase())
+    });
+
+    let rendered = engine.render_bodies("newsletter", &(), &setup_context()).unwrap();
+    assert_eq!(rendered.bodies[0].content, "NEWS\r\n====\r\n\r\nREAD ALL NEWS[1].\r\n\r\n[1] HTTPS://EXAMPLE.COM/NEWS\r\n");
+    assert_eq!(rendered.bodies[1].content, concat!(
+        "<h1 class=\"title\">News</h1>\r\n",
+        "<p>Read <a href=\"https://example.com/news\">all news</a>.</p>",
+        "<img src=\"https://example.com/open/newsletter\">"
+    ));
+
+    assert_eq!(engine.remove_post_processors("text/plain"), 1);
+    engine.add_post_processor("text/html", |_: String, _: &PostProcessContext| {
+        Err(format_err!("minification failed"))
+    });
+    match engine.render_bodies("newsletter", &(), &setup_context()) {
+        Err(UseTemplateError::Processing(ProcessingError::PostProcessing { ref template_id, ref media_type, ref error })) => {
+            assert_eq!(template_id, "newsletter");
+            assert_eq!(media_type, "text/html");
+            assert_eq!(error.to_string(), "minification failed");
+        },
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("failing post-processor did not fail rendering")
+    }
+
+    // through `TemplateEngine` the error is converted into the error of the render engine
+    match TemplateEngine::use_template(&engine, "newsletter", &(), &setup_context()) {
+        Err(MockError::Processing(ProcessingError::PostProcessing { ref error, .. })) => {
+            assert_eq!(error.to_string(), "minification failed");
+        },
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("failing post-processor did not fail rendering")
+    }
+}
+
+#[test]
+fn links_of_html_and_text_bodies_can_be_rewritten() {
+    let mut settings = DEFAULT_SETTINGS.clone();
+    settings.set_generate_text_from_html(true);
+    let spec = TemplateSpec::from_dir("./test_resources/html_only_templates/newsletter", &settings).unwrap();
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("newsletter".to_owned(), spec).unwrap();
+    let mut rendered = engine.render_bodies("newsletter", &(), &setup_context()).unwrap();
+
+    let token = "r3c1p13nt";
+    let links = rendered.rewrite_links(|url| Some(format!("https://t.example.com/{}?to={}", token, url)));
+
+    assert_eq!(links, vec![ RewrittenLink {
+        original: "https://example.com/news".to_owned(),
+        rewritten: "https://t.example.com/r3c1p13nt?to=https://example.com/news".to_owned()
+    }]);
+    assert!(rendered.bodies[0].content.contains("[1] https://t.example.com/r3c1p13nt?to=https://example.com/news\r\n"));
+    assert!(rendered.bodies[1].content.contains("<a href=\"https://t.example.com/r3c1p13nt?to=https://example.com/news\">"));
+}
+
+#[test]
+fn line_length_policy_wraps_or_rejects_too_long_lines() {
+    let source = |id: &str, content: String, media_type: &str| SubTemplateSpec::new_with_template_source(
+        TemplateSource::Source { id: id.to_owned(), content },
+        MediaType::parse(media_type).unwrap(),
+        HashMap::new()
+    );
+    let compliant_line = format!("{} {}", "longer than the flowed width ".repeat(3), "w".repeat(100));
+    let long_text = format!("{}\r\n{}\r\n", "word ".repeat(250), compliant_line);
+    let long_html = format!("<p>{}</p>", "<b>bold</b>".repeat(100));
+    let spec = TemplateSpec::new_with_embeddings(vec1![
+        source("long_text", long_text.clone(), "text/plain; charset=utf-8"),
+        source("long_html", long_html.clone(), "text/html; charset=utf-8")
+    ], HashMap::new());
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("long".to_owned(), spec).unwrap();
+    let ctx = setup_context();
+
+    assert_eq!(engine.line_length_policy(), LineLengthPolicy::Ignore);
+    let rendered = engine.render_bodies("long", &(), &ctx).unwrap();
+    assert_eq!(rendered.bodies[0].content, long_text);
+
+    engine.set_line_length_policy(LineLengthPolicy::Error);
+    match engine.render_bodies("long", &(), &ctx) {
+        Err(UseTemplateError::Processing(ProcessingError::LineTooLong { ref template_id, ref media_type, line, length })) => {
+            assert_eq!(template_id, "long");
+            assert_eq!(media_type, "text/plain");
+            assert_eq!((line, length), (1, 1250));
+        },
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("too long lines were not rejected")
+    }
+
+    engine.set_line_length_policy(LineLengthPolicy::Wrap);
+    let rendered = engine.render_bodies("long", &(), &ctx).unwrap();
+    let text = &rendered.bodies[0];
+    assert_eq!(text.media_type.as_str_repr(), "text/plain; charset=utf-8; format=flowed");
+    let mut lines = text.content.split("\r\n").collect::<Vec<_>>();
+    assert_eq!(lines.pop(), Some(""));
+    assert_eq!(lines.pop(), Some(&*compliant_line));
+    assert!(lines.iter().all(|line| line.len() <= FLOWED_LINE_WIDTH));
+    assert_eq!(lines.concat(), "word ".repeat(250).trim_end());
+
+    let html = &rendered.bodies[1];
+    assert_eq!(html.media_type.as_str_repr(), "text/html; charset=utf-8");
+    assert!(html.content.split("\r\n").all(|line| line.len() <= MAX_LINE_LENGTH));
+    assert_eq!(html.content.replace("\r\n", ""), long_html);
+}
+
+#[test]
+fn text_bodies_can_be_rendered_as_format_flowed() {
+    let dir = "./test_resources/flowed_templates/notice";
+    let mut settings = DEFAULT_SETTINGS.clone();
+    settings.set_format_flowed(Some(30));
+    let spec = TemplateSpec::from_dir(dir, &settings).unwrap();
+
+    let text = &spec.sub_specs()[0];
+    assert_eq!(text.media_type().as_str_repr(), "text/plain; charset=utf-8; format=flowed");
+    assert_eq!(text.flowed_width(), Some(30));
+
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("notice".to_owned(), spec).unwrap();
+    let rendered = engine.render_bodies("notice", &(), &setup_context()).unwrap();
+    assert_eq!(rendered.bodies[0].content, concat!(
+        "Your order was shipped today \r\n",
+        "and will arrive within three \r\n",
+        "days.\r\n",
+        " > Tracking is available \r\n",
+        "online.\r\n",
+        " From your shop team\r\n"
+    ));
+}
+
+#[test]
+fn use_template_future_resolves_to_mail_parts() {
+    let spec = TemplateSpec::new(vec1![ SubTemplateSpec::new_with_template_source(
+        TemplateSource::Source { id: "async_text".to_owned(), content: "Hy".to_owned() },
+        MediaType::parse("text/plain; charset=utf-8").unwrap(),
+        HashMap::new()
+    ) ]);
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("async".to_owned(), spec).unwrap();
+    let ctx = setup_context();
+
+    let parts = engine.use_template_future("async", &(), &ctx).wait().unwrap();
+    assert_eq!(parts.alternative_bodies.len(), 1);
+    assert!(parts.attachments.is_empty());
+
+    match engine.use_template_future("missing", &(), &ctx).wait() {
+        Err(UseTemplateError::Render(_)) => {},
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("unknown template id was rendered")
+    }
+}
+
+#[test]
+fn shared_engine_publishes_new_versions_without_affecting_snapshots() {
+    let text_spec = |content: &str| TemplateSpec::new(vec1![ SubTemplateSpec::new_with_template_source(
+        TemplateSource::Source { id: "shared_text".to_owned(), content: content.to_owned() },
+        MediaType::parse("text/plain; charset=utf-8").unwrap(),
+        HashMap::new()
+    ) ]);
+    let mut engine = RenderTemplateEngine::new(MockEngine::default());
+    engine.insert_spec("shared".to_owned(), text_spec("v1")).unwrap();
+    let shared = Arc::new(SharedRenderTemplateEngine::new(engine));
+    let ctx = setup_context();
+
+    let old = shared.snapshot();
+    shared.update(|engine| engine.insert_spec("shared".to_owned(), text_spec("v2"))).unwrap();
+
+    let render = |engine: &RenderTemplateEngine<MockEngine>| {
+        engine.render_bodies("shared", &(), &ctx).unwrap().bodies[0].content.clone()
+    };
+    assert_eq!(render(&old), "v1");
+    assert_eq!(render(&shared.snapshot()), "v2");
+
+    let res = shared.update(|engine| {
+        engine.remove_spec("shared");
+        Err::<(), _>("aborted")
+    });
+    assert_eq!(res, Err("aborted"));
+    assert_eq!(render(&shared.snapshot()), "v2");
+
+    // a new render engine can be used instead of a clone, the specs are loaded into it
+    let before = shared.snapshot();
+    let replaced = shared.update_with(|_| MockEngine::default(), |engine| {
+        assert_eq!(render(engine), "v2");
+        engine.insert_spec("shared".to_owned(), text_spec("v3"))
+    }).unwrap();
+    assert_eq!(replaced.unwrap().sub_specs().len(), 1);
+    assert_eq!(render(&before), "v2");
+    assert_eq!(render(&shared.snapshot()), "v3");
+
+    // readers take a snapshot, then the new engine is published, then they use their snapshot
+    let taken = Arc::new(Barrier::new(5));
+    let published = Arc::new(Barrier::new(5));
+    let readers = (0..4).map(|_| {
+        let (shared, taken, published) = (shared.clone(), taken.clone(), published.clone());
+        thread::spawn(move || {
+            let snapshot = shared.snapshot();
+            taken.wait();
+            published.wait();
+            snapshot.lookup_spec("shared").is_some()
+        })
+    }).collect::<Vec<_>>();
+    taken.wait();
+    shared.publish(RenderTemplateEngine::new(MockEngine::default()));
+    published.wait();
+    for reader in readers {
+        assert!(reader.join().unwrap(), "the snapshot of a reader changed");
+    }
+    assert!(shared.snapshot().lookup_spec("shared").is_none());
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure::Fail;
+
+use mail_render_template_engine::{
+    RenderEngineBase, RenderEngine, AdditionalCIds,
+    TemplateSpec, SubTemplateSpec, TemplateSource
+};
+use mail_render_template_engine::error::ProcessingError;
+
+#[derive(Debug, Fail)]
+pub enum MockError {
+    #[fail(display = "template {} is malformed", id)]
+    Malformed { id: String },
+    #[fail(display = "template {} can not be read", id)]
+    Unreadable { id: String },
+    #[fail(display = "unknown template {}", id)]
+    UnknownTemplate { id: String },
+    #[fail(display = "{}", _0)]
+    Processing(ProcessingError)
+}
+
+impl From<ProcessingError> for MockError {
+    fn from(err: ProcessingError) -> Self {
+        MockError::Processing(err)
+    }
+}
+
+/// render engine which "renders" by returning the template source
+///
+/// Templates containing `{{ broken` fail to load.
+#[derive(Debug, Clone, Default)]
+pub struct MockEngine {
+    pub loaded: HashMap<String, String>
+}
+
+impl RenderEngineBase for MockEngine {
+    const PRODUCES_VALID_NEWLINES: bool = false;
+
+    type RenderError = MockError;
+    type LoadingError = MockError;
+
+    fn load_templates(&mut self, spec: &TemplateSpec) -> Result<(), Self::LoadingError> {
+        let mut new = Vec::new();
+        for sub_spec in spec.render_templates() {
+            let id = sub_spec.source().id().to_owned();
+            let content = match *sub_spec.source() {
+                TemplateSource::Path(ref path) => fs::read_to_string(path)
+                    .map_err(|_| MockError::Unreadable { id: id.clone() })?,
+                TemplateSource::Source { ref content, .. } => content.clone()
+            };
+            if content.contains("{{ broken") {
+                return Err(MockError::Malformed { id });
+            }
+            new.push((id, content));
+        }
+        self.loaded.extend(new);
+        Ok(())
+    }
+
+    fn unload_templates(&mut self, spec: &TemplateSpec) {
+        for sub_spec in spec.render_templates() {
+            self.loaded.remove(sub_spec.source().id());
+        }
+    }
+
+    fn unknown_template_id_error(id: &str) -> Self::RenderError {
+        MockError::UnknownTemplate { id: id.to_owned() }
+    }
+}
+
+impl<D> RenderEngine<D> for MockEngine {
+    fn render(
+        &self,
+        spec: &SubTemplateSpec,
+        _data: &D,
+        _cids: AdditionalCIds
+    ) -> Result<String, Self::RenderError> {
+        let id = spec.source().id();
+        self.loaded.get(id)
+            .cloned()
+            .ok_or_else(|| MockError::UnknownTemplate { id: id.to_owned() })
+    }
+}
+
+/// copies the given dir into a new dir in the systems temp dir
+pub fn copy_to_temp_dir(dir: &Path, name: &str) -> PathBuf {
+    let target = ::std::env::temp_dir()
+        .join(format!("mail-rte-test-{}-{}", name, ::std::process::id()));
+    if target.exists() {
+        fs::remove_dir_all(&target).unwrap();
+    }
+    copy_dir(dir, &target);
+    target
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in from.read_dir().unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
@@ -35,7 +35,8 @@ use template::{MailSendData, InspectEmbeddedResources, Embedded};
 
 use render_template_engine::{
     RenderTemplateEngine, DEFAULT_SETTINGS,
-    TemplateSpec
+    TemplateSpec, PreviewData, PreviewOptions,
+    render_preview
 };
 use render_template_engine::tera::TeraRenderEngine;
 
@@ -98,6 +99,21 @@ fn use_tera_template_a() {
     assert_mail_out_is_as_expected(out_string);
 }
 
+#[test]
+fn preview_tera_template_a() {
+    let context = setup_context();
+    let engine = setup_template_engine();
+    let data = PreviewData::from_json_str(r#"{"name": "Liz"}"#).unwrap();
+
+    let preview = render_preview(&engine, "template_a", data, &context, &PreviewOptions::default())
+        .unwrap();
+
+    assert_eq!(preview.bodies.len(), 2);
+    assert_eq!(preview.bodies[0].content, "Hy Liz.");
+    assert!(preview.bodies[1].content.contains("Hy Liz."));
+    assert!(preview.eml.contains("Subject: Preview of template_a"));
+}
+
 fn assert_mail_out_is_as_expected(mail_out: String) {
     let mut line_iter = mail_out.lines();
     let mut capture_map = HashMap::new();
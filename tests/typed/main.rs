@@ -0,0 +1,137 @@
+extern crate mail_types as mail;
+extern crate mail_headers as headers;
+#[macro_use]
+extern crate mail_render_template_engine as render_template_engine;
+extern crate vec1;
+extern crate soft_ascii_string;
+
+use std::collections::HashMap;
+
+use soft_ascii_string::SoftAsciiString;
+
+use mail::default_impl::simple_context;
+use headers::HeaderTryFrom;
+use headers::components::{Domain, MediaType};
+
+use render_template_engine::{
+    RenderTemplateEngine, TemplateSpec, SubTemplateSpec, TemplateSource,
+    AdditionalCIds, DEFAULT_SETTINGS
+};
+use render_template_engine::error::UseTemplateError;
+use render_template_engine::typed::TypedRenderEngine;
+use render_template_engine::typed::error::TypedError;
+
+
+struct UserData {
+    name: &'static str
+}
+
+struct AdminData;
+
+typed_templates! {
+    /// templates of the welcome mail
+    trait WelcomeTemplates for UserData {
+        /// the text body
+        "welcome.txt" => fn text_body;
+        /// the html body
+        "welcome.html" => fn html_body;
+    }
+}
+
+impl WelcomeTemplates for UserData {
+    fn text_body(&self, _cids: &AdditionalCIds) -> String {
+        format!("Hy {}.", self.name)
+    }
+
+    fn html_body(&self, _cids: &AdditionalCIds) -> String {
+        format!("<p>Hy {}.</p>", self.name)
+    }
+}
+
+typed_templates! {
+    trait AlertTemplates for AdminData {
+        "alert.txt" => fn text_body;
+    }
+}
+
+impl AlertTemplates for AdminData {
+    fn text_body(&self, _cids: &AdditionalCIds) -> String {
+        "Something happened.".to_owned()
+    }
+}
+
+fn setup_context() -> simple_context::Context {
+    let domain = Domain::try_from("typed.test").unwrap();
+    simple_context::new(domain, SoftAsciiString::from_string("7yp3d").unwrap()).unwrap()
+}
+
+fn source_spec(ids: &[(&str, &str)]) -> TemplateSpec {
+    let sub_specs = ids.iter().map(|&(id, media_type)| {
+        SubTemplateSpec::new_with_template_source(
+            TemplateSource::Source { id: id.to_owned(), content: String::new() },
+            MediaType::parse(media_type).unwrap(),
+            HashMap::new()
+        )
+    }).collect::<Vec<_>>();
+    TemplateSpec::new(vec1::Vec1::from_vec(sub_specs).unwrap())
+}
+
+fn setup_template_engine() -> RenderTemplateEngine<TypedRenderEngine> {
+    let mut typed = TypedRenderEngine::new();
+    typed.register_templates::<UserData>();
+    typed.register_templates::<AdminData>();
+    let mut rte = RenderTemplateEngine::new(typed);
+    rte.insert_spec("welcome".to_owned(), source_spec(&[
+        ("welcome.txt", "text/plain; charset=utf-8"),
+        ("welcome.html", "text/html; charset=utf-8")
+    ])).unwrap();
+    rte
+}
+
+#[test]
+fn render_typed_templates() {
+    let engine = setup_template_engine();
+    let data = UserData { name: "Liz" };
+
+    let rendered = engine.render_bodies("welcome", &data, &setup_context()).unwrap();
+
+    assert_eq!(rendered.bodies.len(), 2);
+    assert_eq!(rendered.bodies[0].content, "Hy Liz.");
+    assert_eq!(rendered.bodies[1].content, "<p>Hy Liz.</p>");
+}
+
+#[test]
+fn data_not_implementing_a_template_fails_rendering() {
+    let engine = setup_template_engine();
+
+    match engine.render_bodies("welcome", &AdminData, &setup_context()) {
+        Err(UseTemplateError::Render(TypedError::NotImplementedByData { id })) =>
+            assert_eq!(id, "welcome.txt"),
+        other => panic!("unexpected result: {:?}", other.map(|_| ()))
+    }
+}
+
+#[test]
+fn only_registered_source_templates_can_be_loaded() {
+    let mut engine = setup_template_engine();
+
+    let err = engine.insert_spec("unknown".to_owned(), source_spec(&[
+        ("unknown.txt", "text/plain; charset=utf-8")
+    ])).unwrap_err();
+    match err.error {
+        TypedError::NotCompiled { id } => assert_eq!(id, "unknown.txt"),
+        other => panic!("unexpected error: {:?}", other)
+    }
+
+    let spec = TemplateSpec::from_dir("./test_resources/templates/template_a", &*DEFAULT_SETTINGS)
+        .unwrap();
+    let err = engine.insert_spec("template_a".to_owned(), spec).unwrap_err();
+    match err.error {
+        TypedError::PathNotSupported { .. } => {},
+        other => panic!("unexpected error: {:?}", other)
+    }
+
+    engine.insert_spec("alert".to_owned(), source_spec(&[
+        ("alert.txt", "text/plain; charset=utf-8")
+    ])).unwrap();
+}